@@ -0,0 +1,34 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("http error: {0}")]
+    Http(#[from] hyper::Error),
+
+    #[error("error building request: {0}")]
+    Request(#[from] http::Error),
+
+    #[error("error encoding query: {0}")]
+    Query(#[from] serde_urlencoded::ser::Error),
+
+    #[error("error (des)serializing data: {0}")]
+    Serde(#[from] serde_json::Error),
+
+    /// The server replied with `error: true`. `code` is stable across versions, so it can be used
+    /// to decide what to do with the error.
+    #[error("sledge error ({status}): {cause}")]
+    Server { status: u16, code: Option<String>, cause: String },
+
+    #[error("no data found in the reply")]
+    MissingData,
+}
+
+impl Error {
+    /// The `code` sent by the server, if any.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Server { code, .. } => code.as_deref(),
+            _ => None,
+        }
+    }
+}
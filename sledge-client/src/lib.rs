@@ -0,0 +1,357 @@
+//! Async client for the sledge HTTP API.
+//!
+//! ```no_run
+//! use sledge_client::{Client, Query};
+//!
+//! # async fn run() -> Result<(), sledge_client::Error> {
+//! let client = Client::new("http://127.0.0.1:3000");
+//! client.create_db("my_db", None).await?;
+//! client.put("my_db", Some("1"), &serde_json::json!({"name": "mario"}), None).await?;
+//!
+//! let query = Query { limit: Some(10), ..Query::default() };
+//! let records = client.all("my_db", Some(&query)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod error;
+mod query;
+
+use http::Method;
+use hyper::{client::HttpConnector, Body, Request};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub use crate::{error::Error, query::Query};
+
+/// Characters encoded in the segments of the paths, so ids and names can have `/`, `?`, spaces...
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Body of every sledge response.
+#[derive(Deserialize, Debug)]
+pub struct Reply {
+    pub error: bool,
+    pub code:  Option<String>,
+    pub cause: Option<String>,
+    pub data:  Option<Value>,
+}
+
+/// A record returned by reads.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub id:  String,
+    pub val: Value,
+}
+
+/// A put or delete read from the changes of a db. `val` is `None` for deletes.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub seq: u64,
+    pub db:  String,
+    pub op:  String,
+    pub id:  String,
+    pub val: Option<Value>,
+}
+
+/// `last_seq` is the `since_seq` of the next read.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Changes {
+    pub changes:  Vec<Change>,
+    pub last_seq: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SnapshotLease {
+    pub id:         String,
+    pub expires_at: String,
+}
+
+#[derive(Deserialize)]
+struct TotalRecords {
+    total_records: usize,
+}
+
+#[derive(Clone)]
+pub struct Client {
+    inner: hyper::Client<HttpConnector>,
+    base:  String,
+}
+
+impl Client {
+    /// `base` is the address of the server, like `http://127.0.0.1:3000`.
+    pub fn new(base: &str) -> Self {
+        Client { inner: hyper::Client::new(), base: base.trim_end_matches('/').to_string() }
+    }
+
+    pub async fn get(&self, db: &str, id: &str, query: Option<&Query>) -> Result<Vec<Record>, Error> {
+        self.data(Method::GET, &format!("/_db/{}/{}", segment(db), segment(id)), query, Body::empty()).await
+    }
+
+    pub async fn all(&self, db: &str, query: Option<&Query>) -> Result<Vec<Record>, Error> {
+        self.data(Method::GET, &format!("/_db/{}/_all", segment(db)), query, Body::empty()).await
+    }
+
+    pub async fn all_reverse(&self, db: &str, query: Option<&Query>) -> Result<Vec<Record>, Error> {
+        self.data(Method::GET, &format!("/_db/{}/_all_reverse", segment(db)), query, Body::empty()).await
+    }
+
+    /// Records of `db` from `id` on.
+    pub async fn since(&self, db: &str, id: &str, query: Option<&Query>) -> Result<Vec<Record>, Error> {
+        self.data(Method::GET, &format!("/_db/{}/_since/{}", segment(db), segment(id)), query, Body::empty()).await
+    }
+
+    /// Records of `db` whose id starts with `prefix`.
+    pub async fn prefix(&self, db: &str, prefix: &str, query: Option<&Query>) -> Result<Vec<Record>, Error> {
+        self.data(Method::GET, &format!("/_db/{}/_since/{}*", segment(db), segment(prefix)), query, Body::empty()).await
+    }
+
+    /// Sends the records of `db` from `id` on to a Kafka `topic`. Returns the number of records sent.
+    pub async fn since_to_topic(&self, db: &str, id: &str, topic: &str, query: Option<&Query>) -> Result<usize, Error> {
+        let path = format!("/_db/{}/_since/{}/_topic/{}", segment(db), segment(id), segment(topic));
+        let total: TotalRecords = self.data(Method::GET, &path, query, Body::empty()).await?;
+        Ok(total.total_records)
+    }
+
+    pub async fn sql(&self, sql: &str, query: Option<&Query>) -> Result<Vec<Record>, Error> {
+        self.data(Method::POST, "/_sql", query, Body::from(sql.to_string())).await
+    }
+
+    /// Writes `value` with `id`, which can be `_auto` or `_auto_time` to let the server generate
+    /// it. `id` can be `None` if the `field_path` of the query is set.
+    pub async fn put<T: Serialize>(
+        &self, db: &str, id: Option<&str>, value: &T, query: Option<&Query>,
+    ) -> Result<(), Error> {
+        let path = format!("/_db/{}/{}", segment(db), segment(id.unwrap_or_default()));
+        let body = serde_json::to_vec(value)?;
+
+        self.request(Method::PUT, &path, query, Body::from(body)).await.map(|_| ())
+    }
+
+    pub async fn list_dbs(&self) -> Result<Vec<String>, Error> {
+        self.data(Method::GET, "/_db/_all", None, Body::empty()).await
+    }
+
+    /// Creates `db` with optional RocksDB options, like `{"compression":"lz4"}`.
+    pub async fn create_db(&self, db: &str, options: Option<&Value>) -> Result<(), Error> {
+        let body = match options {
+            Some(options) => Body::from(serde_json::to_vec(options)?),
+            None => Body::empty(),
+        };
+
+        self.request(Method::PUT, &format!("/_db/{}/_create_db", segment(db)), None, body).await.map(|_| ())
+    }
+
+    pub async fn drop_db(&self, db: &str) -> Result<(), Error> {
+        self.request(Method::DELETE, &format!("/_db/{}", segment(db)), None, Body::empty()).await.map(|_| ())
+    }
+
+    pub async fn truncate_db(&self, db: &str) -> Result<(), Error> {
+        self.request(Method::POST, &format!("/_db/{}/_truncate", segment(db)), None, Body::empty()).await.map(|_| ())
+    }
+
+    /// Returns the number of records moved.
+    pub async fn rename_db(&self, db: &str, to: &str) -> Result<usize, Error> {
+        let query = Query { to: Some(to.to_string()), ..Query::default() };
+        let total: TotalRecords =
+            self.data(Method::POST, &format!("/_db/{}/_rename", segment(db)), Some(&query), Body::empty()).await?;
+
+        Ok(total.total_records)
+    }
+
+    /// Copies `db` into `to`, applying `channel` to every record if set. Returns the number of
+    /// records copied.
+    pub async fn copy_db(&self, db: &str, to: &str, channel: Option<&str>) -> Result<usize, Error> {
+        let query = Query { to: Some(to.to_string()), channel: channel.map(String::from), ..Query::default() };
+        let total: TotalRecords =
+            self.data(Method::POST, &format!("/_db/{}/_copy", segment(db)), Some(&query), Body::empty()).await?;
+
+        Ok(total.total_records)
+    }
+
+    /// Stores a channel like `{"channel":[...]}` with `id`, so it can be used in the `channel`
+    /// option of the queries. The server rejects it with an `invalid_channel` error if any mutator
+    /// is wrong. Returns the version saved, which can be pinned with `channel={id}@{version}`.
+    pub async fn put_channel(&self, id: &str, channel: &Value) -> Result<u64, Error> {
+        let body = Body::from(serde_json::to_vec(channel)?);
+        let saved: Value = self.data(Method::PUT, &format!("/_channel/{}", segment(id)), None, body).await?;
+        saved["version"].as_u64().ok_or(Error::MissingData)
+    }
+
+    /// Reads the channel `id`, or its version `n` with `id@n`.
+    pub async fn get_channel(&self, id: &str) -> Result<Value, Error> {
+        self.data(Method::GET, &format!("/_channel/{}", segment(id)), None, Body::empty()).await
+    }
+
+    pub async fn delete_channel(&self, id: &str) -> Result<(), Error> {
+        self.request(Method::DELETE, &format!("/_channel/{}", segment(id)), None, Body::empty()).await.map(|_| ())
+    }
+
+    pub async fn list_channels(&self) -> Result<Vec<Value>, Error> {
+        self.data(Method::GET, "/_channel", None, Body::empty()).await
+    }
+
+    /// Every version of the channel `id`, oldest first, with the mutators added and removed by it.
+    pub async fn channel_history(&self, id: &str) -> Result<Vec<Value>, Error> {
+        self.data(Method::GET, &format!("/_channel/{}/_history", segment(id)), None, Body::empty()).await
+    }
+
+    /// Runs the mutators of `channel`, like `[{"type":"remove","field":"a"}]`, on `samples` without
+    /// storing anything. Returns the output and the value after every mutator for each sample.
+    pub async fn test_channel(&self, channel: &Value, samples: &[Value]) -> Result<Vec<Value>, Error> {
+        let body = Body::from(serde_json::to_vec(&json!({ "channel": channel, "samples": samples }))?);
+
+        self.data(Method::POST, "/_channel/_test", None, body).await
+    }
+
+    /// Writes of `db` after the `since_seq` of the query. With `follow` set the server waits up to
+    /// `wait_secs` for new writes if there are none.
+    pub async fn changes(&self, db: &str, query: Option<&Query>) -> Result<Changes, Error> {
+        self.data(Method::GET, &format!("/_db/{}/_changes", segment(db)), query, Body::empty()).await
+    }
+
+    pub async fn db_stats(&self, db: &str) -> Result<Value, Error> {
+        self.data(Method::GET, &format!("/_db/{}/_stats", segment(db)), None, Body::empty()).await
+    }
+
+    pub async fn instance_stats(&self) -> Result<Value, Error> {
+        self.data(Method::GET, "/_admin/_stats", None, Body::empty()).await
+    }
+
+    /// Compacts `db`, or every db if `None`, between the `start` and `end` of the query.
+    pub async fn compact(&self, db: Option<&str>, query: Option<&Query>) -> Result<Value, Error> {
+        self.data(Method::POST, &admin_path(db, "_compact"), query, Body::empty()).await
+    }
+
+    pub async fn flush(&self, db: Option<&str>) -> Result<Value, Error> {
+        self.data(Method::POST, &admin_path(db, "_flush"), None, Body::empty()).await
+    }
+
+    pub async fn verify(&self, db: Option<&str>) -> Result<Value, Error> {
+        self.data(Method::POST, &admin_path(db, "_verify"), None, Body::empty()).await
+    }
+
+    pub async fn backup(&self, path: Option<&str>) -> Result<Value, Error> {
+        let query = Query { path: path.map(String::from), ..Query::default() };
+        self.data(Method::POST, "/_admin/_backup", Some(&query), Body::empty()).await
+    }
+
+    pub async fn list_backups(&self) -> Result<Value, Error> {
+        self.data(Method::GET, "/_admin/_backups", None, Body::empty()).await
+    }
+
+    /// Takes a snapshot to use in the `snapshot` option of the reads.
+    pub async fn create_snapshot(&self, lease_secs: Option<u64>) -> Result<SnapshotLease, Error> {
+        let query = Query { lease_secs, ..Query::default() };
+        self.data(Method::POST, "/_admin/_snapshot", Some(&query), Body::empty()).await
+    }
+
+    pub async fn release_snapshot(&self, id: &str) -> Result<(), Error> {
+        let path = format!("/_admin/_snapshot/{}", segment(id));
+        self.request(Method::DELETE, &path, None, Body::empty()).await.map(|_| ())
+    }
+
+    /// Sends a request, returning the `data` of the reply or an error if the reply has `error` set.
+    pub async fn request(
+        &self, method: Method, path: &str, query: Option<&Query>, body: Body,
+    ) -> Result<Option<Value>, Error> {
+        let uri = match query {
+            Some(query) => format!("{}{}?{}", self.base, path, serde_urlencoded::to_string(query)?),
+            None => format!("{}{}", self.base, path),
+        };
+
+        let req = Request::builder().method(method).uri(uri).body(body)?;
+        let res = self.inner.request(req).await?;
+        let status = res.status().as_u16();
+        let bytes = hyper::body::to_bytes(res.into_body()).await?;
+
+        decode_reply(status, bytes.as_ref())
+    }
+
+    async fn data<T: DeserializeOwned>(
+        &self, method: Method, path: &str, query: Option<&Query>, body: Body,
+    ) -> Result<T, Error> {
+        decode_data(self.request(method, path, query, body).await?)
+    }
+}
+
+fn decode_reply(status: u16, body: &[u8]) -> Result<Option<Value>, Error> {
+    let reply: Reply = serde_json::from_slice(body)?;
+    if reply.error {
+        return Err(Error::Server { status, code: reply.code, cause: reply.cause.unwrap_or_default() })
+    }
+
+    Ok(reply.data)
+}
+
+fn decode_data<T: DeserializeOwned>(data: Option<Value>) -> Result<T, Error> {
+    Ok(serde_json::from_value(data.ok_or(Error::MissingData)?)?)
+}
+
+fn segment(s: &str) -> String { utf8_percent_encode(s, SEGMENT).to_string() }
+
+fn admin_path(db: Option<&str>, action: &str) -> String {
+    match db {
+        Some(db) => format!("/_db/{}/{}", segment(db), action),
+        None => format!("/_admin/{}", action),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_decode_reply() {
+        let error = br#"{"error":true,"code":"db_not_found","cause":"column family 'x' not found","data":null}"#;
+        match decode_reply(404, error) {
+            Err(err @ Error::Server { .. }) => {
+                assert_eq!(err.code(), Some("db_not_found"));
+                assert_eq!(err.to_string(), "sledge error (404): column family 'x' not found");
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+
+        let records = decode_reply(200, br#"{"error":false,"data":[{"id":"1","val":{"a":1}}]}"#).unwrap();
+        let records: Vec<Record> = decode_data(records).unwrap();
+        assert_eq!(records, vec![Record { id: "1".to_string(), val: json!({"a":1}) }]);
+
+        let empty = decode_reply(200, br#"{"error":false,"cause":null,"data":null}"#).unwrap();
+        assert!(matches!(decode_data::<Vec<Record>>(empty), Err(Error::MissingData)));
+        assert!(matches!(decode_reply(502, b"Bad Gateway"), Err(Error::Serde(_))));
+    }
+
+    #[test]
+    fn test_query() {
+        assert_eq!(serde_urlencoded::to_string(&Query::default()).unwrap(), "");
+
+        let query = Query {
+            limit: Some(10),
+            channel: Some("ch@2".to_string()),
+            field_equals: Some("name:mario rossi".to_string()),
+            follow: Some(true),
+            ..Query::default()
+        };
+        let expected = "limit=10&field_equals=name%3Amario+rossi&channel=ch%402&follow=true";
+        assert_eq!(serde_urlencoded::to_string(&query).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_segment() {
+        assert_eq!(segment("my_db"), "my_db");
+        assert_eq!(segment("a/b c?d#e%"), "a%2Fb%20c%3Fd%23e%25");
+        assert_eq!(admin_path(Some("a/b"), "_flush"), "/_db/a%2Fb/_flush");
+    }
+}
@@ -0,0 +1,51 @@
+use serde::Serialize;
+
+/// Query string options accepted by sledge. Unset fields are not sent.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Query {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_path:        Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end:               Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit:             Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until_id:          Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_equals:      Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip:              Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction_reverse: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel:           Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_ids:       Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub omit_errors:       Option<bool>,
+    /// Sink of the server used by `_topic` requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sink:              Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to:                Option<String>,
+    /// One of `none`, `wal`, `wal_sync` or `flush`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub durability:        Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path:              Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot:          Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lease_secs:        Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start:             Option<String>,
+    /// Id `_all` reads start from, taken as it is, unlike the ids of `_since`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from:              Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_seq:         Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follow:            Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait_secs:         Option<u64>,
+}
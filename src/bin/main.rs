@@ -2,18 +2,30 @@ extern crate hyper;
 extern crate tokio;
 
 use std::env;
-use std::sync::{Arc, RwLock};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
 use std::task::{Context, Poll};
 
 use futures_util::future;
 use hyper::service::Service;
 use hyper::Server;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
 
-use sledge::components::rocks;
+use sledge::components::backup;
+use sledge::components::config::{flag, Config};
+use sledge::components::storage::{self, Backend};
+use sledge::inputs;
+use sledge::replication;
 use sledge::server::service::Svc;
+use sledge::shell::{Shell, Step};
+use sledge::transfer::{self, Format, IdMode, Remote, Target};
+use sledge::Db;
 
 pub struct MakeSvc {
-    db: Arc<RwLock<rocksdb::DB>>,
+    svc: Svc,
 }
 
 impl<T> Service<T> for MakeSvc {
@@ -25,23 +37,224 @@ impl<T> Service<T> for MakeSvc {
         Ok(()).into()
     }
 
-    fn call(&mut self, _: T) -> Self::Future { future::ok(Svc::new(self.db.clone())) }
+    fn call(&mut self, _: T) -> Self::Future {
+        future::ok(self.svc.clone())
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    let config = Config::load(&args)?;
+
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&config.log.level)).init();
+
+    match args.get(1).map(String::as_str) {
+        None | Some("serve") => serve(config).await,
+        Some(flag) if flag.starts_with("--") => serve(config).await,
+        Some("restore") => restore(&args[2..], &config),
+        Some("export") => export(&args[2..], &config).await,
+        Some("import") => import(&args[2..], &config).await,
+        Some("shell") => shell(&args[2..], &config).await,
+        Some(cmd @ "compact") | Some(cmd @ "flush") | Some(cmd @ "verify") => maintenance(cmd, &args[2..], &config),
+        Some(cmd) => Err(format!(
+            "unknown command '{}'. Available commands are 'serve', 'restore', 'export', 'import', 'shell', 'compact', \
+             'flush' and 'verify'",
+            cmd
+        )
+        .into()),
+    }
+}
+
+/// `sledge [serve] [--config <file>] [--listen <addr>] [--path <data path>] [--storage <storage>]
+/// [--durability <durability>] [--default-limit <n>] [--kafka-brokers <brokers>] [--log-level
+/// <level>] [--leader <url>]` starts the server.
+///
+/// With `--leader` the server is a read-only follower: it bootstraps the data path from a backup
+/// of the leader if it's empty and then applies the changes of the leader.
+async fn serve(mut config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let addr: SocketAddr = config.listen.parse()?;
 
-    let addr = "127.0.0.1:3000".parse().unwrap();
+    let leader = match config.leader.clone() {
+        Some(leader) => leader,
+        None => {
+            let db = Db::from_config(&config)?;
+            inputs::start(&config, db.clone())?;
+            return listen(addr, db, &config, future::pending()).await
+        }
+    };
+
+    if config.storage != Backend::Rocksdb {
+        return Err("followers can only use the rocksdb storage".into())
+    }
+    config.rocksdb.change_log = true;
+
+    // a follower whose changes were trimmed from the leader's log is bootstrapped again
+    loop {
+        replication::bootstrap(&leader, &config.path).await?;
+        let db = Db::from_config(&config)?;
+
+        if replication::handshake(&db, &leader).await? {
+            let follower = tokio::spawn(replication::follow(db.clone(), leader.clone()));
+            let stopped = async {
+                match follower.await {
+                    Ok(err) => log::warn!("stopped following '{}': {}", leader, err),
+                    Err(err) => log::error!("error following '{}': {}", leader, err),
+                }
+            };
+            listen(addr, db, &config, stopped).await?;
+        } else {
+            log::warn!("'{}' no longer has the changes after the last one applied", leader);
+            drop(db);
+        }
+
+        replication::discard(&config.path)?;
+    }
+}
+
+/// Serves `db` until `shutdown` completes.
+async fn listen(
+    addr: SocketAddr,
+    db: Db,
+    config: &Config,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let svc = Svc::new(db, config)?;
+    log::info!("Listening on http://{} using {:?} storage in '{}'", addr, config.storage, config.path);
+
+    Server::bind(&addr).serve(MakeSvc { svc }).with_graceful_shutdown(shutdown).await?;
+
+    Ok(())
+}
+
+/// `sledge restore <backup> [<data path>]` restores a backup into a new data folder, the
+/// configured data path by default.
+fn restore(args: &[String], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let from = args.get(0).ok_or("usage: sledge restore <backup> [<data path>]")?;
+    let to = args.get(1).unwrap_or(&config.path);
+
+    backup::restore(Path::new(&config.backup_path), from, to)?;
+
+    Ok(())
+}
 
-    let maybe_path = env::var("FEEDB_PATH").unwrap_or_else(|_| "/tmp/storage".to_string());
-    let db = Arc::new(RwLock::new(rocks::new_storage(maybe_path)));
+/// `sledge export --db <db> [--format ndjson|csv|json] [--since <id>] [--channel <channel>] [--file <file>]
+/// [--server <url>]` writes the records of a db into a file, stdout by default.
+///
+/// Without `--server` the configured data folder is opened, so it must not be in use by a running
+/// server.
+async fn export(args: &[String], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let db = flag(args, "--db").ok_or("usage: sledge export --db <db> [--format ndjson|csv|json] [--since <id>]")?;
+    let format: Format = flag(args, "--format").unwrap_or("ndjson").parse()?;
+    let mut out: Box<dyn Write> = match flag(args, "--file") {
+        Some(file) => Box::new(BufWriter::new(File::create(file)?)),
+        None => Box::new(io::stdout()),
+    };
+
+    let target = target(args, config)?;
+    let n = transfer::export(&target, db, format, flag(args, "--since"), flag(args, "--channel"), &mut *out).await?;
+    log::info!("exported {} records of '{}'", n, db);
+
+    Ok(())
+}
+
+/// `sledge import --db <db> --file <file> [--id-path <path> | --auto-id] [--format ndjson|json]
+/// [--server <url>]` writes the records of a file, or of stdin with `--file -`, into a db. The
+/// records are the ones written by `export` unless `--id-path` or `--auto-id` are set, and then
+/// they are the values to write. The format is taken from the extension if not set.
+///
+/// Without `--server` the configured data folder is opened, so it must not be in use by a running
+/// server.
+async fn import(args: &[String], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: sledge import --db <db> --file <file> [--id-path <path> | --auto-id] [--format ndjson|json]";
+    let (db, file) = (flag(args, "--db").ok_or(usage)?, flag(args, "--file").ok_or(usage)?);
+    let format = match flag(args, "--format") {
+        Some(format) => format.parse()?,
+        None if file.ends_with(".json") => Format::Json,
+        None => Format::Ndjson,
+    };
+    let ids = match (flag(args, "--id-path"), args.iter().any(|a| a == "--auto-id")) {
+        (Some(_), true) => return Err(usage.into()),
+        (Some(path), false) => IdMode::Path(path.to_string()),
+        (None, true) => IdMode::Auto,
+        (None, false) => IdMode::Record,
+    };
+    let mut input: Box<dyn BufRead> = match file {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        file => Box::new(BufReader::new(File::open(file)?)),
+    };
+
+    let target = target(args, config)?;
+    let n = transfer::import(&target, db, format, &ids, &mut *input).await?;
+    log::info!("imported {} records into '{}'", n, db);
+
+    Ok(())
+}
+
+/// `sledge shell [--server <url>]` starts an interactive SQL session. Statements end with `;` and
+/// lines starting with `\` are commands, `\?` lists them. The history is kept in
+/// `~/.sledge_history`.
+///
+/// Without `--server` the configured data folder is opened, so it must not be in use by a running
+/// server.
+async fn shell(args: &[String], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut shell = Shell::new(target(args, config)?);
+    let mut editor = Editor::<()>::new();
+    let history = env::var("HOME").map(|home| format!("{}/.sledge_history", home)).ok();
+    if let Some(history) = history.as_ref() {
+        editor.load_history(history).ok();
+    }
+
+    loop {
+        let line = match editor.readline(shell.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                shell.cancel();
+                continue
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        editor.add_history_entry(line.as_str());
+
+        match shell.line(&line).await {
+            Ok(Step::Print(out)) => println!("{}", out),
+            Ok(Step::Continue) => {}
+            Ok(Step::Quit) => break,
+            Err(err) => eprintln!("ERROR: {}", err),
+        }
+    }
+
+    if let Some(history) = history.as_ref() {
+        editor.save_history(history)?;
+    }
+
+    Ok(())
+}
+
+/// The server of `--server` or the configured data folder.
+fn target(args: &[String], config: &Config) -> Result<Target, Box<dyn std::error::Error>> {
+    match flag(args, "--server") {
+        Some(server) => Ok(Target::Remote(Remote::new(server))),
+        None => Ok(Target::Local(Db::from_config(config)?)),
+    }
+}
 
-    let server = Server::bind(&addr).serve(MakeSvc { db });
+/// `sledge compact|flush|verify [--db <db>] [--start <key>] [--end <key>]` runs a maintenance
+/// operation over the configured RocksDB data folder, which must not be in use by a running
+/// server.
+/// Every db is used if `--db` is not set. `--start` and `--end` only apply to `compact`.
+fn maintenance(cmd: &str, args: &[String], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let db = storage::new_storage(Backend::Rocksdb, config.path.clone(), &config.rocksdb)?;
+    let cf = flag(args, "--db");
 
-    log::info!("Listening on http://{}", addr);
+    let report = match cmd {
+        "compact" => serde_json::to_string_pretty(&db.compact(cf, flag(args, "--start"), flag(args, "--end"))?),
+        "flush" => serde_json::to_string_pretty(&db.flush(cf)?),
+        _ => serde_json::to_string_pretty(&db.verify(cf)?),
+    }?;
 
-    server.await?;
+    println!("{}", report);
 
     Ok(())
 }
@@ -1,13 +1,19 @@
-use std::{borrow::BorrowMut, ops::Deref};
+use std::{fmt, ops::Deref};
 
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
 use crate::{
-    channels::mutators::{factory, Mutator, MutatorType},
+    channels::{
+        error::Error as MutatorsError,
+        mutators::{factory, Mutator},
+    },
     components::errors::Error,
 };
 
+/// Field of a first grok mutator that parses the input as a plain string instead of JSON.
+const PLAIN_INPUT: &str = "_plain_input";
+
 pub struct Channel {
     pub name:        String,
     pub channel:     Vec<Box<dyn Mutator>>,
@@ -16,8 +22,151 @@ pub struct Channel {
 
 #[derive(Serialize, Deserialize)]
 pub struct ChannelToParseJSON {
-    name:    String,
-    channel: Vec<Value>,
+    #[serde(default)]
+    pub name:     String,
+    /// Set by the server on every save, starting at 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version:  Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saved_at: Option<String>,
+    pub channel:  Vec<Value>,
+}
+
+/// A saved definition of a channel with the changes to its mutators since the previous version.
+#[derive(Serialize, Debug)]
+pub struct ChannelVersion {
+    pub version:  u64,
+    pub saved_at: Option<String>,
+    pub channel:  Vec<Value>,
+    pub changes:  Vec<MutatorChange>,
+}
+
+/// A mutator added to or removed from a channel, by its position in the new or the old version. A
+/// changed mutator is removed and added.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MutatorChange {
+    Added { index: usize, mutator: Value },
+    Removed { index: usize, mutator: Value },
+}
+
+/// Versions of a channel, oldest first, with their changes. The first version adds all its
+/// mutators.
+pub fn history(versions: Vec<ChannelToParseJSON>) -> Vec<ChannelVersion> {
+    let mut previous: Vec<Value> = Vec::new();
+    let mut history = Vec::new();
+
+    for definition in versions {
+        history.push(ChannelVersion {
+            version:  definition.version.unwrap_or_default(),
+            saved_at: definition.saved_at,
+            changes:  diff(&previous, &definition.channel),
+            channel:  definition.channel.clone(),
+        });
+        previous = definition.channel;
+    }
+
+    history
+}
+
+/// Changes between two lists of mutators, keeping the longest common subsequence in place.
+fn diff(old: &[Value], new: &[Value]) -> Vec<MutatorChange> {
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] =
+                if old[i] == new[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+
+    let (mut i, mut j, mut changes) = (0, 0, Vec::new());
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(MutatorChange::Removed { index: i, mutator: old[i].clone() });
+            i += 1;
+        } else {
+            changes.push(MutatorChange::Added { index: j, mutator: new[j].clone() });
+            j += 1;
+        }
+    }
+
+    changes
+}
+
+/// A mutator of a channel definition that can't be created, by its position in the definition.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MutatorError {
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+    pub error: String,
+}
+
+/// A mutator applied to a sample in a dry run, with the value after it or the reason it failed.
+#[derive(Serialize, Debug)]
+pub struct MutatorStep {
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Dry run of a channel on a sample. `output` is what `parse_and_modify` returns and `error` why
+/// the sample couldn't be parsed.
+#[derive(Serialize, Debug)]
+pub struct Trace {
+    pub output: Option<Value>,
+    pub steps:  Vec<MutatorStep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error:  Option<String>,
+}
+
+/// A channel definition and the samples to run it on without storing anything. Strings are passed
+/// as plain input, like the lines of the socket and file inputs, and other values as their JSON.
+#[derive(Deserialize)]
+pub struct ChannelTest {
+    #[serde(flatten)]
+    pub definition:  ChannelToParseJSON,
+    pub samples:     Vec<Value>,
+    #[serde(default)]
+    pub omit_errors: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SampleTrace {
+    pub input: Value,
+    #[serde(flatten)]
+    pub trace: Trace,
+}
+
+impl ChannelTest {
+    pub fn run(self) -> Result<Vec<SampleTrace>, Error> {
+        let channel = Channel::new_strict(self.definition, self.omit_errors)?;
+
+        let mut traces = Vec::new();
+        for input in self.samples {
+            let trace = match &input {
+                Value::String(s) => channel.trace(s.as_bytes()),
+                value => channel.trace(&serde_json::to_vec(value)?),
+            };
+            traces.push(SampleTrace { input, trace });
+        }
+
+        Ok(traces)
+    }
+}
+
+impl fmt::Display for MutatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mutator {} ({}): {}", self.index, self.type_.as_deref().unwrap_or("no type"), self.error)
+    }
 }
 
 impl Channel {
@@ -28,61 +177,120 @@ impl Channel {
         Channel::new(ms, omit_errors)
     }
 
-    fn new(ms: ChannelToParseJSON, omit_errors: bool) -> Result<Self, Error> {
-        let mutators = ms
-            .channel
-            .into_iter()
-            .filter_map(|x| {
-                match factory(x.clone()) {
-                    Err(e) => {
-                        log::error!("channel parsing error {}. Error: {}", x, e.to_string());
-                        None
-                    }
-                    Ok(v) => Some(v),
-                }
-            })
-            .collect::<Vec<Box<dyn Mutator>>>();
+    /// Creates the mutators of the definition. The ones that can't be created are logged and
+    /// dropped, so channels stored before they were validated still load.
+    pub fn new(ms: ChannelToParseJSON, omit_errors: bool) -> Result<Self, Error> {
+        let (channel, errors) = Channel::build(ms, omit_errors);
+        for err in errors {
+            log::error!("channel '{}' parsing error: {}", channel.name, err);
+        }
+
+        Ok(channel)
+    }
+
+    /// Creates every mutator of the definition. The channel is rejected with the errors of all the
+    /// mutators that can't be created, or if it has none.
+    pub fn new_strict(ms: ChannelToParseJSON, omit_errors: bool) -> Result<Self, Error> {
+        let (channel, errors) = Channel::build(ms, omit_errors);
+        if !errors.is_empty() || channel.channel.is_empty() {
+            return Err(Error::InvalidChannel(errors))
+        }
 
-        Ok(Channel { name: "".to_string(), channel: mutators, omit_errors })
+        Ok(channel)
+    }
+
+    fn build(ms: ChannelToParseJSON, omit_errors: bool) -> (Self, Vec<MutatorError>) {
+        let (mut mutators, mut errors) = (Vec::new(), Vec::new());
+        for (index, m) in ms.channel.into_iter().enumerate() {
+            let type_ = m["type"].as_str().map(String::from);
+            match factory(m) {
+                Ok(mutator) => mutators.push(mutator),
+                Err(err) => errors.push(MutatorError { index, type_, error: err.to_string() }),
+            }
+        }
+
+        (Channel { name: ms.name, channel: mutators, omit_errors }, errors)
     }
 
     pub fn parse_and_modify(&self, input_data: &[u8]) -> Option<Vec<u8>> {
-        if self.channel.is_empty() {
-            log::warn!("mutator list in channel is empty");
-            return None
+        let res = self.run(input_data, |_, _, res| {
+            if let Err(err) = res {
+                log::warn!("error trying to modify json '{}'", err);
+            }
+        });
+
+        match res {
+            Ok(value) => {
+                serde_json::to_vec(&value?)
+                    .map_err(|err| log::warn!("error trying to create mutable reference to json: {}", err.to_string()))
+                    .ok()
+            }
+            Err(err) => {
+                log::warn!("error trying to mutate value: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Runs the channel like `parse_and_modify`, keeping the value after every mutator or the
+    /// reason it failed.
+    pub fn trace(&self, input_data: &[u8]) -> Trace {
+        let mut steps = Vec::new();
+        let res = self.run(input_data, |index, mutator, res| {
+            steps.push(MutatorStep {
+                index,
+                type_: mutator.mutator_type().to_string(),
+                value: res.ok().map(|v| Value::Object(v.clone())),
+                error: res.err().map(|err| err.to_string()),
+            })
+        });
+
+        match res {
+            Ok(output) => Trace { output: output.map(Value::Object), steps, error: None },
+            Err(err) => Trace { output: None, steps, error: Some(err) },
         }
+    }
 
-        let first_mod = self.channel.first().unwrap();
-        let maybe_value = match first_mod.mutator_type() {
-            MutatorType::Grok => {
-                let g = first_mod.as_grok().unwrap();
-                if g.modifier.field == "_plain_input" {
-                    g.mutate_plain_string(input_data)
-                } else {
-                    None
+    /// Parses the input, with a first grok mutator on `_plain_input` if there's one, and applies
+    /// the mutators calling `step` after each of them. Returns `None` if a mutator fails with
+    /// `omit_errors`, and an error if the input can't be parsed.
+    fn run(
+        &self, input_data: &[u8],
+        mut step: impl FnMut(usize, &dyn Mutator, Result<&Map<String, Value>, &MutatorsError>),
+    ) -> Result<Option<Map<String, Value>>, String> {
+        let first = self.channel.first().ok_or_else(|| "mutator list in channel is empty".to_string())?;
+        let plain = first.as_grok().filter(|g| g.modifier.field == PLAIN_INPUT);
+
+        let (mut value, skip) = match plain.and_then(|g| g.mutate_plain_string(input_data)) {
+            Some(Value::Object(value)) => {
+                step(0, first.as_ref(), Ok(&value));
+                (value, 1)
+            }
+            _ => {
+                match serde_json::from_slice(input_data) {
+                    Ok(Value::Object(value)) => (value, 0),
+                    Ok(_) => return Err("the input is not a JSON object".to_string()),
+                    Err(err) if plain.is_some() => {
+                        return Err(format!("the input doesn't match the grok pattern and isn't JSON: {}", err))
+                    }
+                    Err(err) => return Err(err.to_string()),
                 }
             }
-            _ => None,
         };
 
-        let mut x = maybe_value.or_else(|| {
-            serde_json::from_slice(input_data).map_err(|err| log::warn!("error trying to mutate value: {}", err)).ok()
-        })?;
-
-        let mutp = x.as_object_mut()?;
-
-        for modifier in self.channel.iter() {
-            if let Err(err) = modifier.mutate(mutp.borrow_mut()) {
-                log::warn!("error trying to modify json '{}'", err);
-                if self.omit_errors {
-                    return None
+        for (index, modifier) in self.channel.iter().enumerate().skip(skip) {
+            match modifier.mutate(&mut value) {
+                Ok(()) => step(index, modifier.as_ref(), Ok(&value)),
+                Err(err) => {
+                    step(index, modifier.as_ref(), Err(&err));
+                    if self.omit_errors {
+                        return Ok(None)
+                    }
                 }
             }
         }
 
-        serde_json::to_vec(&mutp)
-            .map_err(|err| log::warn!("error trying to create mutable reference to json: {}", err.to_string()))
-            .ok()
+        Ok(Some(value))
     }
 }
 
@@ -91,3 +299,35 @@ impl Deref for Channel {
 
     fn deref(&self) -> &Self::Target { &self.channel }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::channels::channel::*;
+
+    #[test]
+    fn test_trace() {
+        let test: ChannelTest = serde_json::from_value(json!({
+            "channel": [
+                {"type": "grok", "field": "_plain_input", "pattern": "%{WORD:user} %{WORD:action}"},
+                {"type": "uppercase", "field": "action"},
+                {"type": "remove", "field": "missing"}
+            ],
+            "samples": ["mario login", {"user": "ula"}, 3]
+        }))
+        .unwrap();
+        let traces = test.run().unwrap();
+
+        let login = json!({"user": "mario", "action": "LOGIN"});
+        assert_eq!(traces[0].trace.output, Some(login.clone()));
+        assert_eq!(traces[0].trace.steps.len(), 3);
+        assert_eq!(traces[0].trace.steps[1].value, Some(login));
+        assert!(traces[0].trace.steps[2].error.is_some());
+
+        assert_eq!(traces[1].trace.steps[0].index, 0);
+        assert!(traces[1].trace.steps[0].error.is_some());
+        assert!(traces[2].trace.error.is_some());
+        assert!(traces[2].trace.steps.is_empty());
+    }
+}
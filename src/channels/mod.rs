@@ -1,5 +1,5 @@
 mod append;
-pub(crate) mod channel;
+pub mod channel;
 mod error;
 mod grok;
 mod join;
@@ -0,0 +1,219 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use rocksdb::{checkpoint::Checkpoint, DB};
+use serde::{Deserialize, Serialize};
+
+use crate::components::errors::Error;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupInfo {
+    pub name:       String,
+    pub path:       String,
+    pub created:    Option<String>,
+    pub size_bytes: u64,
+}
+
+/// A file of a backup, which followers download to bootstrap from it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupFile {
+    pub name:       String,
+    pub size_bytes: u64,
+}
+
+/// Creates a consistent copy of the whole db using a RocksDB checkpoint, so the server doesn't need
+/// to be stopped. The backup is created inside the backups folder `root` with the name in `path`,
+/// or the current time if none, so it can be listed and restored. The name must not exist.
+pub fn create(root: &Path, db: Arc<RwLock<DB>>, path: Option<&str>) -> Result<BackupInfo, Error> {
+    let target = match path {
+        Some(p) => root.join(safe_name(p)?),
+        None => root.join(Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string()),
+    };
+
+    if target.exists() {
+        return Err(Error::Backup(format!("path '{}' already exists", target.display())))
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|err| Error::Backup(err.to_string()))?;
+    }
+
+    // Logged writes hold the read lock from reserving their sequence numbers until they are written,
+    // so with the write lock the change log in the checkpoint has no gaps and ends at its last change.
+    // Followers bootstrapped from it take that as the last change applied.
+    let db = db.write().unwrap();
+    let checkpoint = Checkpoint::new(&db).map_err(Error::RocksDB)?;
+    checkpoint.create_checkpoint(&target).map_err(Error::RocksDB)?;
+    log::info!("backup created in '{}'", target.display());
+
+    backup_info(&target)
+}
+
+pub fn list(root: &Path) -> Result<Vec<BackupInfo>, Error> {
+    if !root.exists() {
+        return Ok(Vec::new())
+    }
+
+    let mut backups = fs::read_dir(root)
+        .map_err(|err| Error::Backup(err.to_string()))?
+        .filter_map(|entry| {
+            entry.map_err(|err| log::warn!("error reading backups folder: {}", err)).ok().map(|e| e.path())
+        })
+        .filter(|p| p.is_dir())
+        .filter_map(|p| backup_info(&p).map_err(|err| log::warn!("error reading backup: {}", err)).ok())
+        .collect::<Vec<BackupInfo>>();
+
+    backups.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(backups)
+}
+
+/// Copies the backup in `from`, absolute or inside `root`, into `to` so it can be used as a new data
+/// folder. `to` must not exist.
+pub fn restore(root: &Path, from: &str, to: &str) -> Result<(), Error> {
+    let from = if Path::new(from).is_absolute() { PathBuf::from(from) } else { root.join(from) };
+    let to = Path::new(to);
+
+    if !from.is_dir() {
+        return Err(Error::Backup(format!("backup '{}' not found", from.display())))
+    }
+
+    if to.exists() {
+        return Err(Error::Backup(format!("target path '{}' already exists", to.display())))
+    }
+
+    copy_dir(&from, to).map_err(|err| Error::Backup(err.to_string()))?;
+    log::info!("backup '{}' restored into '{}'", from.display(), to.display());
+
+    Ok(())
+}
+
+/// Files of the backup `name` in the backups folder. Checkpoints have no subfolders.
+pub fn files(root: &Path, name: &str) -> Result<Vec<BackupFile>, Error> {
+    let path = backup_path(root, name)?;
+
+    let mut files = fs::read_dir(path)
+        .map_err(|err| Error::Backup(err.to_string()))?
+        .map(|entry| {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            Ok(BackupFile { name, size_bytes: entry.metadata()?.len() })
+        })
+        .collect::<std::io::Result<Vec<BackupFile>>>()
+        .map_err(|err| Error::Backup(err.to_string()))?;
+
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(files)
+}
+
+pub fn file_path(root: &Path, name: &str, file: &str) -> Result<PathBuf, Error> {
+    let path = backup_path(root, name)?.join(safe_name(file)?);
+    if !path.is_file() {
+        return Err(Error::Backup(format!("file '{}' not found in backup '{}'", file, name)))
+    }
+
+    Ok(path)
+}
+
+pub fn delete(root: &Path, name: &str) -> Result<(), Error> {
+    fs::remove_dir_all(backup_path(root, name)?).map_err(|err| Error::Backup(err.to_string()))?;
+    log::info!("backup '{}' deleted", name);
+
+    Ok(())
+}
+
+fn backup_path(root: &Path, name: &str) -> Result<PathBuf, Error> {
+    let path = root.join(safe_name(name)?);
+    if !path.is_dir() {
+        return Err(Error::Backup(format!("backup '{}' not found", name)))
+    }
+
+    Ok(path)
+}
+
+/// Names coming from requests can't leave the backups folder.
+fn safe_name(name: &str) -> Result<&str, Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(|c| c == '/' || c == '\\') {
+        return Err(Error::Backup(format!("wrong name '{}'", name)))
+    }
+
+    Ok(name)
+}
+
+fn backup_info(path: &Path) -> Result<BackupInfo, Error> {
+    let metadata = fs::metadata(path).map_err(|err| Error::Backup(err.to_string()))?;
+
+    Ok(BackupInfo {
+        name:       path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        path:       path.to_string_lossy().to_string(),
+        created:    metadata.modified().ok().map(|t| DateTime::<Utc>::from(t).to_rfc3339()),
+        size_bytes: dir_size(path).map_err(|err| Error::Backup(err.to_string()))?,
+    })
+}
+
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    fs::read_dir(path)?.try_fold(0, |acc, entry| {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let size = if metadata.is_dir() { dir_size(&entry.path())? } else { metadata.len() };
+
+        Ok(acc + size)
+    })
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::components::backup::*;
+
+    #[test]
+    fn test_backups() {
+        let dir = env::temp_dir().join(format!("sledge_backups_{}", uuid::Uuid::new_v4()));
+        let root = dir.join("backups");
+
+        let db = DB::open_default(dir.join("db")).unwrap();
+        db.put(b"k", b"v").unwrap();
+        let db = Arc::new(RwLock::new(db));
+
+        for path in &["../x", "/tmp/x", "a/../../x", ".", ""] {
+            assert!(matches!(create(&root, db.clone(), Some(path)), Err(Error::Backup(_))), "{}", path);
+        }
+        assert!(!dir.join("x").exists());
+
+        let info = create(&root, db.clone(), Some("b1")).unwrap();
+        assert_eq!(info.name, "b1");
+        assert!(create(&root, db.clone(), Some("b1")).is_err());
+        assert_eq!(list(&root).unwrap().into_iter().map(|b| b.name).collect::<Vec<_>>(), vec!["b1"]);
+
+        let restored = dir.join("restored");
+        restore(&root, "b1", &restored.to_string_lossy()).unwrap();
+        let db = DB::open_default(&restored).unwrap();
+        assert_eq!(db.get(b"k").unwrap().unwrap().to_vec(), b"v".to_vec());
+
+        drop(db);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::BTreeSet,
+    convert::TryInto,
+    ops::Range,
+    sync::{Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::components::errors::Error;
+
+/// Column family with a record of every write, keyed by its big endian sequence number.
+///
+/// The WAL could be read back with `get_updates_since`, but the `WriteBatchIterator` of the rocksdb
+/// bindings doesn't report the column family id of each operation, so the db of a change would be
+/// lost. Writes are logged here in the same batch instead. Sequence numbers are a counter of
+/// sledge, not the ones of RocksDB.
+pub const CHANGES_CF: &str = "_changes";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeOp {
+    Put,
+    Delete,
+    /// The value holds the `DbOptions` of the db as JSON.
+    CreateDb,
+    DropDb,
+    TruncateDb,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub seq:   u64,
+    pub db:    String,
+    pub op:    ChangeOp,
+    pub key:   Vec<u8>,
+    /// `None` for deletes, dropped and truncated dbs.
+    pub value: Option<Vec<u8>>,
+}
+
+/// Changes returned by a read of the log. `last_seq` is the last sequence number read, which can
+/// be newer than the last change returned if the read was filtered by db, and is the `since_seq`
+/// of the next read.
+#[derive(Serialize)]
+pub struct ChangesPage {
+    pub changes:  Vec<Change>,
+    pub last_seq: u64,
+}
+
+impl Change {
+    pub(crate) fn encode(&self) -> Vec<u8> { encode(&self.db, self.op, &self.key, self.value.as_deref()) }
+
+    pub(crate) fn decode(seq: u64, data: &[u8]) -> Result<Self, Error> {
+        let corrupted = || Error::Db(format!("corrupted change with sequence number {}", seq));
+
+        let (op, rest) = data.split_first().ok_or_else(corrupted)?;
+        let op = match op {
+            0 => ChangeOp::Put,
+            1 => ChangeOp::Delete,
+            2 => ChangeOp::CreateDb,
+            3 => ChangeOp::DropDb,
+            4 => ChangeOp::TruncateDb,
+            _ => return Err(corrupted()),
+        };
+        let (db, rest) = split_sized(rest).ok_or_else(corrupted)?;
+        let (key, value) = split_sized(rest).ok_or_else(corrupted)?;
+
+        Ok(Change {
+            seq,
+            db: String::from_utf8(db.to_vec())?,
+            op,
+            key: key.to_vec(),
+            value: match op {
+                ChangeOp::Put | ChangeOp::CreateDb => Some(value.to_vec()),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Sent as `{"seq":1,"db":"my_db","op":"put","id":"1","val":{...}}`. Values that aren't JSON are
+/// sent as strings.
+impl Serialize for Change {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let val = self.value.as_ref().map(|v| {
+            serde_json::from_slice(v).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(v).to_string()))
+        });
+
+        let mut s = serializer.serialize_struct("Change", 5)?;
+        s.serialize_field("seq", &self.seq)?;
+        s.serialize_field("db", &self.db)?;
+        s.serialize_field("op", &self.op)?;
+        s.serialize_field("id", &String::from_utf8_lossy(&self.key))?;
+        s.serialize_field("val", &val)?;
+        s.end()
+    }
+}
+
+/// `op`, then the length and bytes of the db and of the key and then the value.
+pub(crate) fn encode(db: &str, op: ChangeOp, key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+    let value = value.unwrap_or_default();
+    let mut res = Vec::with_capacity(9 + db.len() + key.len() + value.len());
+
+    res.push(match op {
+        ChangeOp::Put => 0,
+        ChangeOp::Delete => 1,
+        ChangeOp::CreateDb => 2,
+        ChangeOp::DropDb => 3,
+        ChangeOp::TruncateDb => 4,
+    });
+    res.extend_from_slice(&(db.len() as u32).to_be_bytes());
+    res.extend_from_slice(db.as_bytes());
+    res.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    res.extend_from_slice(key);
+    res.extend_from_slice(value);
+
+    res
+}
+
+/// Changes as sent to followers: the sequence number, the length of the encoded change and the
+/// encoded change, one after the other.
+pub fn encode_frames(changes: &[Change]) -> Vec<u8> {
+    let mut res = Vec::new();
+    for change in changes {
+        let encoded = change.encode();
+        res.extend_from_slice(&seq_key(change.seq));
+        res.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        res.extend_from_slice(&encoded);
+    }
+
+    res
+}
+
+pub fn decode_frames(mut data: &[u8]) -> Result<Vec<Change>, Error> {
+    let mut res = Vec::new();
+    while !data.is_empty() {
+        let corrupted = || Error::Db("corrupted replication frame".to_string());
+        if data.len() < 8 {
+            return Err(corrupted())
+        }
+
+        let (seq, rest) = data.split_at(8);
+        let seq = seq_from_key(seq).ok_or_else(corrupted)?;
+        let (encoded, rest) = split_sized(rest).ok_or_else(corrupted)?;
+
+        res.push(Change::decode(seq, encoded)?);
+        data = rest;
+    }
+
+    Ok(res)
+}
+
+pub(crate) fn seq_key(seq: u64) -> [u8; 8] { seq.to_be_bytes() }
+
+pub(crate) fn seq_from_key(key: &[u8]) -> Option<u64> { Some(u64::from_be_bytes(key.try_into().ok()?)) }
+
+fn split_sized(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if data.len() < 4 {
+        return None
+    }
+
+    let (len, rest) = data.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().ok()?) as usize;
+    if rest.len() < len {
+        return None
+    }
+
+    Some(rest.split_at(len))
+}
+
+/// Sequence numbers of the log. Writers reserve the numbers of their changes and write them
+/// concurrently. A change is only visible once every change before it has been written, so
+/// readers following the log never skip one, and they wait on the condvar for new ones.
+///
+/// With `retention` set only that number of changes is kept.
+pub struct ChangeLog {
+    state:     Mutex<LogState>,
+    written:   Condvar,
+    retention: u64,
+}
+
+struct LogState {
+    /// Last sequence number handed out.
+    reserved: u64,
+    /// First sequence number of the reservations not released yet.
+    pending:  BTreeSet<u64>,
+    /// Every change up to this one has been written, or failed.
+    last:     u64,
+    /// Oldest change kept, older ones were trimmed.
+    first:    u64,
+}
+
+/// Sequence numbers reserved for a write, released when dropped whether the write succeeded or
+/// not. The numbers of a failed write are never used. Empty reservations hold no number.
+pub struct Reservation<'a> {
+    log:   &'a ChangeLog,
+    start: u64,
+    next:  u64,
+    end:   u64,
+}
+
+impl Reservation<'a> {
+    pub fn next(&mut self) -> u64 {
+        assert!(self.next <= self.end, "more changes than sequence numbers reserved");
+        self.next += 1;
+        self.next - 1
+    }
+}
+
+impl Drop for Reservation<'a> {
+    fn drop(&mut self) {
+        if self.start <= self.end {
+            self.log.release(self.start)
+        }
+    }
+}
+
+impl ChangeLog {
+    /// `first` and `last` are the oldest and newest changes stored. `retention` is the number of
+    /// changes kept, or 0 to keep all of them.
+    pub fn new(first: u64, last: u64, retention: u64) -> Self {
+        let state = LogState { reserved: last, pending: BTreeSet::new(), last, first };
+        ChangeLog { state: Mutex::new(state), written: Condvar::new(), retention }
+    }
+
+    fn lock(&self) -> MutexGuard<LogState> { self.state.lock().unwrap() }
+
+    /// Last change visible to readers.
+    pub fn last(&self) -> u64 { self.lock().last }
+
+    pub fn first(&self) -> u64 { self.lock().first }
+
+    /// Reserves `n` consecutive sequence numbers. The lock is only held to take them. Reserving none
+    /// leaves no gap in the log and isn't tracked as pending, as its start is the next reservation's.
+    pub fn reserve(&self, n: usize) -> Reservation {
+        let mut state = self.lock();
+        let start = state.reserved + 1;
+        state.reserved += n as u64;
+        if n > 0 {
+            state.pending.insert(start);
+        }
+
+        Reservation { log: self, start, next: start, end: state.reserved }
+    }
+
+    fn release(&self, start: u64) {
+        let mut state = self.lock();
+        state.pending.remove(&start);
+        state.last = match state.pending.iter().next() {
+            Some(pending) => pending - 1,
+            None => state.reserved,
+        };
+        drop(state);
+
+        self.written.notify_all()
+    }
+
+    /// Sets the last change applied by a follower, whose sequence numbers come from the leader.
+    pub fn applied(&self, seq: u64) {
+        let mut state = self.lock();
+        state.reserved = state.reserved.max(seq);
+        if state.pending.is_empty() {
+            state.last = state.reserved;
+        }
+        drop(state);
+
+        self.written.notify_all()
+    }
+
+    /// Sequence numbers over the retention, at most `max` of them. They are no longer readable
+    /// once returned, so they must be deleted.
+    pub fn trim(&self, max: u64) -> Option<Range<u64>> {
+        let mut state = self.lock();
+        if self.retention == 0 || state.last < state.first + self.retention {
+            return None
+        }
+
+        let trimmed = state.first..(state.last + 1 - self.retention).min(state.first + max);
+        state.first = trimmed.end;
+
+        Some(trimmed)
+    }
+
+    /// Waits up to `timeout` for a change newer than `seq`. Returns whether there is one.
+    pub fn wait_after(&self, seq: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+
+        while state.last <= seq {
+            let now = Instant::now();
+            if now >= deadline {
+                return false
+            }
+            state = self.written.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::components::changes::*;
+
+    #[test]
+    fn test_encode_change() {
+        let put =
+            Change { seq: 3, db: "people".to_string(), op: ChangeOp::Put, key: b"1".to_vec(), value: Some(vec![]) };
+        assert_eq!(Change::decode(3, &put.encode()).unwrap(), put);
+
+        let delete = Change { seq: 4, db: "people".to_string(), op: ChangeOp::Delete, key: b"1".to_vec(), value: None };
+        assert_eq!(Change::decode(4, &delete.encode()).unwrap(), delete);
+
+        assert!(Change::decode(5, &[0, 0, 0, 0, 9]).is_err());
+
+        let opts = Some(b"{}".to_vec());
+        let create = Change { seq: 5, db: "people".to_string(), op: ChangeOp::CreateDb, key: vec![], value: opts };
+        let frames = encode_frames(&[put.clone(), delete.clone(), create.clone()]);
+        assert_eq!(decode_frames(&frames).unwrap(), vec![put, delete, create]);
+        assert!(decode_frames(&frames[..frames.len() - 1]).is_err());
+        assert_eq!(seq_from_key(&seq_key(300)), Some(300));
+    }
+
+    #[test]
+    fn test_change_log() {
+        let log = ChangeLog::new(1, 2, 3);
+
+        let mut first = log.reserve(2);
+        let empty = log.reserve(0);
+        let mut second = log.reserve(1);
+        assert_eq!((first.next(), first.next(), second.next()), (3, 4, 5));
+
+        // empty reservations take no number and don't hold back the others
+        drop(empty);
+        assert_eq!(log.last(), 2);
+
+        // changes are visible in order
+        drop(second);
+        assert_eq!(log.last(), 2);
+        assert!(!log.wait_after(2, Duration::from_millis(1)));
+        drop(first);
+        assert_eq!(log.last(), 5);
+        assert!(log.wait_after(4, Duration::from_millis(1)));
+
+        assert_eq!(log.trim(1), Some(1..2));
+        assert_eq!(log.trim(10), Some(2..3));
+        assert_eq!(log.trim(10), None);
+        assert_eq!(log.first(), 3);
+
+        log.applied(9);
+        assert_eq!((log.last(), log.reserve(1).next()), (9, 10));
+    }
+}
@@ -0,0 +1,562 @@
+use std::{collections::HashMap, env, fs, path::Path};
+
+use rocksdb::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::components::{durability::Durability, errors::Error, storage::Backend};
+
+/// Records returned by a read when the query doesn't set a `limit`.
+pub const DEFAULT_LIMIT: usize = 1000;
+
+pub const DEFAULT_BACKUP_PATH: &str = "/tmp/storage_backups";
+
+/// Settings of the server. Every value can be set in a TOML file, which is overridden by the
+/// `FEEDB_*` environment variables and then by the command line flags.
+///
+/// ```toml
+/// listen = "0.0.0.0:3000"
+/// path = "/var/lib/sledge"
+/// backup_path = "/var/backups/sledge"
+/// storage = "rocksdb"
+/// durability = "wal_sync"
+/// default_limit = 100
+///
+/// [rocksdb]
+/// max_open_files = 512
+/// change_log = true
+/// change_log_retention = 100000
+///
+/// [kafka]
+/// brokers = "kafka-1:9092,kafka-2:9092"
+///
+/// [sinks.events]
+/// type = "kafka"
+/// brokers = "kafka-events:9093"
+/// acks = "all"
+/// compression = "lz4"
+/// key = "field"
+/// key_field = "user.id"
+/// security_protocol = "sasl_ssl"
+/// sasl_mechanism = "PLAIN"
+/// sasl_username = "sledge"
+/// sasl_password = "secret"
+///
+/// [sinks.alerts]
+/// type = "webhook"
+/// url = "http://alerting:8080/events"
+///
+/// [sinks.archive]
+/// type = "file"
+/// dir = "/var/log/sledge"
+///
+/// [inputs.clicks]
+/// type = "kafka"
+/// brokers = "kafka-1:9092"
+/// group_id = "sledge"
+/// topics = ["clicks"]
+/// db = "clicks"
+/// channel = "clicks_channel"
+/// field_path = "click.id"
+///
+/// [log]
+/// level = "debug"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen:        String,
+    pub path:          String,
+    /// Folder where backups are created, listed and restored from.
+    pub backup_path:   String,
+    /// Address of the server to replicate, like `http://10.0.0.1:3000`. The server is then a
+    /// read-only follower.
+    pub leader:        Option<String>,
+    pub storage:       Backend,
+    pub durability:    Durability,
+    pub default_limit: usize,
+    pub rocksdb:       RocksConfig,
+    /// Sink used by `_topic` requests without a `sink` parameter.
+    pub kafka:         KafkaConfig,
+    /// Named sinks selected with the `sink` parameter of `_topic` requests.
+    pub sinks:         HashMap<String, SinkConfig>,
+    /// Pipelines started with the server that write what they receive into a db.
+    pub inputs:        HashMap<String, InputConfig>,
+    pub log:           LogConfig,
+}
+
+/// Options of the RocksDB instance. Options of each db are set when creating it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RocksConfig {
+    pub max_open_files:       Option<i32>,
+    pub max_background_jobs:  Option<i32>,
+    pub parallelism:          Option<i32>,
+    /// Logs every write so it can be read from `_changes`.
+    pub change_log:           bool,
+    /// Number of changes kept in the log, 0 to keep all of them.
+    pub change_log_retention: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    Kafka(KafkaConfig),
+    Webhook(WebhookConfig),
+    File(FileSinkConfig),
+}
+
+/// POSTs the records to `url` as NDJSON in batches of `batch_size`. Failed batches are retried
+/// `max_retries` times, doubling `backoff_ms` every time.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url:         String,
+    pub batch_size:  usize,
+    pub max_retries: u32,
+    pub backoff_ms:  u64,
+    pub headers:     HashMap<String, String>,
+}
+
+/// Appends the records as NDJSON to `{dir}/{topic}.ndjson`, which is rotated once it reaches
+/// `max_bytes`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileSinkConfig {
+    pub dir:       String,
+    pub max_bytes: u64,
+}
+
+/// Kafka producer of a sink. `properties` are passed to librdkafka as they are, for settings
+/// without a field of their own.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    pub brokers:            String,
+    pub message_timeout_ms: u64,
+    /// `0`, `1` or `all`.
+    pub acks:               Option<String>,
+    /// `none`, `gzip`, `snappy`, `lz4` or `zstd`.
+    pub compression:        Option<String>,
+    pub key:                KafkaKey,
+    /// Path of the value used as key with `key = "field"`, like `user.id`.
+    pub key_field:          Option<String>,
+    /// librdkafka partitioner of keyed messages, like `murmur2_random`.
+    pub partitioner:        Option<String>,
+    /// Sends every message to this partition, ignoring the key.
+    pub partition:          Option<i32>,
+    /// `plaintext`, `ssl`, `sasl_plaintext` or `sasl_ssl`.
+    pub security_protocol:  Option<String>,
+    pub sasl_mechanism:     Option<String>,
+    pub sasl_username:      Option<String>,
+    pub sasl_password:      Option<String>,
+    pub ssl_ca_location:    Option<String>,
+    pub properties:         HashMap<String, String>,
+}
+
+/// Key of the messages sent to Kafka.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KafkaKey {
+    /// The id of the record.
+    Id,
+    /// A field of the value, set in `key_field`.
+    Field,
+    /// No key, so messages are spread over the partitions.
+    None,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InputConfig {
+    Kafka(KafkaInputConfig),
+    Socket(SocketInputConfig),
+    File(FileInputConfig),
+}
+
+/// Consumes `topics` with a consumer group, running `channel` on every message before writing it
+/// into `db`. Offsets are committed once a batch is written.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaInputConfig {
+    pub brokers:           String,
+    pub group_id:          String,
+    pub topics:            Vec<String>,
+    pub db:                String,
+    /// Stored channel applied to every message.
+    pub channel:           Option<String>,
+    /// Drops the messages that fail any mutator of the channel.
+    pub omit_errors:       bool,
+    /// Path of the id in the message, like the `field_path` parameter of the writes.
+    pub field_path:        Option<String>,
+    /// Id used if `field_path` is not set, `_auto` or `_auto_time`.
+    pub id:                String,
+    pub batch_size:        usize,
+    pub batch_timeout_ms:  u64,
+    /// `earliest` or `latest`, for groups without committed offsets.
+    pub auto_offset_reset: Option<String>,
+    /// Passed to librdkafka as they are, like the security settings.
+    pub properties:        HashMap<String, String>,
+}
+
+/// Listens on `listen` for newline delimited lines, or syslog messages with `format = "syslog"`,
+/// running `channel` on every one of them before writing it into `db`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketInputConfig {
+    pub listen:           String,
+    pub protocol:         Protocol,
+    pub format:           LineFormat,
+    pub db:               String,
+    /// Stored channel applied to every line. Raw lines reach it as they are, so a first grok
+    /// mutator on `_plain_input` can parse them, and syslog messages as their parsed fields.
+    pub channel:          Option<String>,
+    /// Drops the lines that fail any mutator of the channel.
+    pub omit_errors:      bool,
+    /// Id of the records, `_auto_time` or `_auto`.
+    pub id:               String,
+    pub batch_size:       usize,
+    pub batch_timeout_ms: u64,
+}
+
+/// Follows the files matching the `paths` glob patterns, running `channel` on every line before
+/// writing it into `db`. Read offsets are kept in the `_inputs` db so a restart resumes where the
+/// input stopped, and rotated or truncated files are read again from their start.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileInputConfig {
+    pub paths:       Vec<String>,
+    pub format:      LineFormat,
+    pub db:          String,
+    /// Stored channel applied to every line, like in the socket input.
+    pub channel:     Option<String>,
+    /// Drops the lines that fail any mutator of the channel.
+    pub omit_errors: bool,
+    /// Id of the records, `_auto_time` or `_auto`.
+    pub id:          String,
+    /// Where files without a stored offset are read from.
+    pub start_at:    StartAt,
+    pub batch_size:  usize,
+    /// Time between looks for new lines and files once every file is read.
+    pub poll_ms:     u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartAt {
+    Beginning,
+    End,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineFormat {
+    /// Lines are stored as they are if they are JSON and as `{"message":"..."}` otherwise.
+    Raw,
+    /// RFC 3164 and RFC 5424 messages, stored as their fields.
+    Syslog,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `env_logger` filter like `info` or `sledge=debug`. `RUST_LOG` takes precedence.
+    pub level: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen:        "127.0.0.1:3000".to_string(),
+            path:          "/tmp/storage".to_string(),
+            backup_path:   DEFAULT_BACKUP_PATH.to_string(),
+            leader:        None,
+            storage:       Backend::default(),
+            durability:    Durability::default(),
+            default_limit: DEFAULT_LIMIT,
+            rocksdb:       RocksConfig::default(),
+            kafka:         KafkaConfig::default(),
+            sinks:         HashMap::new(),
+            inputs:        HashMap::new(),
+            log:           LogConfig::default(),
+        }
+    }
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        KafkaConfig {
+            brokers:            "localhost:9092".to_string(),
+            message_timeout_ms: 5000,
+            acks:               None,
+            compression:        None,
+            key:                KafkaKey::Id,
+            key_field:          None,
+            partitioner:        None,
+            partition:          None,
+            security_protocol:  None,
+            sasl_mechanism:     None,
+            sasl_username:      None,
+            sasl_password:      None,
+            ssl_ca_location:    None,
+            properties:         HashMap::new(),
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            url:         String::new(),
+            batch_size:  500,
+            max_retries: 3,
+            backoff_ms:  500,
+            headers:     HashMap::new(),
+        }
+    }
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self { FileSinkConfig { dir: "/tmp/sledge_sinks".to_string(), max_bytes: 100 * 1024 * 1024 } }
+}
+
+impl Default for KafkaInputConfig {
+    fn default() -> Self {
+        KafkaInputConfig {
+            brokers:           "localhost:9092".to_string(),
+            group_id:          "sledge".to_string(),
+            topics:            Vec::new(),
+            db:                String::new(),
+            channel:           None,
+            omit_errors:       false,
+            field_path:        None,
+            id:                "_auto".to_string(),
+            batch_size:        100,
+            batch_timeout_ms:  1000,
+            auto_offset_reset: None,
+            properties:        HashMap::new(),
+        }
+    }
+}
+
+impl Default for SocketInputConfig {
+    fn default() -> Self {
+        SocketInputConfig {
+            listen:           "0.0.0.0:5514".to_string(),
+            protocol:         Protocol::Udp,
+            format:           LineFormat::Raw,
+            db:               String::new(),
+            channel:          None,
+            omit_errors:      false,
+            id:               "_auto_time".to_string(),
+            batch_size:       100,
+            batch_timeout_ms: 1000,
+        }
+    }
+}
+
+impl Default for FileInputConfig {
+    fn default() -> Self {
+        FileInputConfig {
+            paths:       Vec::new(),
+            format:      LineFormat::Raw,
+            db:          String::new(),
+            channel:     None,
+            omit_errors: false,
+            id:          "_auto_time".to_string(),
+            start_at:    StartAt::Beginning,
+            batch_size:  100,
+            poll_ms:     1000,
+        }
+    }
+}
+
+impl Default for KafkaKey {
+    fn default() -> Self { KafkaKey::Id }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self { LogConfig { level: "info".to_string() } }
+}
+
+impl Default for RocksConfig {
+    fn default() -> Self {
+        RocksConfig {
+            max_open_files:       None,
+            max_background_jobs:  None,
+            parallelism:          None,
+            change_log:           false,
+            change_log_retention: 1_000_000,
+        }
+    }
+}
+
+impl RocksConfig {
+    pub fn apply(&self, opts: &mut Options) {
+        if let Some(n) = self.max_open_files {
+            opts.set_max_open_files(n);
+        }
+        if let Some(n) = self.max_background_jobs {
+            opts.set_max_background_jobs(n);
+        }
+        if let Some(n) = self.parallelism {
+            opts.increase_parallelism(n);
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content =
+            fs::read_to_string(path).map_err(|err| Error::Config(format!("reading '{}': {}", path.display(), err)))?;
+
+        toml::from_str(&content).map_err(|err| Error::Config(format!("parsing '{}': {}", path.display(), err)))
+    }
+
+    /// Builds the config from the defaults, the file in `--config` or `FEEDB_CONFIG`, the
+    /// environment and the flags in `args`, in that order.
+    pub fn load(args: &[String]) -> Result<Self, Error> {
+        let file = flag(args, "--config").map(String::from).or_else(|| env::var("FEEDB_CONFIG").ok());
+
+        let mut config = match file {
+            Some(file) => Config::from_file(file)?,
+            None => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.apply_args(args)?;
+
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), Error> {
+        if let Ok(path) = env::var("FEEDB_PATH") {
+            self.path = path;
+        }
+        if let Ok(path) = env::var("FEEDB_BACKUP_PATH") {
+            self.backup_path = path;
+        }
+        if let Ok(storage) = env::var("FEEDB_STORAGE") {
+            self.storage = storage.parse().map_err(Error::Config)?;
+        }
+        if let Ok(durability) = env::var("FEEDB_DURABILITY") {
+            self.durability = durability.parse().map_err(Error::Config)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: &[String]) -> Result<(), Error> {
+        if let Some(listen) = flag(args, "--listen") {
+            self.listen = listen.to_string();
+        }
+        if let Some(path) = flag(args, "--path") {
+            self.path = path.to_string();
+        }
+        if let Some(leader) = flag(args, "--leader") {
+            self.leader = Some(leader.to_string());
+        }
+        if let Some(storage) = flag(args, "--storage") {
+            self.storage = storage.parse().map_err(Error::Config)?;
+        }
+        if let Some(durability) = flag(args, "--durability") {
+            self.durability = durability.parse().map_err(Error::Config)?;
+        }
+        if let Some(limit) = flag(args, "--default-limit") {
+            self.default_limit =
+                limit.parse().map_err(|err| Error::Config(format!("wrong --default-limit '{}': {}", limit, err)))?;
+        }
+        if let Some(brokers) = flag(args, "--kafka-brokers") {
+            self.kafka.brokers = brokers.to_string();
+        }
+        if let Some(level) = flag(args, "--log-level") {
+            self.log.level = level.to_string();
+        }
+
+        Ok(())
+    }
+}
+
+/// Value of the flag `name` in `args`, like `--path /tmp/storage`.
+pub fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::components::config::*;
+
+    #[test]
+    fn test_config_precedence() {
+        let mut config: Config = toml::from_str(
+            r#"
+            listen = "0.0.0.0:4000"
+            default_limit = 10
+
+            [kafka]
+            brokers = "kafka:9092"
+
+            [sinks.events]
+            type = "kafka"
+            brokers = "events:9092"
+            acks = "all"
+            key = "none"
+
+            [sinks.alerts]
+            type = "webhook"
+            url = "http://alerting/events"
+
+            [inputs.clicks]
+            type = "kafka"
+            topics = ["clicks"]
+            db = "clicks"
+
+            [inputs.syslog]
+            type = "socket"
+            protocol = "tcp"
+            format = "syslog"
+            db = "logs"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen, "0.0.0.0:4000");
+        assert_eq!(config.kafka.brokers, "kafka:9092");
+        assert_eq!(config.kafka.message_timeout_ms, 5000);
+        match (&config.sinks["events"], &config.sinks["alerts"]) {
+            (SinkConfig::Kafka(events), SinkConfig::Webhook(alerts)) => {
+                assert_eq!(events.acks.as_deref(), Some("all"));
+                assert_eq!(events.key, KafkaKey::None);
+                assert_eq!(alerts.max_retries, 3);
+            }
+            _ => panic!("wrong sink types"),
+        }
+        match &config.inputs["clicks"] {
+            InputConfig::Kafka(input) => assert_eq!((input.db.as_str(), input.batch_size), ("clicks", 100)),
+            _ => panic!("wrong input type"),
+        }
+        match &config.inputs["syslog"] {
+            InputConfig::Socket(input) => {
+                assert_eq!((input.protocol, input.format), (Protocol::Tcp, LineFormat::Syslog));
+                assert_eq!((input.listen.as_str(), input.id.as_str()), ("0.0.0.0:5514", "_auto_time"));
+            }
+            _ => panic!("wrong input type"),
+        }
+        assert_eq!(config.path, "/tmp/storage");
+
+        let args: Vec<String> =
+            vec!["--default-limit", "20", "--log-level", "debug"].into_iter().map(String::from).collect();
+        config.apply_args(&args).unwrap();
+        assert_eq!(config.default_limit, 20);
+        assert_eq!(config.log.level, "debug");
+
+        assert!(toml::from_str::<Config>("unknown = 1").is_err());
+    }
+}
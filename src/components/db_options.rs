@@ -0,0 +1,191 @@
+use rocksdb::{BlockBasedOptions, DBCompressionType, Options, SliceTransform};
+use serde::{Deserialize, Serialize};
+
+use crate::components::errors::Error;
+
+/// Column family where the options of every db are persisted, so they can be reapplied on restart.
+pub const OPTIONS_CF: &str = "_db_options";
+
+/// Longest prefix that can be used with a capped prefix extractor.
+pub const MAX_CAPPED_PREFIX: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DbOptions {
+    pub compression:       Option<Compression>,
+    pub prefix_extractor:  Option<PrefixExtractor>,
+    pub bloom_filter_bits: Option<i32>,
+    pub block_cache_size:  Option<usize>,
+    pub write_buffer_size: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Snappy,
+    Zlib,
+    Bz2,
+    Lz4,
+    Lz4hc,
+    Zstd,
+}
+
+/// `fixed` only considers keys of at least `length` bytes as part of the prefix domain while
+/// `capped` uses the first `length` bytes of the key or the whole key if it is shorter.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", content = "length", rename_all = "lowercase")]
+pub enum PrefixExtractor {
+    Fixed(usize),
+    Capped(usize),
+}
+
+impl DbOptions {
+    pub fn from_slice(body: &[u8]) -> Result<Self, Error> {
+        if body.is_empty() {
+            return Ok(DbOptions::default())
+        }
+
+        let opts: DbOptions = serde_json::from_slice(body).map_err(Error::SerdeError)?;
+        opts.validate()?;
+
+        Ok(opts)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        match self.prefix_extractor {
+            Some(PrefixExtractor::Fixed(0)) | Some(PrefixExtractor::Capped(0)) =>
+                Err(Error::WrongDbOptions("prefix extractor length must be greater than 0".to_string())),
+            Some(PrefixExtractor::Capped(n)) if n > MAX_CAPPED_PREFIX =>
+                Err(Error::WrongDbOptions(format!("capped prefix length must be {} or lower", MAX_CAPPED_PREFIX))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Length of the prefix extractor if any. Prefix seeks with a shorter prefix than this can't
+    /// use the extractor.
+    pub fn prefix_len(&self) -> Option<usize> {
+        self.prefix_extractor.map(|p| {
+            match p {
+                PrefixExtractor::Fixed(n) | PrefixExtractor::Capped(n) => n,
+            }
+        })
+    }
+
+    pub fn to_rocksdb(&self) -> Result<Options, Error> {
+        self.validate()?;
+
+        let mut opts = Options::default();
+
+        if let Some(compression) = self.compression {
+            opts.set_compression_type(compression.into());
+        }
+
+        if let Some(size) = self.write_buffer_size {
+            opts.set_write_buffer_size(size);
+        }
+
+        match self.prefix_extractor {
+            Some(PrefixExtractor::Fixed(n)) => opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(n)),
+            Some(PrefixExtractor::Capped(n)) => {
+                let transform = capped_prefix_fn(n)
+                    .ok_or_else(|| Error::WrongDbOptions(format!("unsupported capped prefix length {}", n)))?;
+                let name = format!("sledge.CappedPrefix.{}", n);
+                opts.set_prefix_extractor(SliceTransform::create(&name, transform, None))
+            }
+            None => (),
+        }
+
+        if self.bloom_filter_bits.is_some() || self.block_cache_size.is_some() {
+            let mut block_opts = BlockBasedOptions::default();
+
+            if let Some(bits) = self.bloom_filter_bits {
+                block_opts.set_bloom_filter(bits, false);
+            }
+
+            if let Some(size) = self.block_cache_size {
+                block_opts.set_lru_cache(size);
+            }
+
+            opts.set_block_based_table_factory(&block_opts);
+        }
+
+        Ok(opts)
+    }
+}
+
+impl From<Compression> for DBCompressionType {
+    fn from(c: Compression) -> Self {
+        match c {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Zlib => DBCompressionType::Zlib,
+            Compression::Bz2 => DBCompressionType::Bz2,
+            Compression::Lz4 => DBCompressionType::Lz4,
+            Compression::Lz4hc => DBCompressionType::Lz4hc,
+            Compression::Zstd => DBCompressionType::Zstd,
+        }
+    }
+}
+
+/// Smallest key greater than every key starting with `prefix`, used as upper bound in prefix
+/// iterations. `None` if there is no such key (empty prefix or only 0xff bytes).
+pub fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut bound = prefix.to_vec();
+
+    while let Some(last) = bound.pop() {
+        if last < u8::max_value() {
+            bound.push(last + 1);
+            return Some(bound)
+        }
+    }
+
+    None
+}
+
+// RocksDB only accepts plain functions as slice transforms so a capped transform can't capture its
+// length. One function is generated per supported length instead.
+macro_rules! capped_prefixes {
+    ($($n:literal),*) => {
+        fn capped_prefix_fn(len: usize) -> Option<fn(&[u8]) -> &[u8]> {
+            match len {
+                $($n => {
+                    fn capped(k: &[u8]) -> &[u8] { &k[..k.len().min($n)] }
+                    Some(capped)
+                })*
+                _ => None,
+            }
+        }
+    };
+}
+
+capped_prefixes!(
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30,
+    31, 32
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::components::db_options::*;
+
+    #[test]
+    fn test_db_options_from_json() {
+        let body = r#"{"compression":"lz4","prefix_extractor":{"type":"capped","length":8},"bloom_filter_bits":10}"#;
+        let opts = DbOptions::from_slice(body.as_bytes()).unwrap();
+
+        assert_eq!(opts.compression, Some(Compression::Lz4));
+        assert_eq!(opts.prefix_extractor, Some(PrefixExtractor::Capped(8)));
+        assert_eq!(opts.prefix_len(), Some(8));
+        assert!(opts.to_rocksdb().is_ok());
+
+        assert!(DbOptions::from_slice(b"").unwrap().prefix_extractor.is_none());
+        assert!(DbOptions::from_slice(br#"{"prefix_extractor":{"type":"capped","length":64}}"#).is_err());
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound(b"abc"), Some(Vec::from("abd")));
+        assert_eq!(prefix_upper_bound(&[b'a', 0xff]), Some(vec![b'b']));
+        assert_eq!(prefix_upper_bound(&[0xff, 0xff]), None);
+        assert_eq!(prefix_upper_bound(b""), None);
+    }
+}
@@ -0,0 +1,52 @@
+use std::str::FromStr;
+
+use rocksdb::WriteOptions;
+use serde::{Deserialize, Serialize};
+
+/// How much a write must be persisted before it is acknowledged.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Skip the write ahead log. Data is lost if the process crashes before a flush.
+    None,
+    /// Write to the write ahead log without syncing it. Survives a process crash.
+    Wal,
+    /// Write to the write ahead log and fsync it. Survives a machine crash.
+    WalSync,
+    /// Like `WalSync` and then flush the memtables to disk, waiting for it to finish. The rocksdb
+    /// bindings can only flush the default column family, so the records of a db are kept by the
+    /// synced write ahead log until RocksDB flushes them.
+    Flush,
+}
+
+impl Default for Durability {
+    fn default() -> Self { Durability::Wal }
+}
+
+impl Durability {
+    pub fn write_options(self) -> WriteOptions {
+        let mut opts = WriteOptions::default();
+        match self {
+            Durability::None => opts.disable_wal(true),
+            Durability::WalSync | Durability::Flush => opts.set_sync(true),
+            Durability::Wal => (),
+        }
+        opts
+    }
+
+    pub fn needs_flush(self) -> bool { self == Durability::Flush }
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Durability::None),
+            "wal" => Ok(Durability::Wal),
+            "wal_sync" => Ok(Durability::WalSync),
+            "flush" => Ok(Durability::Flush),
+            s => Err(format!("unknown durability '{}', use one of none, wal, wal_sync or flush", s)),
+        }
+    }
+}
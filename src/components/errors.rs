@@ -1,10 +1,11 @@
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
+use http::StatusCode;
 use hyper::{Body, Response};
+use serde_json::Value;
 
-use crate::server::reply::Reply;
-use crate::server::responses::unknown_error;
+use crate::{channels::channel::MutatorError, server::reply::Reply};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -14,6 +15,12 @@ pub enum Error {
     #[error("error {0}")]
     Multi(String),
 
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    #[error("error running request: {0}")]
+    Join(#[from] tokio::task::JoinError),
+
     #[error("error applying filters")]
     FilterError,
 
@@ -32,6 +39,9 @@ pub enum Error {
     #[error("rocksdb error: {0}")]
     RocksDB(#[from] rocksdb::Error),
 
+    #[error("sled error: {0}")]
+    Sled(#[from] sled::Error),
+
     #[error("kafka error: {0}")]
     KafkaError(#[from] rdkafka::error::KafkaError),
 
@@ -59,6 +69,27 @@ pub enum Error {
     #[error("error creating db with name {0}: {1}")]
     CannotCreateDb(String, String),
 
+    #[error("backup error: {0}")]
+    Backup(String),
+
+    #[error("wrong db options: {0}")]
+    WrongDbOptions(String),
+
+    #[error("error dropping db with name {0}: {1}")]
+    CannotDropDb(String, String),
+
+    #[error("db '{0}' already exists")]
+    DbAlreadyExists(String),
+
+    #[error("'{0}' is an internal db and can't be changed through the /_db routes")]
+    InternalDb(String),
+
+    #[error("db '{0}' is being copied or renamed")]
+    DbBusy(String),
+
+    #[error("a target db is required, use the 'to' query parameter")]
+    MissingTargetDb,
+
     #[error("error reading db with name {0}: {1}")]
     CannotReadDB(String, String),
 
@@ -71,9 +102,21 @@ pub enum Error {
     #[error("id/db '{0}' not found")]
     NotFound(String),
 
+    #[error("snapshot '{0}' not found or its lease expired")]
+    SnapshotNotFound(String),
+
+    #[error("changes after {0} were trimmed from the log, the oldest one kept is {1}")]
+    ChangesTrimmed(u64, u64),
+
+    #[error("dbs can't be created or dropped while snapshots are alive, release them first: {}", .0.join(", "))]
+    SnapshotsAlive(Vec<String>),
+
     #[error("channel '{0}' not found")]
     ChannelNotFound(String),
 
+    #[error("sink '{0}' not found")]
+    SinkNotFound(String),
+
     #[error("json error: {0}")]
     SerdeError(#[from] serde_json::Error),
 
@@ -86,6 +129,30 @@ pub enum Error {
     #[error("error applying channel: {0}")]
     ChannelError(String),
 
+    #[error("invalid channel: {}", describe_mutator_errors(.0))]
+    InvalidChannel(Vec<MutatorError>),
+
+    #[error("invalid channel id '{0}': ids can't start with '_' or contain '@', which pins a version")]
+    InvalidChannelId(String),
+
+    #[error("{0} not supported by the storage")]
+    Unsupported(String),
+
+    #[error("the server is a read-only follower")]
+    ReadOnly,
+
+    #[error("replication error: {0}")]
+    Replication(String),
+
+    #[error("error from the server: {0}")]
+    Remote(String),
+
+    #[error("error sending records to the sink: {}", .0.join(". "))]
+    Sink(Vec<String>),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
     #[error("method not implemented")]
     MethodNotFound,
 
@@ -96,20 +163,111 @@ pub enum Error {
     SqlError(#[from] sqlparser::parser::ParserError),
 }
 
-impl From<Error> for Response<Body> {
-    fn from(err: Error) -> Self {
-        let string = match serde_json::to_string(&Reply::error(err)) {
-            Ok(s) => s,
-            Err(err) => err.to_string(),
-        };
-
-        let res = http::Response::builder()
-            .header("Content-Type", "application/json")
-            .body(Body::from(string));
-
-        match res {
-            Ok(ok_res) => ok_res,
-            Err(err) => unknown_error(err.to_string()),
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_)
+            | Error::CFNotFound(_)
+            | Error::CannotRetrieveCF(_)
+            | Error::ChannelNotFound(_)
+            | Error::SnapshotNotFound(_)
+            | Error::SinkNotFound(_)
+            | Error::ValueNotFound(_) => StatusCode::NOT_FOUND,
+            Error::SqlError(_)
+            | Error::WrongQuery
+            | Error::BodyParsingError(_)
+            | Error::IdNotFoundInJSON(_)
+            | Error::NoIdFoundOnRequest
+            | Error::MissingID
+            | Error::MissingQuery
+            | Error::MissingTargetDb
+            | Error::WrongDbOptions(_)
+            | Error::Parse(_)
+            | Error::ParseFromUtf8(_)
+            | Error::Utf8Error(_)
+            | Error::SerdeError(_)
+            | Error::ChannelError(_)
+            | Error::InvalidChannel(_)
+            | Error::InvalidChannelId(_) => StatusCode::BAD_REQUEST,
+            Error::DbAlreadyExists(_) | Error::DbBusy(_) | Error::SnapshotsAlive(_) => StatusCode::CONFLICT,
+            Error::InternalDb(_) => StatusCode::FORBIDDEN,
+            Error::ChangesTrimmed(..) => StatusCode::GONE,
+            Error::MethodNotFound => StatusCode::METHOD_NOT_ALLOWED,
+            Error::ReadOnly => StatusCode::FORBIDDEN,
+            Error::Sink(_) => StatusCode::BAD_GATEWAY,
+            Error::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable identifier of the error, sent in the `code` field of the replies so clients don't
+    /// need to parse the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::BodyParsingError(_) => "body_parsing_error",
+            Error::Multi(_) => "multiple_errors",
+            Error::Config(_) => "config_error",
+            Error::Join(_) => "internal_error",
+            Error::FilterError => "filter_error",
+            Error::IdNotFoundInJSON(_) => "id_not_found_in_json",
+            Error::NoIdFoundOnRequest | Error::MissingID => "missing_id",
+            Error::MissingQuery => "missing_query",
+            Error::RocksDB(_) | Error::Sled(_) | Error::Db(_) => "storage_error",
+            Error::KafkaError(_) => "kafka_error",
+            Error::ValueNotFound(_) | Error::NotFound(_) => "not_found",
+            Error::Parse(_) | Error::ParseFromUtf8(_) | Error::Utf8Error(_) => "invalid_utf8",
+            Error::Preparing(_) => "preparing_error",
+            Error::Put(_) => "put_error",
+            Error::CannotCreateDb(..) => "cannot_create_db",
+            Error::Backup(_) => "backup_error",
+            Error::WrongDbOptions(_) => "invalid_db_options",
+            Error::CannotDropDb(..) => "cannot_drop_db",
+            Error::DbAlreadyExists(_) => "db_already_exists",
+            Error::InternalDb(_) => "internal_db",
+            Error::DbBusy(_) => "db_busy",
+            Error::MissingTargetDb => "missing_target_db",
+            Error::CannotReadDB(..) => "cannot_read_db",
+            Error::CannotRetrieveCF(_) | Error::CFNotFound(_) => "db_not_found",
+            Error::SnapshotNotFound(_) => "snapshot_not_found",
+            Error::SnapshotsAlive(_) => "snapshots_alive",
+            Error::ChangesTrimmed(..) => "changes_trimmed",
+            Error::ChannelNotFound(_) => "channel_not_found",
+            Error::SinkNotFound(_) => "sink_not_found",
+            Error::SerdeError(_) | Error::Serializing(_) => "invalid_json",
+            Error::WrongQuery => "wrong_query",
+            Error::ChannelError(_) => "channel_error",
+            Error::InvalidChannel(_) => "invalid_channel",
+            Error::InvalidChannelId(_) => "invalid_channel_id",
+            Error::Unsupported(_) => "unsupported",
+            Error::ReadOnly => "read_only",
+            Error::Replication(_) => "replication_error",
+            Error::Remote(_) => "remote_error",
+            Error::Sink(_) => "sink_error",
+            Error::Io(_) => "io_error",
+            Error::MethodNotFound => "method_not_allowed",
+            Error::GeneratingResponse(_) => "internal_error",
+            Error::SqlError(_) => "invalid_sql",
+        }
+    }
+
+    /// Structured details of the error, sent in the `data` field of the replies.
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            Error::InvalidChannel(errors) => serde_json::to_value(errors).ok(),
+            Error::SnapshotsAlive(ids) => serde_json::to_value(ids).ok(),
+            _ => None,
         }
     }
 }
+
+fn describe_mutator_errors(errors: &[MutatorError]) -> String {
+    if errors.is_empty() {
+        return "it has no mutators".to_string()
+    }
+
+    errors.iter().map(MutatorError::to_string).collect::<Vec<_>>().join(", ")
+}
+
+impl From<Error> for Response<Body> {
+    fn from(err: Error) -> Self { Reply::error(err).into() }
+}
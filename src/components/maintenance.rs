@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+/// Result of reading every record of a db verifying the checksums of the blocks read.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VerifyReport {
+    pub name:          String,
+    pub keys_verified: u64,
+    pub error:         Option<String>,
+}
+
+/// Dbs affected by a maintenance operation (compaction or flush).
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MaintenanceReport {
+    pub dbs: Vec<String>,
+}
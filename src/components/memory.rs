@@ -0,0 +1,181 @@
+use std::{
+    collections::BTreeMap,
+    ops::Bound::{Excluded, Included, Unbounded},
+    sync::RwLock,
+};
+
+use crate::components::{
+    db_options::{prefix_upper_bound, DbOptions},
+    durability::Durability,
+    errors::Error,
+    simple_pair::SimplePair,
+    storage::{no_snapshot, BatchOp, IterFn, KvIter, Storage},
+};
+
+type Tree = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// In-memory storage where every db is a `BTreeMap`. Nothing is persisted so durability and db
+/// options are ignored.
+#[derive(Default)]
+pub struct Memory {
+    dbs: RwLock<BTreeMap<String, Tree>>,
+}
+
+impl Storage for Memory {
+    fn get(&self, db: &str, id: &str, snapshot: Option<&str>) -> Result<SimplePair, Error> {
+        no_snapshot(snapshot)?;
+        let dbs = self.dbs.read().unwrap();
+        let tree = dbs.get(db).ok_or_else(|| Error::CFNotFound(db.to_string()))?;
+
+        tree.get(id.as_bytes())
+            .map(|v| SimplePair::new_str_vec(id, v.clone()))
+            .ok_or_else(|| Error::NotFound(id.to_string()))
+    }
+
+    fn batch(&self, db: &str, ops: Vec<BatchOp>, _durability: Durability) -> Result<(), Error> {
+        let mut dbs = self.dbs.write().unwrap();
+        let tree = dbs.get_mut(db).ok_or_else(|| Error::CannotRetrieveCF(db.to_string()))?;
+
+        for op in ops {
+            match op {
+                BatchOp::Put(k, v) => tree.insert(k, v),
+                BatchOp::Delete(k) => tree.remove(&k),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn range(
+        &self, db: &str, is_reverse: bool, id: Option<String>, snapshot: Option<&str>, f: IterFn,
+    ) -> Result<Vec<SimplePair>, Error> {
+        no_snapshot(snapshot)?;
+        let dbs = self.dbs.read().unwrap();
+        let tree = dbs.get(db).ok_or_else(|| Error::CFNotFound(db.to_string()))?;
+
+        let iter: KvIter = match (id, is_reverse) {
+            (Some(id), false) => box to_pairs(tree.range(id.into_bytes()..)),
+            (Some(id), true) => box to_pairs(tree.range(..=id.into_bytes()).rev()),
+            (None, false) => box to_pairs(tree.iter()),
+            (None, true) => box to_pairs(tree.iter().rev()),
+        };
+
+        Ok(f(iter))
+    }
+
+    fn range_prefix(
+        &self, db: &str, prefix: String, snapshot: Option<&str>, f: IterFn,
+    ) -> Result<Vec<SimplePair>, Error> {
+        no_snapshot(snapshot)?;
+        let dbs = self.dbs.read().unwrap();
+        let tree = dbs.get(db).ok_or_else(|| Error::CFNotFound(db.to_string()))?;
+
+        let upper = match prefix_upper_bound(prefix.as_bytes()) {
+            Some(upper) => Excluded(upper),
+            None => Unbounded,
+        };
+
+        Ok(f(box to_pairs(tree.range((Included(prefix.into_bytes()), upper)))))
+    }
+
+    fn create_db(&self, db: &str, _opts: &DbOptions) -> Result<(), Error> {
+        let mut dbs = self.dbs.write().unwrap();
+        if dbs.contains_key(db) {
+            return Err(Error::DbAlreadyExists(db.to_string()))
+        }
+
+        dbs.insert(db.to_string(), Tree::new());
+        Ok(())
+    }
+
+    fn drop_db(&self, db: &str) -> Result<(), Error> {
+        let mut dbs = self.dbs.write().unwrap();
+        dbs.remove(db).ok_or_else(|| Error::CannotDropDb(db.to_string(), "db not found".to_string()))?;
+
+        Ok(())
+    }
+
+    fn truncate_db(&self, db: &str) -> Result<(), Error> {
+        let mut dbs = self.dbs.write().unwrap();
+        dbs.get_mut(db).ok_or_else(|| Error::CFNotFound(db.to_string()))?.clear();
+
+        Ok(())
+    }
+
+    fn copy_db(&self, from: &str, to: &str, f: &dyn Fn(SimplePair) -> Option<SimplePair>) -> Result<usize, Error> {
+        let mut dbs = self.dbs.write().unwrap();
+        if dbs.contains_key(to) {
+            return Err(Error::DbAlreadyExists(to.to_string()))
+        }
+
+        let copy = dbs
+            .get(from)
+            .ok_or_else(|| Error::CFNotFound(from.to_string()))?
+            .iter()
+            .filter_map(|(k, v)| f(SimplePair::new_vec(k.clone(), v.clone())))
+            .map(|sp| (sp.id, sp.value))
+            .collect::<Tree>();
+
+        let total = copy.len();
+        dbs.insert(to.to_string(), copy);
+
+        Ok(total)
+    }
+
+    fn rename_db(&self, from: &str, to: &str) -> Result<usize, Error> {
+        let mut dbs = self.dbs.write().unwrap();
+        if dbs.contains_key(to) {
+            return Err(Error::DbAlreadyExists(to.to_string()))
+        }
+
+        let tree = dbs.remove(from).ok_or_else(|| Error::CFNotFound(from.to_string()))?;
+        let total = tree.len();
+        dbs.insert(to.to_string(), tree);
+
+        Ok(total)
+    }
+
+    fn list_dbs(&self) -> Result<Vec<String>, Error> { Ok(self.dbs.read().unwrap().keys().cloned().collect()) }
+}
+
+fn to_pairs<'a>(
+    iter: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)> + Send + Sync + 'a,
+) -> impl Iterator<Item = SimplePair> + Send + Sync + 'a {
+    iter.map(|(k, v)| SimplePair::new_vec(k.clone(), v.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::components::{durability::Durability, memory::*};
+
+    fn collect() -> IterFn { box |iter| iter.collect() }
+
+    #[test]
+    fn test_memory_storage() {
+        let storage = Memory::default();
+        storage.create_db("test_db", &DbOptions::default()).unwrap();
+
+        for k in &["a1", "a2", "b1", "b2"] {
+            storage.put("test_db", Vec::from(*k), Vec::from(r#"{"hello":"world"}"#), Durability::None).unwrap();
+        }
+
+        assert_eq!(storage.get("test_db", "a2", None).unwrap().id, Vec::from("a2"));
+        assert!(storage.get("test_db", "c1", None).is_err());
+        assert!(storage.put("unknown", Vec::from("a"), Vec::from("b"), Durability::None).is_err());
+
+        let res = storage.range_prefix("test_db", "a".to_string(), None, collect()).unwrap();
+        assert_eq!(res.iter().map(|sp| sp.id.clone()).collect::<Vec<_>>(), vec![Vec::from("a1"), Vec::from("a2")]);
+
+        let res = storage.range("test_db", true, Some("b1".to_string()), None, collect()).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].id, Vec::from("b1"));
+
+        assert_eq!(storage.copy_db("test_db", "copy_db", &Some).unwrap(), 4);
+        assert_eq!(storage.rename_db("copy_db", "renamed_db").unwrap(), 4);
+        storage.truncate_db("renamed_db").unwrap();
+        assert!(storage.range("renamed_db", false, None, None, collect()).unwrap().is_empty());
+
+        storage.drop_db("renamed_db").unwrap();
+        assert_eq!(storage.list_dbs().unwrap(), vec!["test_db".to_string()]);
+    }
+}
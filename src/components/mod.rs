@@ -1,5 +1,16 @@
-pub(crate) mod errors;
+pub mod backup;
+pub mod changes;
+pub mod config;
+pub mod db_options;
+pub mod durability;
+pub mod errors;
+pub mod maintenance;
+pub mod memory;
 pub(crate) mod raw_iterator;
 pub mod rocks;
-pub(crate) mod simple_pair;
+pub mod simple_pair;
+pub mod sled_storage;
+pub mod snapshots;
+pub mod stats;
+pub mod storage;
 pub(crate) mod sql;
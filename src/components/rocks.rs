@@ -1,117 +1,859 @@
 use std::{
-    env,
-    sync::{Arc, RwLock},
+    collections::{HashMap, HashSet},
+    mem,
+    path::Path,
+    sync::{Arc, Mutex, RwLock, RwLockWriteGuard},
+    time::{Duration, Instant},
 };
 
-use rocksdb::{DBIterator, Direction, IteratorMode, Options, DB};
+use rocksdb::{ColumnFamilyDescriptor, Direction, FlushOptions, IteratorMode, Options, ReadOptions, WriteBatch, DB};
 
-use crate::components::{errors::Error, simple_pair::SimplePair};
-
-pub fn range<F>(
-    db: Arc<RwLock<DB>>, is_reverse: bool, id: Option<String>, cf: &str, f: F,
-) -> Result<Vec<SimplePair>, Error>
-where
-    F: FnOnce(DBIterator) -> Vec<SimplePair>,
-{
-    let mode = get_range_mode(is_reverse, &id);
-    let db = db.read().unwrap();
-    let cf = db.cf_handle(cf).ok_or_else(|| Error::CFNotFound(cf.to_string()))?;
+use crate::components::{
+    backup::{self, BackupInfo},
+    changes::{encode, seq_from_key, seq_key, Change, ChangeLog, ChangeOp, ChangesPage, Reservation, CHANGES_CF},
+    config::RocksConfig,
+    db_options::{DbOptions, OPTIONS_CF},
+    durability::Durability,
+    errors::Error,
+    maintenance::{MaintenanceReport, VerifyReport},
+    simple_pair::SimplePair,
+    snapshots::{SnapshotLease, SnapshotRef, Snapshots},
+    stats::{
+        DbStats, InstanceStats, CUR_SIZE_ALL_MEM_TABLES, ESTIMATE_NUM_KEYS, ESTIMATE_PENDING_COMPACTION_BYTES,
+        LIVE_SST_FILES_SIZE, TOTAL_SST_FILES_SIZE,
+    },
+    storage::{BatchOp, IterFn, Storage},
+};
 
-    let source_iter = db.iterator_cf(cf, mode).map_err(Error::RocksDB)?;
+pub const DEFAULT_CF: &str = "default";
 
-    let vector = f(source_iter);
+/// Prefix of the keys of the default column family marking a rename in progress.
+const RENAME_MARKER: &str = "_rename:";
 
-    Ok(vector)
+/// RocksDB storage, where every db is a column family.
+///
+/// The lock only needs to be taken exclusively by the operations that change the set of column
+/// families (create, drop, truncate...). Reads and writes share it.
+///
+/// With `change_log` enabled every put and delete is also written into `CHANGES_CF`, which is
+/// trimmed to the last `change_log_retention` changes after every write.
+pub struct Rocks {
+    db:        Arc<RwLock<DB>>,
+    snapshots: Snapshots,
+    changes:   Option<ChangeLog>,
+    /// Dbs being copied or renamed, which can't be written, dropped or truncated meanwhile.
+    busy:      Mutex<HashSet<String>>,
 }
 
-pub fn range_prefix<F>(db: Arc<RwLock<DB>>, id: String, cf_name: &str, f: F) -> Result<Vec<SimplePair>, Error>
-where
-    F: FnOnce(DBIterator) -> Vec<SimplePair>,
-{
-    let db = db.read().unwrap();
+impl Rocks {
+    pub fn new(path: String, config: &RocksConfig) -> Result<Self, Error> {
+        let mut db = open(path, config)?;
+        let changes = if config.change_log {
+            Some(open_change_log(&mut db, config.change_log_retention)?)
+        } else {
+            None
+        };
 
-    let cf = db.cf_handle(cf_name).ok_or_else(|| Error::CFNotFound(cf_name.to_string()))?;
-    let iter = db.prefix_iterator_cf(cf, id).map_err(Error::RocksDB)?;
+        let db = Arc::new(RwLock::new(db));
+        let rocks = Rocks { snapshots: Snapshots::new(db.clone()), db, changes, busy: Mutex::new(HashSet::new()) };
+        rocks.recover_renames();
+        Ok(rocks)
+    }
 
-    let vector = f(iter);
+    /// Logs a change of a whole db, like its creation.
+    fn log_db_change(&self, db: &DB, cf: &str, op: ChangeOp, value: Option<&[u8]>) -> Result<(), Error> {
+        if let Some(mut log) = LogWriter::new(&self.changes, 1) {
+            let mut batch = WriteBatch::default();
+            log.add(db, &mut batch, cf, op, &[], value)?;
+            db.write(batch).map_err(Error::RocksDB)?;
+            drop(log);
+            self.trim_changes(db);
+        }
 
-    Ok(vector)
-}
+        Ok(())
+    }
+
+    /// Deletes the changes over the retention of the log. A failure is only logged, as the
+    /// write that triggered it is already done.
+    fn trim_changes(&self, db: &DB) {
+        let trimmed = match self.changes.as_ref().and_then(|log| log.trim(TRIM_BATCH_SIZE)) {
+            Some(trimmed) => trimmed,
+            None => return,
+        };
+
+        let changes_cf = match db.cf_handle(CHANGES_CF) {
+            Some(changes_cf) => changes_cf,
+            None => return,
+        };
+
+        let mut batch = WriteBatch::default();
+        let result = trimmed.clone().try_for_each(|seq| batch.delete_cf(changes_cf, seq_key(seq)));
+        if let Err(err) = result.and_then(|_| db.write(batch)) {
+            log::warn!("error trimming the changes {:?} of the change log: {}", trimmed, err);
+        }
+    }
+
+    fn snapshot(&self, id: Option<&str>) -> Result<Option<Arc<SnapshotRef>>, Error> {
+        match id {
+            Some(id) => Ok(Some(self.snapshots.get(id)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Takes the lock to create or drop column families. RocksDB needs `&mut DB` for that, which
+    /// can't be handed out while the snapshots taken through the API borrow the db. The error lists
+    /// the leases to release, released snapshots still used by a read aren't listed.
+    fn lock_for_cf_change(&self) -> Result<RwLockWriteGuard<DB>, Error> {
+        let db = self.db.write().unwrap();
+        match self.snapshots.alive() {
+            0 => Ok(db),
+            _ => Err(Error::SnapshotsAlive(self.snapshots.ids())),
+        }
+    }
+
+    fn check_not_busy(&self, cf: &str) -> Result<(), Error> {
+        if self.busy.lock().unwrap().contains(cf) {
+            return Err(Error::DbBusy(cf.to_string()))
+        }
+
+        Ok(())
+    }
+
+    /// Writes `ops` into the column family, logging them when the change log is enabled.
+    fn write(&self, db: &DB, cf_name: &str, ops: Vec<BatchOp>, durability: Durability) -> Result<(), Error> {
+        let cf = db.cf_handle(cf_name).ok_or_else(|| Error::CannotRetrieveCF(cf_name.to_string()))?;
+
+        let mut log = LogWriter::new(&self.changes, ops.len());
+        let mut batch = WriteBatch::default();
+        for op in ops {
+            if let Some(log) = log.as_mut() {
+                match &op {
+                    BatchOp::Put(k, v) => log.add(db, &mut batch, cf_name, ChangeOp::Put, k, Some(v))?,
+                    BatchOp::Delete(k) => log.add(db, &mut batch, cf_name, ChangeOp::Delete, k, None)?,
+                }
+            }
+
+            match op {
+                BatchOp::Put(k, v) => batch.put_cf(cf, k, v),
+                BatchOp::Delete(k) => batch.delete_cf(cf, k),
+            }
+            .map_err(Error::RocksDB)?;
+        }
+
+        db.write_opt(batch, &durability.write_options()).map_err(|err| Error::Put(err.to_string()))?;
+        drop(log);
+
+        if durability.needs_flush() {
+            flush_memtables(db).map_err(|err| Error::Put(err.to_string()))?;
+        }
+        self.trim_changes(db);
+
+        Ok(())
+    }
+
+    /// Creates `to` with the options of `from`. Both stay busy until the copy is finished or
+    /// aborted, and a rename also leaves a marker so it can be recovered after a crash.
+    fn start_copy(&self, from: &str, to: &str, rename: bool) -> Result<(), Error> {
+        let mut inner = self.lock_for_cf_change()?;
+        inner.cf_handle(from).ok_or_else(|| Error::CFNotFound(from.to_string()))?;
+        if inner.cf_handle(to).is_some() {
+            return Err(Error::DbAlreadyExists(to.to_string()))
+        }
+
+        let mut busy = self.busy.lock().unwrap();
+        if busy.contains(from) {
+            return Err(Error::DbBusy(from.to_string()))
+        }
+
+        if rename {
+            inner.put(rename_marker(from), to).map_err(Error::RocksDB)?;
+        }
+
+        let opts = get_db_options(&inner, from)?.unwrap_or_default();
+        create(&mut inner, to, &opts)?;
+        self.log_db_change(&inner, to, ChangeOp::CreateDb, Some(&serde_json::to_vec(&opts)?))?;
+
+        busy.insert(to.to_string());
+        if rename {
+            busy.insert(from.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Copies the records of a snapshot of `from` in batches. Only the read lock is held, so the
+    /// other dbs can still be read and written.
+    fn copy_records(&self, from: &str, to: &str, f: &dyn Fn(SimplePair) -> Option<SimplePair>) -> Result<usize, Error> {
+        let db = self.db.read().unwrap();
+        let from_cf = db.cf_handle(from).ok_or_else(|| Error::CFNotFound(from.to_string()))?;
+
+        let snapshot = db.snapshot();
+        let iter = snapshot.iterator_cf(from_cf, IteratorMode::Start).map_err(Error::RocksDB)?;
+
+        let mut total = 0;
+        let mut ops = Vec::with_capacity(COPY_BATCH_SIZE);
+        for sp in iter.map(SimplePair::new_boxed).filter_map(f) {
+            ops.push(BatchOp::Put(sp.id, sp.value));
+            total += 1;
+
+            if ops.len() >= COPY_BATCH_SIZE {
+                let batch = mem::replace(&mut ops, Vec::with_capacity(COPY_BATCH_SIZE));
+                self.write(&db, to, batch, Durability::Wal)?;
+            }
+        }
+
+        if !ops.is_empty() {
+            self.write(&db, to, ops, Durability::Wal)?;
+        }
+
+        log::debug!("{} records copied from column family '{}' to '{}'", total, from, to);
+
+        Ok(total)
+    }
+
+    /// Drops the source db of a rename once every record has been copied.
+    fn finish_rename(&self, from: &str) -> Result<(), Error> {
+        let mut inner = self.lock_for_cf_change()?;
+        inner.drop_cf(from).map_err(|err| Error::CannotDropDb(from.to_string(), err.to_string()))?;
+        delete_db_options(&inner, from)?;
+        self.log_db_change(&inner, from, ChangeOp::DropDb, None)?;
+        inner.delete(rename_marker(from)).map_err(Error::RocksDB)
+    }
 
-pub fn try_streaming<F, R>(db: Arc<RwLock<DB>>, f: F) -> Result<R, Error>
-where
-    F: FnOnce(DBIterator) -> R,
-{
-    let mode = get_range_mode(false, &None);
-    let db = db.read().unwrap();
-    let cf = db.cf_handle("test_db").ok_or_else(|| Error::CannotRetrieveCF("test_db".to_string()))?;
+    /// Drops the partial copy of a failed copy or rename, leaving `from` as it was.
+    fn abort_copy(&self, from: &str, to: &str) -> Result<(), Error> {
+        let mut inner = self.lock_for_cf_change()?;
+        if inner.cf_handle(to).is_some() {
+            inner.drop_cf(to).map_err(|err| Error::CannotDropDb(to.to_string(), err.to_string()))?;
+            delete_db_options(&inner, to)?;
+            self.log_db_change(&inner, to, ChangeOp::DropDb, None)?;
+        }
+
+        inner.delete(rename_marker(from)).map_err(Error::RocksDB)
+    }
 
-    let source_iter = db.iterator_cf(cf, mode).map_err(Error::RocksDB)?;
+    fn end_copy(&self, dbs: &[&str]) {
+        let mut busy = self.busy.lock().unwrap();
+        for db in dbs {
+            busy.remove(*db);
+        }
+    }
 
-    Ok(f(source_iter))
+    /// A rename is a copy followed by the drop of the source db, so a crash can leave it half done.
+    /// When the source db still exists the copy is discarded, otherwise the rename had finished.
+    fn recover_renames(&self) {
+        let renames = rename_markers(&self.db.read().unwrap());
+
+        for (from, to) in renames {
+            let exists = self.db.read().unwrap().cf_handle(&from).is_some();
+            let result = if exists {
+                self.abort_copy(&from, &to)
+            } else {
+                self.db.read().unwrap().delete(rename_marker(&from)).map_err(Error::RocksDB)
+            };
+
+            match result {
+                Ok(()) if exists => log::warn!("interrupted rename of '{}' to '{}' rolled back", from, to),
+                Ok(()) => log::info!("interrupted rename of '{}' to '{}' completed", from, to),
+                Err(err) => log::error!("error recovering the rename of '{}' to '{}': {}", from, to, err),
+            }
+        }
+    }
 }
 
-pub fn get<F>(db: Arc<RwLock<DB>>, cf: &str, id: &str, f: F) -> Result<Vec<SimplePair>, Error>
-where
-    F: FnOnce(SimplePair) -> Vec<SimplePair>,
-{
-    let db = db.read().unwrap();
+impl Storage for Rocks {
+    fn get(&self, cf: &str, id: &str, snapshot: Option<&str>) -> Result<SimplePair, Error> {
+        let snapshot = self.snapshot(snapshot)?;
+        let db = self.db.read().unwrap();
 
-    let cf = db.cf_handle(&cf).ok_or_else(|| Error::CFNotFound(cf.to_string()))?;
+        let cf = db.cf_handle(&cf).ok_or_else(|| Error::CFNotFound(cf.to_string()))?;
 
-    let res = db
-        .get_cf(cf, id)
+        match &snapshot {
+            Some(snapshot) => snapshot.get_cf(cf, id),
+            None => db.get_cf(cf, id),
+        }
         .map_err(Error::RocksDB)?
         .ok_or_else(|| Error::NotFound(id.to_string()))
-        .map(|v| SimplePair::new_str_vec(id, v))?;
+        .map(|v| SimplePair::new_str_vec(id, v))
+    }
 
-    let result = f(res);
+    fn put(&self, cf_name: &str, k: Vec<u8>, v: Vec<u8>, durability: Durability) -> Result<(), Error> {
+        if self.changes.is_some() {
+            return self.batch(cf_name, vec![BatchOp::Put(k, v)], durability)
+        }
 
-    Ok(result)
-}
+        let db = self.db.read().unwrap();
+        self.check_not_busy(cf_name)?;
 
-pub fn put(db: Arc<RwLock<DB>>, cf_name: &str, k: Vec<u8>, v: Vec<u8>) -> Result<(), Error> {
-    let db = db.write().unwrap();
+        let cf = db.cf_handle(cf_name).ok_or_else(|| Error::CannotRetrieveCF(cf_name.to_string()))?;
 
-    let cf = db.cf_handle(cf_name).ok_or_else(|| Error::CannotRetrieveCF(cf_name.to_string()))?;
+        db.put_cf_opt(cf, k, v, &durability.write_options()).map_err(|err| Error::Put(err.to_string()))?;
 
-    let mut res: rocksdb::FlushOptions = rocksdb::FlushOptions::default();
-    res.set_wait(true);
-    db.put_cf(cf, k, v).and(db.flush_opt(&res)).or_else(|err| Err(Error::Put(err.to_string())))
-}
+        if durability.needs_flush() {
+            flush_memtables(db).map_err(|err| Error::Put(err.to_string()))?;
+        }
 
-pub fn create_cf(db: Arc<RwLock<DB>>, cf: &str) -> Result<(), Error> {
-    let mut inner = db.write().unwrap();
-    inner
-        .create_cf(cf, &rocksdb::Options::default())
-        .map_err(|err| Error::CannotCreateDb(cf.to_string(), err.to_string()))?;
-    log::debug!("column family '{}' created", cf);
+        Ok(())
+    }
 
-    Ok(())
-}
+    fn batch(&self, cf_name: &str, ops: Vec<BatchOp>, durability: Durability) -> Result<(), Error> {
+        let db = self.db.read().unwrap();
+        self.check_not_busy(cf_name)?;
+
+        self.write(&db, cf_name, ops, durability)
+    }
+
+    fn range(
+        &self, cf: &str, is_reverse: bool, id: Option<String>, snapshot: Option<&str>, f: IterFn,
+    ) -> Result<Vec<SimplePair>, Error> {
+        let snapshot = self.snapshot(snapshot)?;
+        let mode = get_range_mode(is_reverse, &id);
+        let db = self.db.read().unwrap();
+        let cf = db.cf_handle(cf).ok_or_else(|| Error::CFNotFound(cf.to_string()))?;
+
+        let source_iter = match &snapshot {
+            Some(snapshot) => snapshot.iterator_cf(cf, mode),
+            None => db.iterator_cf(cf, mode),
+        }
+        .map_err(Error::RocksDB)?;
+
+        let vector = f(box source_iter.map(SimplePair::new_boxed));
+
+        Ok(vector)
+    }
+
+    fn range_prefix(
+        &self, cf_name: &str, id: String, snapshot: Option<&str>, f: IterFn,
+    ) -> Result<Vec<SimplePair>, Error> {
+        let snapshot = self.snapshot(snapshot)?;
+        let db = self.db.read().unwrap();
+
+        let cf = db.cf_handle(cf_name).ok_or_else(|| Error::CFNotFound(cf_name.to_string()))?;
+
+        // The prefix extractor can only be used when the requested prefix is at least as long as the
+        // one of the extractor, otherwise a total order seek is done. In both cases the iteration
+        // stops at the first key not starting with `id`. An iterate upper bound would be cheaper, but
+        // RocksDB keeps a pointer to it for the whole life of the iterator.
+        let mut read_opts = ReadOptions::default();
+        match get_db_options(&db, cf_name)?.and_then(|o| o.prefix_len()) {
+            Some(len) if id.len() >= len => read_opts.set_prefix_same_as_start(true),
+            _ => read_opts.set_total_order_seek(true),
+        }
+
+        let mode = IteratorMode::From(id.as_bytes(), Direction::Forward);
+        let iter = match &snapshot {
+            Some(snapshot) => snapshot.iterator_cf_opt(cf, read_opts, mode),
+            None => db.iterator_cf_opt(cf, &read_opts, mode),
+        }
+        .map_err(Error::RocksDB)?;
+
+        let prefix = id.into_bytes();
+        let vector = f(box iter.take_while(move |(k, _)| k.starts_with(&prefix)).map(SimplePair::new_boxed));
+
+        Ok(vector)
+    }
+
+    fn create_db(&self, cf: &str, opts: &DbOptions) -> Result<(), Error> {
+        let mut inner = self.lock_for_cf_change()?;
+        if inner.cf_handle(cf).is_some() {
+            return Err(Error::DbAlreadyExists(cf.to_string()))
+        }
+
+        create(&mut inner, cf, opts)?;
+        self.log_db_change(&inner, cf, ChangeOp::CreateDb, Some(&serde_json::to_vec(opts)?))?;
+        log::debug!("column family '{}' created", cf);
+
+        Ok(())
+    }
+
+    fn drop_db(&self, cf: &str) -> Result<(), Error> {
+        let mut inner = self.lock_for_cf_change()?;
+        self.check_not_busy(cf)?;
+        inner.drop_cf(cf).map_err(|err| Error::CannotDropDb(cf.to_string(), err.to_string()))?;
+        delete_db_options(&inner, cf)?;
+        self.log_db_change(&inner, cf, ChangeOp::DropDb, None)?;
+        log::debug!("column family '{}' dropped", cf);
+
+        Ok(())
+    }
+
+    fn truncate_db(&self, cf: &str) -> Result<(), Error> {
+        let mut inner = self.lock_for_cf_change()?;
+        self.check_not_busy(cf)?;
+        inner.cf_handle(cf).ok_or_else(|| Error::CFNotFound(cf.to_string()))?;
+        let opts = get_db_options(&inner, cf)?.unwrap_or_default();
+
+        inner.drop_cf(cf).map_err(|err| Error::CannotDropDb(cf.to_string(), err.to_string()))?;
+        create(&mut inner, cf, &opts)?;
+        self.log_db_change(&inner, cf, ChangeOp::TruncateDb, None)?;
+        log::debug!("column family '{}' truncated", cf);
+
+        Ok(())
+    }
+
+    /// The write lock is only taken to create `to`. A failed copy drops it again.
+    fn copy_db(&self, from: &str, to: &str, f: &dyn Fn(SimplePair) -> Option<SimplePair>) -> Result<usize, Error> {
+        self.start_copy(from, to, false)?;
+
+        let result = self.copy_records(from, to, f).or_else(|err| self.abort_copy(from, to).and(Err(err)));
+
+        self.end_copy(&[to]);
+        result
+    }
+
+    /// RocksDB can't rename column families, so the records are copied and `from` is dropped
+    /// afterwards. It isn't atomic: both dbs exist meanwhile and can't be written. A rename
+    /// interrupted by a crash is rolled back, or completed if `from` was already dropped, when the
+    /// storage is opened again.
+    fn rename_db(&self, from: &str, to: &str) -> Result<usize, Error> {
+        self.start_copy(from, to, true)?;
+
+        let result = match self.copy_records(from, to, &Some) {
+            Ok(total) => self.finish_rename(from).map(|_| total),
+            Err(err) => self.abort_copy(from, to).and(Err(err)),
+        };
+
+        self.end_copy(&[from, to]);
+        if result.is_ok() {
+            log::debug!("column family '{}' renamed to '{}'", from, to);
+        }
+
+        result
+    }
+
+    fn list_dbs(&self) -> Result<Vec<String>, Error> {
+        let db = self.db.read().unwrap();
+        cf_names(&db)
+    }
+
+    fn stats(&self, cf: &str) -> Result<DbStats, Error> {
+        let db = self.db.read().unwrap();
+        db_stats(&db, cf)
+    }
+
+    fn instance_stats(&self) -> Result<InstanceStats, Error> {
+        let db = self.db.read().unwrap();
+        let path = db.path().to_string_lossy().to_string();
+
+        let dbs = cf_names(&db)?.iter().map(|cf| db_stats(&db, cf)).collect::<Result<Vec<DbStats>, Error>>()?;
+
+        Ok(InstanceStats::new(path, dbs))
+    }
+
+    fn compact(&self, cf: Option<&str>, start: Option<&str>, end: Option<&str>) -> Result<MaintenanceReport, Error> {
+        let db = self.db.read().unwrap();
+        let dbs = target_cfs(&db, cf)?;
+
+        for name in dbs.iter() {
+            let handle = db.cf_handle(name).ok_or_else(|| Error::CFNotFound(name.to_string()))?;
+            db.compact_range_cf(handle, start, end);
+            log::info!("column family '{}' compacted", name);
+        }
+
+        Ok(MaintenanceReport { dbs })
+    }
+
+    /// The bindings can't flush a single column family, so whatever the dbs requested the memtables are
+    /// flushed once with `flush_memtables`.
+    fn flush(&self, cf: Option<&str>) -> Result<MaintenanceReport, Error> {
+        let db = self.db.read().unwrap();
+        let dbs = target_cfs(&db, cf)?;
+
+        flush_memtables(&db).map_err(Error::RocksDB)?;
+        log::info!("memtables flushed for {:?}", dbs);
+
+        Ok(MaintenanceReport { dbs })
+    }
+
+    /// Reads every record with checksum verification enabled, so corrupted blocks are reported.
+    fn verify(&self, cf: Option<&str>) -> Result<Vec<VerifyReport>, Error> {
+        let db = self.db.read().unwrap();
+
+        target_cfs(&db, cf)?.into_iter().map(|name| verify_cf(&db, name)).collect()
+    }
+
+    fn backup(&self, root: &Path, path: Option<&str>) -> Result<BackupInfo, Error> {
+        backup::create(root, self.db.clone(), path)
+    }
+
+    fn create_snapshot(&self, lease_secs: Option<u64>) -> Result<SnapshotLease, Error> {
+        // Snapshots would keep the dbs being copied or renamed from being created or dropped
+        let db = self.db.read().unwrap();
+        if let Some(busy) = self.busy.lock().unwrap().iter().next() {
+            return Err(Error::DbBusy(busy.clone()))
+        }
+
+        Ok(self.snapshots.create(&db, lease_secs))
+    }
+
+    fn release_snapshot(&self, id: &str) -> Result<(), Error> { self.snapshots.release(id) }
+
+    fn last_seq(&self) -> Result<u64, Error> { Ok(self.changes.as_ref().ok_or_else(no_change_log)?.last()) }
+
+    fn first_seq(&self) -> Result<u64, Error> { Ok(self.changes.as_ref().ok_or_else(no_change_log)?.first()) }
+
+    /// Changes not newer than the last one applied are skipped, so a follower can apply the same
+    /// changes again after a restart.
+    fn apply_changes(&self, changes: Vec<Change>) -> Result<(), Error> {
+        let log = self.changes.as_ref().ok_or_else(no_change_log)?;
+        let db_changes = changes.iter().any(|c| c.op != ChangeOp::Put && c.op != ChangeOp::Delete);
+        let mut inner = if db_changes { self.lock_for_cf_change()? } else { self.db.write().unwrap() };
+
+        let from = log.last();
+        let mut applied = from;
+        let mut batch = WriteBatch::default();
+        for change in changes.into_iter().filter(|c| c.seq > from) {
+            match change.op {
+                ChangeOp::Put | ChangeOp::Delete => {
+                    let cf = inner.cf_handle(&change.db).ok_or_else(|| Error::CFNotFound(change.db.clone()))?;
+                    match &change.value {
+                        Some(value) if change.op == ChangeOp::Put => batch.put_cf(cf, &change.key, value),
+                        _ => batch.delete_cf(cf, &change.key),
+                    }
+                    .map_err(Error::RocksDB)?;
+                }
+                _ => {
+                    inner.write(mem::take(&mut batch)).map_err(Error::RocksDB)?;
+                    apply_db_change(&mut inner, &change)?;
+                }
+            }
+
+            let changes_cf =
+                inner.cf_handle(CHANGES_CF).ok_or_else(|| Error::CannotRetrieveCF(CHANGES_CF.to_string()))?;
+            batch.put_cf(changes_cf, seq_key(change.seq), change.encode()).map_err(Error::RocksDB)?;
+            applied = change.seq;
+        }
 
-pub fn get_all_dbs() -> Result<Vec<String>, Error> {
-    DB::list_cf(&rocksdb::Options::default(), env::var("FEEDB_PATH").unwrap_or_else(|_| "/tmp/storage".to_string()))
-        .map_err(Error::RocksDB)
+        inner.write(batch).map_err(Error::RocksDB)?;
+        log.applied(applied);
+        self.trim_changes(&inner);
+
+        Ok(())
+    }
+
+    fn changes(
+        &self, cf: Option<&str>, since_seq: u64, limit: usize, wait: Option<Duration>,
+    ) -> Result<ChangesPage, Error> {
+        let log = self.changes.as_ref().ok_or_else(no_change_log)?;
+        let deadline = wait.map(|wait| Instant::now() + wait);
+
+        let mut since_seq = since_seq;
+        loop {
+            let page = read_changes(&self.db.read().unwrap(), cf, since_seq, log.last(), limit)?;
+
+            // Checked after reading, as the changes could have been trimmed meanwhile
+            let first = log.first();
+            if since_seq + 1 < first {
+                return Err(Error::ChangesTrimmed(since_seq, first))
+            }
+
+            if !page.changes.is_empty() {
+                return Ok(page)
+            }
+
+            // Only changes of other dbs may have been read, which don't need to be read again
+            since_seq = page.last_seq;
+            match deadline {
+                Some(deadline) if Instant::now() < deadline => {
+                    if !log.wait_after(since_seq, deadline - Instant::now()) {
+                        return Ok(page)
+                    }
+                }
+                _ => return Ok(page),
+            }
+        }
+    }
 }
 
-pub fn new_storage(path: String) -> DB {
+fn open(path: String, config: &RocksConfig) -> Result<DB, Error> {
     let mut opts = Options::default();
     opts.create_if_missing(true);
+    config.apply(&mut opts);
 
-    match DB::list_cf(&opts, path.clone()) {
-        Ok(cfs) =>
-            match DB::open_cf(&opts, path, cfs) {
-                Ok(db) => db,
-                Err(err) => panic!(err),
-            },
+    let cfs = match DB::list_cf(&opts, path.clone()) {
+        Ok(cfs) => cfs,
         Err(e) => {
             log::warn!("{}", e.to_string());
-            DB::open(&opts, path).unwrap()
+            return DB::open(&opts, path).map_err(Error::RocksDB)
+        }
+    };
+
+    // Options of each db are stored in the db itself, so it is opened once with default options
+    // to read them and then reopened applying them to every column family.
+    let db = DB::open_cf(&opts, path.clone(), cfs.clone()).map_err(Error::RocksDB)?;
+    let mut stored = get_all_db_options(&db);
+    drop(db);
+
+    let descriptors = cfs.into_iter().map(|name| {
+        let cf_opts = stored
+            .remove(&name)
+            .map(|o| {
+                o.to_rocksdb().unwrap_or_else(|err| {
+                    log::warn!("error applying stored options of db '{}', using defaults: {}", name, err);
+                    Options::default()
+                })
+            })
+            .unwrap_or_default();
+
+        ColumnFamilyDescriptor::new(name, cf_opts)
+    });
+
+    DB::open_cf_descriptors(&opts, path, descriptors).map_err(Error::RocksDB)
+}
+
+/// Creates `CHANGES_CF` if needed and reads the first and last sequence numbers written.
+fn open_change_log(db: &mut DB, retention: u64) -> Result<ChangeLog, Error> {
+    if db.cf_handle(CHANGES_CF).is_none() {
+        db.create_cf(CHANGES_CF, &Options::default()).map_err(Error::RocksDB)?;
+    }
+
+    let changes_cf = db.cf_handle(CHANGES_CF).ok_or_else(|| Error::CFNotFound(CHANGES_CF.to_string()))?;
+    let edge_seq = |mode: IteratorMode| -> Result<Option<u64>, Error> {
+        let mut iter = db.iterator_cf(changes_cf, mode).map_err(Error::RocksDB)?;
+        Ok(iter.next().and_then(|(k, _)| seq_from_key(&k)))
+    };
+    let last = edge_seq(IteratorMode::End)?.unwrap_or_default();
+    let first = edge_seq(IteratorMode::Start)?.unwrap_or(last + 1);
+
+    Ok(ChangeLog::new(first, last, retention))
+}
+
+fn no_change_log() -> Error { Error::Unsupported("change data capture without 'change_log' enabled".to_string()) }
+
+/// Changes added to the batch of a write, with sequence numbers reserved before building it. The
+/// changes become visible once it is dropped, which must be after the batch is written.
+struct LogWriter<'a> {
+    reservation: Reservation<'a>,
+}
+
+impl LogWriter<'a> {
+    /// Reserves the sequence numbers of `changes` changes.
+    fn new(log: &'a Option<ChangeLog>, changes: usize) -> Option<Self> {
+        log.as_ref().map(|log| LogWriter { reservation: log.reserve(changes) })
+    }
+
+    /// Adds the change to `batch` with the next sequence number.
+    fn add(
+        &mut self, db: &DB, batch: &mut WriteBatch, cf: &str, op: ChangeOp, key: &[u8], value: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        let changes_cf = db.cf_handle(CHANGES_CF).ok_or_else(|| Error::CannotRetrieveCF(CHANGES_CF.to_string()))?;
+        let seq = self.reservation.next();
+
+        batch.put_cf(changes_cf, seq_key(seq), encode(cf, op, key, value)).map_err(Error::RocksDB)
+    }
+}
+
+/// Applies a replicated change of a whole db. Changes already applied, like the creation of a db
+/// that exists, are ignored.
+fn apply_db_change(db: &mut DB, change: &Change) -> Result<(), Error> {
+    let name = change.db.as_str();
+
+    match change.op {
+        ChangeOp::CreateDb if db.cf_handle(name).is_none() => {
+            let opts = change.value.as_deref().map(serde_json::from_slice::<DbOptions>).transpose()?;
+            create(db, name, &opts.unwrap_or_default())
+        }
+        ChangeOp::DropDb if db.cf_handle(name).is_some() => {
+            db.drop_cf(name).map_err(|err| Error::CannotDropDb(name.to_string(), err.to_string()))?;
+            delete_db_options(db, name)
+        }
+        ChangeOp::TruncateDb if db.cf_handle(name).is_some() => {
+            let opts = get_db_options(db, name)?.unwrap_or_default();
+            db.drop_cf(name).map_err(|err| Error::CannotDropDb(name.to_string(), err.to_string()))?;
+            create(db, name, &opts)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Reads the changes after `since_seq` up to `last`, the last one visible.
+fn read_changes(db: &DB, cf: Option<&str>, since_seq: u64, last: u64, limit: usize) -> Result<ChangesPage, Error> {
+    if let Some(cf) = cf {
+        db.cf_handle(cf).ok_or_else(|| Error::CFNotFound(cf.to_string()))?;
+    }
+
+    let changes_cf = db.cf_handle(CHANGES_CF).ok_or_else(|| Error::CannotRetrieveCF(CHANGES_CF.to_string()))?;
+    let from = seq_key(since_seq + 1);
+    let iter = db.iterator_cf(changes_cf, IteratorMode::From(&from, Direction::Forward)).map_err(Error::RocksDB)?;
+
+    let mut page = ChangesPage { changes: Vec::new(), last_seq: since_seq };
+    for (k, v) in iter {
+        let seq = seq_from_key(&k).ok_or_else(|| Error::Db("wrong key in the change log".to_string()))?;
+        if seq > last {
+            break
+        }
+
+        let change = Change::decode(seq, &v)?;
+        page.last_seq = seq;
+
+        if cf.map(|cf| cf == change.db).unwrap_or(true) {
+            page.changes.push(change);
+            if page.changes.len() >= limit {
+                break
+            }
+        }
+    }
+
+    Ok(page)
+}
+
+const COPY_BATCH_SIZE: usize = 1000;
+
+/// Changes deleted at most after a write, so a retention lowered on a big log is applied little
+/// by little.
+const TRIM_BATCH_SIZE: u64 = 10_000;
+
+fn rename_marker(from: &str) -> String { format!("{}{}", RENAME_MARKER, from) }
+
+/// Renames in progress, from the source db to the target one.
+fn rename_markers(db: &DB) -> Vec<(String, String)> {
+    let iter = match db.iterator(IteratorMode::From(RENAME_MARKER.as_bytes(), Direction::Forward)) {
+        Ok(iter) => iter,
+        Err(err) => {
+            log::warn!("error reading the renames in progress: {}", err);
+            return Vec::new()
         }
+    };
+
+    iter.take_while(|(k, _)| k.starts_with(RENAME_MARKER.as_bytes()))
+        .map(|(k, v)| {
+            let from = String::from_utf8_lossy(&k[RENAME_MARKER.len()..]).to_string();
+            (from, String::from_utf8_lossy(&v).to_string())
+        })
+        .collect()
+}
+
+fn create(db: &mut DB, cf: &str, opts: &DbOptions) -> Result<(), Error> {
+    db.create_cf(cf, &opts.to_rocksdb()?).map_err(|err| Error::CannotCreateDb(cf.to_string(), err.to_string()))?;
+    put_db_options(db, cf, opts)
+}
+
+fn put_db_options(db: &mut DB, cf: &str, opts: &DbOptions) -> Result<(), Error> {
+    if db.cf_handle(OPTIONS_CF).is_none() {
+        db.create_cf(OPTIONS_CF, &Options::default())
+            .map_err(|err| Error::CannotCreateDb(OPTIONS_CF.to_string(), err.to_string()))?;
+    }
+
+    let options_cf = db.cf_handle(OPTIONS_CF).ok_or_else(|| Error::CannotRetrieveCF(OPTIONS_CF.to_string()))?;
+    let value = serde_json::to_vec(opts).map_err(Error::SerdeError)?;
+
+    db.put_cf(options_cf, cf, value).map_err(|err| Error::Put(err.to_string()))
+}
+
+fn get_db_options(db: &DB, cf: &str) -> Result<Option<DbOptions>, Error> {
+    let options_cf = match db.cf_handle(OPTIONS_CF) {
+        Some(options_cf) => options_cf,
+        None => return Ok(None),
+    };
+
+    match db.get_cf(options_cf, cf).map_err(Error::RocksDB)? {
+        Some(v) => Ok(Some(serde_json::from_slice(v.as_slice()).map_err(Error::SerdeError)?)),
+        None => Ok(None),
     }
 }
 
+fn delete_db_options(db: &DB, cf: &str) -> Result<(), Error> {
+    match db.cf_handle(OPTIONS_CF) {
+        Some(options_cf) => db.delete_cf(options_cf, cf).map_err(Error::RocksDB),
+        None => Ok(()),
+    }
+}
+
+fn get_all_db_options(db: &DB) -> HashMap<String, DbOptions> {
+    let options_cf = match db.cf_handle(OPTIONS_CF) {
+        Some(options_cf) => options_cf,
+        None => return HashMap::new(),
+    };
+
+    let iter = match db.iterator_cf(options_cf, IteratorMode::Start) {
+        Ok(iter) => iter,
+        Err(err) => {
+            log::warn!("error reading stored db options: {}", err);
+            return HashMap::new()
+        }
+    };
+
+    iter.filter_map(|(k, v)| {
+        let name = String::from_utf8(k.to_vec())
+            .map_err(|err| log::warn!("error trying to get db name from stored options: {}", err))
+            .ok()?;
+        let opts = serde_json::from_slice(&v)
+            .map_err(|err| log::warn!("error trying to parse stored options of db '{}': {}", name, err))
+            .ok()?;
+
+        Some((name, opts))
+    })
+    .collect()
+}
+
+fn cf_names(db: &DB) -> Result<Vec<String>, Error> {
+    DB::list_cf(&Options::default(), db.path()).map_err(Error::RocksDB)
+}
+
+fn target_cfs(db: &DB, cf: Option<&str>) -> Result<Vec<String>, Error> {
+    match cf {
+        Some(cf) => {
+            db.cf_handle(cf).ok_or_else(|| Error::CFNotFound(cf.to_string()))?;
+            Ok(vec![cf.to_string()])
+        }
+        None => cf_names(db),
+    }
+}
+
+/// Flushes the memtables, waiting for it to finish. Only the default column family is flushed, as
+/// the rocksdb bindings have no flush of a single column family.
+fn flush_memtables(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut opts = FlushOptions::default();
+    opts.set_wait(true);
+    db.flush_opt(&opts)
+}
+
+fn verify_cf(db: &DB, name: String) -> Result<VerifyReport, Error> {
+    let cf = db.cf_handle(&name).ok_or_else(|| Error::CFNotFound(name.clone()))?;
+
+    let mut read_opts = ReadOptions::default();
+    read_opts.set_verify_checksums(true);
+
+    let mut iter = db.raw_iterator_cf_opt(cf, &read_opts).map_err(Error::RocksDB)?;
+    iter.seek_to_first();
+
+    let mut keys_verified = 0;
+    while iter.valid() {
+        keys_verified += 1;
+        iter.next();
+    }
+
+    let error = iter.status().err().map(|err| err.to_string());
+    match &error {
+        Some(err) => log::error!("checksum verification of column family '{}' failed: {}", name, err),
+        None => log::info!("column family '{}' verified, {} keys read", name, keys_verified),
+    }
+
+    Ok(VerifyReport { name, keys_verified, error })
+}
+
+fn db_stats(db: &DB, cf_name: &str) -> Result<DbStats, Error> {
+    let cf = db.cf_handle(cf_name).ok_or_else(|| Error::CFNotFound(cf_name.to_string()))?;
+    let property = |name: &str| -> Result<u64, Error> {
+        Ok(db.property_int_value_cf(cf, name).map_err(Error::RocksDB)?.unwrap_or_default())
+    };
+    let edge_key = |mode: IteratorMode| -> Result<Option<String>, Error> {
+        let mut iter = db.iterator_cf(cf, mode).map_err(Error::RocksDB)?;
+        Ok(iter.next().map(|(k, _)| String::from_utf8_lossy(&k).to_string()))
+    };
+
+    Ok(DbStats {
+        name:                     cf_name.to_string(),
+        estimated_keys:           property(ESTIMATE_NUM_KEYS)?,
+        live_sst_size_bytes:      property(LIVE_SST_FILES_SIZE)?,
+        total_sst_size_bytes:     property(TOTAL_SST_FILES_SIZE)?,
+        memtable_size_bytes:      property(CUR_SIZE_ALL_MEM_TABLES)?,
+        pending_compaction_bytes: property(ESTIMATE_PENDING_COMPACTION_BYTES)?,
+        first_key:                edge_key(IteratorMode::Start)?,
+        last_key:                 edge_key(IteratorMode::End)?,
+    })
+}
+
 fn get_range_mode(is_reverse: bool, id: &Option<String>) -> rocksdb::IteratorMode {
     match id {
         Some(id) => IteratorMode::From(id.as_bytes(), if is_reverse { Direction::Reverse } else { Direction::Forward }),
@@ -123,3 +865,203 @@ fn get_range_mode(is_reverse: bool, id: &Option<String>) -> rocksdb::IteratorMod
             },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::mpsc, thread};
+
+    use crate::components::rocks::*;
+
+    fn temp_path() -> String {
+        std::env::temp_dir().join(format!("sledge_rocks_{}", uuid::Uuid::new_v4())).to_string_lossy().to_string()
+    }
+
+    fn keys(rocks: &Rocks, cf: &str) -> Vec<String> {
+        let pairs = rocks.range(cf, false, None, None, box |iter| iter.collect()).unwrap();
+        pairs.into_iter().map(|sp| String::from_utf8(sp.id).unwrap()).collect()
+    }
+
+    fn put(rocks: &Rocks, cf: &str, ids: &[&str]) {
+        for id in ids {
+            rocks.put(cf, id.as_bytes().to_vec(), b"{}".to_vec(), Durability::Wal).unwrap();
+        }
+    }
+
+    fn wal_size(path: &str) -> u64 {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map(|ext| ext == "log").unwrap_or_default())
+            .map(|path| fs::metadata(path).unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn test_durability() {
+        let path = temp_path();
+        let rocks = Rocks::new(path.clone(), &RocksConfig::default()).unwrap();
+        rocks.create_db("a", &DbOptions::default()).unwrap();
+
+        let wal = wal_size(&path);
+        rocks.put("a", b"1".to_vec(), b"{}".to_vec(), Durability::None).unwrap();
+        assert_eq!(wal_size(&path), wal);
+        rocks.put("a", b"2".to_vec(), b"{}".to_vec(), Durability::Wal).unwrap();
+        assert!(wal_size(&path) > wal);
+        assert_eq!(rocks.stats("a").unwrap().live_sst_size_bytes, 0);
+
+        let wal = wal_size(&path);
+        rocks.put("a", b"3".to_vec(), b"{}".to_vec(), Durability::Flush).unwrap();
+        assert!(wal_size(&path) > wal);
+        assert_eq!(keys(&rocks, "a"), vec!["1", "2", "3"]);
+
+        drop(rocks);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_db_management() {
+        let path = temp_path();
+        let rocks = Rocks::new(path.clone(), &RocksConfig::default()).unwrap();
+        rocks.create_db("a", &DbOptions::default()).unwrap();
+        put(&rocks, "a", &["1", "2", "3"]);
+
+        assert_eq!(rocks.copy_db("a", "b", &|sp| if sp.id == b"2" { None } else { Some(sp) }).unwrap(), 2);
+        assert_eq!(keys(&rocks, "b"), vec!["1", "3"]);
+        assert_eq!(keys(&rocks, "a"), vec!["1", "2", "3"]);
+        assert!(matches!(rocks.copy_db("a", "b", &Some), Err(Error::DbAlreadyExists(_))));
+        assert!(matches!(rocks.copy_db("nope", "c", &Some), Err(Error::CFNotFound(_))));
+
+        assert_eq!(rocks.rename_db("a", "c").unwrap(), 3);
+        assert_eq!(keys(&rocks, "c"), vec!["1", "2", "3"]);
+        assert!(!rocks.list_dbs().unwrap().contains(&"a".to_string()));
+        assert!(rename_markers(&rocks.db.read().unwrap()).is_empty());
+        put(&rocks, "c", &["4"]);
+
+        rocks.truncate_db("c").unwrap();
+        assert!(keys(&rocks, "c").is_empty());
+        assert!(get_db_options(&rocks.db.read().unwrap(), "c").unwrap().is_some());
+
+        rocks.drop_db("c").unwrap();
+        assert!(!rocks.list_dbs().unwrap().contains(&"c".to_string()));
+        assert!(get_db_options(&rocks.db.read().unwrap(), "c").unwrap().is_none());
+        assert!(rocks.drop_db("c").is_err());
+
+        // dbs being copied or renamed can't be changed and snapshots can't be taken meanwhile
+        rocks.busy.lock().unwrap().insert("b".to_string());
+        assert!(matches!(rocks.create_snapshot(None), Err(Error::DbBusy(_))));
+        let delete = vec![BatchOp::Delete(b"1".to_vec())];
+        assert!(matches!(rocks.batch("b", delete, Durability::Wal), Err(Error::DbBusy(_))));
+        assert!(matches!(rocks.drop_db("b"), Err(Error::DbBusy(_))));
+        assert!(matches!(rocks.rename_db("b", "d"), Err(Error::DbBusy(_))));
+
+        drop(rocks);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshots_block_db_changes() {
+        let path = temp_path();
+        let rocks = Rocks::new(path.clone(), &RocksConfig::default()).unwrap();
+        rocks.create_db("a", &DbOptions::default()).unwrap();
+        put(&rocks, "a", &["1"]);
+
+        let lease = rocks.create_snapshot(None).unwrap();
+        let alive = vec![lease.id.clone()];
+        assert!(matches!(rocks.drop_db("a"), Err(Error::SnapshotsAlive(ids)) if ids == alive));
+        assert!(matches!(rocks.create_db("b", &DbOptions::default()), Err(Error::SnapshotsAlive(ids)) if ids == alive));
+        assert!(matches!(rocks.rename_db("a", "b"), Err(Error::SnapshotsAlive(ids)) if ids == alive));
+        assert_eq!(rocks.get("a", "1", Some(&lease.id)).unwrap().id, b"1".to_vec());
+
+        rocks.release_snapshot(&lease.id).unwrap();
+        rocks.drop_db("a").unwrap();
+
+        // expired leases don't block them
+        rocks.create_snapshot(Some(0)).unwrap();
+        rocks.create_db("b", &DbOptions::default()).unwrap();
+
+        drop(rocks);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    fn change_log(retention: u64) -> RocksConfig {
+        RocksConfig { change_log: true, change_log_retention: retention, ..RocksConfig::default() }
+    }
+
+    fn seqs(page: ChangesPage) -> Vec<u64> { page.changes.into_iter().map(|c| c.seq).collect() }
+
+    #[test]
+    fn test_concurrent_writes() {
+        let path = temp_path();
+        let rocks = Arc::new(Rocks::new(path.clone(), &change_log(0)).unwrap());
+        rocks.create_db("a", &DbOptions::default()).unwrap();
+
+        // a slow writer holding the sequence number 2 doesn't block the next write
+        let slow = rocks.changes.as_ref().unwrap().reserve(1);
+        let (tx, rx) = mpsc::channel();
+        let writer = rocks.clone();
+        thread::spawn(move || {
+            put(&writer, "a", &["1"]);
+            tx.send(()).unwrap();
+        });
+        rx.recv_timeout(Duration::from_secs(5)).expect("the write waited for the slow writer");
+
+        // but it isn't visible until the slow writer is done, so followers don't skip the number 2
+        assert_eq!(rocks.last_seq().unwrap(), 1);
+        assert_eq!(seqs(rocks.changes(Some("a"), 0, 10, None).unwrap()), vec![1]);
+
+        drop(slow);
+        assert_eq!(rocks.last_seq().unwrap(), 3);
+        assert_eq!(seqs(rocks.changes(Some("a"), 0, 10, None).unwrap()), vec![1, 3]);
+
+        drop(rocks);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_change_log_retention() {
+        let path = temp_path();
+        let rocks = Rocks::new(path.clone(), &change_log(3)).unwrap();
+        rocks.create_db("a", &DbOptions::default()).unwrap();
+        put(&rocks, "a", &["1", "2", "3", "4"]);
+
+        assert_eq!(seqs(rocks.changes(None, 2, 10, None).unwrap()), vec![3, 4, 5]);
+        assert!(matches!(rocks.changes(None, 1, 10, None), Err(Error::ChangesTrimmed(1, 3))));
+        assert!(matches!(rocks.changes(None, 0, 10, None), Err(Error::ChangesTrimmed(0, 3))));
+
+        // the oldest change kept is read again when opening the log
+        drop(rocks);
+        let rocks = Rocks::new(path.clone(), &change_log(3)).unwrap();
+        assert_eq!(rocks.changes.as_ref().unwrap().first(), 3);
+        put(&rocks, "a", &["5"]);
+        assert_eq!(seqs(rocks.changes(None, 3, 10, None).unwrap()), vec![4, 5, 6]);
+
+        drop(rocks);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_rename() {
+        let path = temp_path();
+        let rocks = Rocks::new(path.clone(), &RocksConfig::default()).unwrap();
+        rocks.create_db("a", &DbOptions::default()).unwrap();
+        rocks.create_db("b", &DbOptions::default()).unwrap();
+        rocks.create_db("d", &DbOptions::default()).unwrap();
+        put(&rocks, "a", &["1"]);
+        put(&rocks, "b", &["1"]);
+
+        // the copy into "b" didn't finish and "x" had been dropped already after being copied into "d"
+        rocks.db.read().unwrap().put(rename_marker("a"), "b").unwrap();
+        rocks.db.read().unwrap().put(rename_marker("x"), "d").unwrap();
+        drop(rocks);
+
+        let rocks = Rocks::new(path.clone(), &RocksConfig::default()).unwrap();
+        let dbs = rocks.list_dbs().unwrap();
+        assert!(dbs.contains(&"a".to_string()) && dbs.contains(&"d".to_string()));
+        assert!(!dbs.contains(&"b".to_string()));
+        assert_eq!(keys(&rocks, "a"), vec!["1"]);
+        assert!(rename_markers(&rocks.db.read().unwrap()).is_empty());
+
+        drop(rocks);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
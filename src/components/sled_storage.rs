@@ -0,0 +1,214 @@
+use std::sync::{Arc, Mutex};
+
+use sled::{Batch, Db, IVec, Tree};
+
+use crate::components::{
+    db_options::DbOptions,
+    durability::Durability,
+    errors::Error,
+    simple_pair::SimplePair,
+    storage::{no_snapshot, BatchOp, IterFn, KvIter, Storage},
+};
+
+const DEFAULT_TREE: &[u8] = b"__sled__default";
+
+/// sled storage where every db is a tree. RocksDB options are ignored and `wal_sync` and `flush`
+/// durabilities flush the tree to disk before returning.
+pub struct Sled {
+    db: Db,
+}
+
+impl Sled {
+    pub fn new(path: String) -> Result<Self, Error> { Ok(Sled { db: sled::open(path).map_err(Error::Sled)? }) }
+
+    /// sled creates trees when they are opened, so it must be checked that the db exists first.
+    fn tree(&self, name: &str) -> Result<Tree, Error> {
+        if !self.exists(name) {
+            return Err(Error::CFNotFound(name.to_string()))
+        }
+
+        self.db.open_tree(name).map_err(Error::Sled)
+    }
+
+    fn exists(&self, name: &str) -> bool { self.db.tree_names().iter().any(|n| n.as_ref() == name.as_bytes()) }
+}
+
+impl Storage for Sled {
+    fn get(&self, db: &str, id: &str, snapshot: Option<&str>) -> Result<SimplePair, Error> {
+        no_snapshot(snapshot)?;
+
+        self.tree(db)?
+            .get(id)
+            .map_err(Error::Sled)?
+            .map(|v| SimplePair::new_str_vec(id, v.to_vec()))
+            .ok_or_else(|| Error::NotFound(id.to_string()))
+    }
+
+    fn batch(&self, db: &str, ops: Vec<BatchOp>, durability: Durability) -> Result<(), Error> {
+        let tree = self.tree(db).map_err(|_| Error::CannotRetrieveCF(db.to_string()))?;
+
+        let mut batch = Batch::default();
+        for op in ops {
+            match op {
+                BatchOp::Put(k, v) => batch.insert(k, v),
+                BatchOp::Delete(k) => batch.remove(k),
+            }
+        }
+
+        tree.apply_batch(batch).map_err(|err| Error::Put(err.to_string()))?;
+
+        match durability {
+            Durability::WalSync | Durability::Flush => {
+                tree.flush().map_err(|err| Error::Put(err.to_string()))?;
+            }
+            Durability::None | Durability::Wal => (),
+        }
+
+        Ok(())
+    }
+
+    fn range(
+        &self, db: &str, is_reverse: bool, id: Option<String>, snapshot: Option<&str>, f: IterFn,
+    ) -> Result<Vec<SimplePair>, Error> {
+        no_snapshot(snapshot)?;
+        let tree = self.tree(db)?;
+
+        match (id, is_reverse) {
+            (Some(id), false) => read_pairs(tree.range(id.into_bytes()..), f),
+            (Some(id), true) => read_pairs(tree.range(..=id.into_bytes()).rev(), f),
+            (None, false) => read_pairs(tree.iter(), f),
+            (None, true) => read_pairs(tree.iter().rev(), f),
+        }
+    }
+
+    fn range_prefix(
+        &self, db: &str, prefix: String, snapshot: Option<&str>, f: IterFn,
+    ) -> Result<Vec<SimplePair>, Error> {
+        no_snapshot(snapshot)?;
+        let tree = self.tree(db)?;
+
+        read_pairs(tree.scan_prefix(prefix), f)
+    }
+
+    fn create_db(&self, db: &str, _opts: &DbOptions) -> Result<(), Error> {
+        if self.exists(db) {
+            return Err(Error::DbAlreadyExists(db.to_string()))
+        }
+
+        self.db.open_tree(db).map_err(|err| Error::CannotCreateDb(db.to_string(), err.to_string()))?;
+        Ok(())
+    }
+
+    fn drop_db(&self, db: &str) -> Result<(), Error> {
+        match self.db.drop_tree(db.as_bytes()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::CannotDropDb(db.to_string(), "db not found".to_string())),
+            Err(err) => Err(Error::CannotDropDb(db.to_string(), err.to_string())),
+        }
+    }
+
+    fn truncate_db(&self, db: &str) -> Result<(), Error> { self.tree(db)?.clear().map_err(Error::Sled) }
+
+    fn copy_db(&self, from: &str, to: &str, f: &dyn Fn(SimplePair) -> Option<SimplePair>) -> Result<usize, Error> {
+        let source = self.tree(from)?;
+        if self.exists(to) {
+            return Err(Error::DbAlreadyExists(to.to_string()))
+        }
+
+        let target = self.db.open_tree(to).map_err(|err| Error::CannotCreateDb(to.to_string(), err.to_string()))?;
+
+        let mut total = 0;
+        for res in source.iter() {
+            let (k, v) = res.map_err(Error::Sled)?;
+            if let Some(sp) = f(SimplePair::new_vec(k.to_vec(), v.to_vec())) {
+                target.insert(sp.id, sp.value).map_err(|err| Error::Put(err.to_string()))?;
+                total += 1;
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn rename_db(&self, from: &str, to: &str) -> Result<usize, Error> {
+        let total = self.copy_db(from, to, &Some)?;
+        self.drop_db(from)?;
+
+        Ok(total)
+    }
+
+    fn list_dbs(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .db
+            .tree_names()
+            .into_iter()
+            .filter(|n| n.as_ref() != DEFAULT_TREE)
+            .map(|n| String::from_utf8_lossy(n.as_ref()).to_string())
+            .collect())
+    }
+}
+
+/// Hands the records of `iter` to `f`, stopping at the first error reading them, which is returned
+/// instead of the records read until then.
+fn read_pairs(
+    iter: impl Iterator<Item = sled::Result<(IVec, IVec)>> + Send + Sync, f: IterFn,
+) -> Result<Vec<SimplePair>, Error> {
+    let error = Arc::new(Mutex::new(None));
+    let slot = error.clone();
+
+    let pairs: KvIter = box iter.scan((), move |_, res| match res {
+        Ok((k, v)) => Some(SimplePair::new_vec(k.to_vec(), v.to_vec())),
+        Err(err) => {
+            *slot.lock().unwrap() = Some(err);
+            None
+        }
+    });
+    let pairs = f(pairs);
+
+    let error = error.lock().unwrap().take();
+    match error {
+        Some(err) => Err(Error::Sled(err)),
+        None => Ok(pairs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::components::{durability::Durability, sled_storage::*};
+
+    fn collect() -> IterFn { box |iter| iter.collect() }
+
+    #[test]
+    fn test_sled_storage() {
+        let path = std::env::temp_dir().join(format!("sledge_sled_{}", uuid::Uuid::new_v4()));
+        let storage = Sled::new(path.to_string_lossy().to_string()).unwrap();
+        storage.create_db("test_db", &DbOptions::default()).unwrap();
+
+        for k in &["a1", "a2", "b1", "b2"] {
+            storage.put("test_db", Vec::from(*k), Vec::from(r#"{"hello":"world"}"#), Durability::None).unwrap();
+        }
+
+        assert_eq!(storage.get("test_db", "a2", None).unwrap().id, Vec::from("a2"));
+        assert!(storage.get("test_db", "c1", None).is_err());
+        assert!(storage.put("unknown", Vec::from("a"), Vec::from("b"), Durability::None).is_err());
+
+        let res = storage.range_prefix("test_db", "a".to_string(), None, collect()).unwrap();
+        assert_eq!(res.iter().map(|sp| sp.id.clone()).collect::<Vec<_>>(), vec![Vec::from("a1"), Vec::from("a2")]);
+
+        let res = storage.range("test_db", true, Some("b1".to_string()), None, collect()).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].id, Vec::from("b1"));
+
+        assert_eq!(storage.copy_db("test_db", "copy_db", &Some).unwrap(), 4);
+        assert_eq!(storage.rename_db("copy_db", "renamed_db").unwrap(), 4);
+        storage.truncate_db("renamed_db").unwrap();
+        assert!(storage.range("renamed_db", false, None, None, collect()).unwrap().is_empty());
+
+        storage.drop_db("renamed_db").unwrap();
+        assert_eq!(storage.list_dbs().unwrap(), vec!["test_db".to_string()]);
+
+        drop(storage);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use rocksdb::{Snapshot, DB};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::components::errors::Error;
+
+pub const DEFAULT_LEASE_SECS: u64 = 60;
+/// Live snapshots block every change to the column families of the instance, so they can't be kept
+/// for long.
+pub const MAX_LEASE_SECS: u64 = 300;
+
+/// A RocksDB snapshot that outlives the request that created it. It keeps a reference to the db so
+/// the db can't be closed while the snapshot is alive.
+///
+/// The snapshot borrows the db, so the column families can't be changed, which needs `&mut DB`,
+/// until it is dropped. `alive` counts them for that.
+pub struct SnapshotRef {
+    // Declared before `_db` so it is released before the reference to the db is dropped.
+    snapshot: Snapshot<'static>,
+    _db:      Arc<RwLock<DB>>,
+    alive:    Arc<AtomicUsize>,
+}
+
+impl Drop for SnapshotRef {
+    fn drop(&mut self) { self.alive.fetch_sub(1, Ordering::SeqCst); }
+}
+
+impl Deref for SnapshotRef {
+    type Target = Snapshot<'static>;
+
+    fn deref(&self) -> &Self::Target { &self.snapshot }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotLease {
+    pub id:         String,
+    pub expires_at: String,
+}
+
+struct Lease {
+    snapshot: Arc<SnapshotRef>,
+    duration: Duration,
+    expires:  Instant,
+}
+
+/// Snapshots taken through the API, identified by id. Every read done with a snapshot renews its
+/// lease and snapshots whose lease expired are released on the next access to the registry.
+pub struct Snapshots {
+    db:    Arc<RwLock<DB>>,
+    inner: Mutex<HashMap<String, Lease>>,
+    alive: Arc<AtomicUsize>,
+}
+
+impl Snapshots {
+    pub fn new(db: Arc<RwLock<DB>>) -> Self {
+        Snapshots { db, inner: Mutex::new(HashMap::new()), alive: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    /// `db` is the read guard of the db of the registry. The snapshot is counted as alive before the
+    /// guard is released, so whoever takes the write lock next sees it.
+    pub fn create(&self, db: &DB, lease_secs: Option<u64>) -> SnapshotLease {
+        let duration = Duration::from_secs(lease_secs.unwrap_or(DEFAULT_LEASE_SECS).min(MAX_LEASE_SECS));
+
+        // SAFETY: the snapshot only borrows the db, which lives inside the `Arc` stored next to it in
+        // `SnapshotRef`, so the db is alive and in the same address until the snapshot is dropped.
+        // The db is never borrowed mutably meanwhile, as `alive` isn't zero.
+        let snapshot = unsafe { std::mem::transmute::<Snapshot<'_>, Snapshot<'static>>(db.snapshot()) };
+        self.alive.fetch_add(1, Ordering::SeqCst);
+
+        let id = Uuid::new_v4().to_string();
+        let snapshot = SnapshotRef { snapshot, _db: self.db.clone(), alive: self.alive.clone() };
+        let lease = Lease { snapshot: Arc::new(snapshot), duration, expires: Instant::now() + duration };
+
+        let mut inner = self.inner.lock().unwrap();
+        purge_expired(&mut inner);
+        inner.insert(id.clone(), lease);
+        log::debug!("snapshot '{}' created with a lease of {:?}", id, duration);
+
+        SnapshotLease { id, expires_at: expires_at(duration) }
+    }
+
+    /// Returns the snapshot with `id`, renewing its lease.
+    pub fn get(&self, id: &str) -> Result<Arc<SnapshotRef>, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        purge_expired(&mut inner);
+
+        let lease = inner.get_mut(id).ok_or_else(|| Error::SnapshotNotFound(id.to_string()))?;
+        lease.expires = Instant::now() + lease.duration;
+
+        Ok(lease.snapshot.clone())
+    }
+
+    /// Snapshots not dropped yet, after releasing the expired ones. Includes released snapshots
+    /// still used by a read.
+    pub fn alive(&self) -> usize {
+        purge_expired(&mut self.inner.lock().unwrap());
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Ids of the snapshots with a live lease.
+    pub fn ids(&self) -> Vec<String> {
+        let mut inner = self.inner.lock().unwrap();
+        purge_expired(&mut inner);
+        inner.keys().cloned().collect()
+    }
+
+    pub fn release(&self, id: &str) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        purge_expired(&mut inner);
+
+        inner.remove(id).ok_or_else(|| Error::SnapshotNotFound(id.to_string()))?;
+        log::debug!("snapshot '{}' released", id);
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Snapshots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Snapshots {{ leases: {} }}", self.inner.lock().unwrap().len())
+    }
+}
+
+fn purge_expired(leases: &mut HashMap<String, Lease>) {
+    let now = Instant::now();
+    leases.retain(|id, lease| {
+        let alive = lease.expires > now;
+        if !alive {
+            log::debug!("snapshot '{}' lease expired", id);
+        }
+        alive
+    });
+}
+
+fn expires_at(duration: Duration) -> String {
+    (Utc::now() + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero())).to_rfc3339()
+}
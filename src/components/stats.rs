@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+pub const ESTIMATE_NUM_KEYS: &str = "rocksdb.estimate-num-keys";
+pub const LIVE_SST_FILES_SIZE: &str = "rocksdb.live-sst-files-size";
+pub const TOTAL_SST_FILES_SIZE: &str = "rocksdb.total-sst-files-size";
+pub const CUR_SIZE_ALL_MEM_TABLES: &str = "rocksdb.cur-size-all-mem-tables";
+pub const ESTIMATE_PENDING_COMPACTION_BYTES: &str = "rocksdb.estimate-pending-compaction-bytes";
+
+/// Statistics of a single db built from RocksDB properties. All values are estimations.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DbStats {
+    pub name:                     String,
+    pub estimated_keys:           u64,
+    pub live_sst_size_bytes:      u64,
+    pub total_sst_size_bytes:     u64,
+    pub memtable_size_bytes:      u64,
+    pub pending_compaction_bytes: u64,
+    pub first_key:                Option<String>,
+    pub last_key:                 Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct InstanceStats {
+    pub path:                     String,
+    pub total_dbs:                usize,
+    pub estimated_keys:           u64,
+    pub live_sst_size_bytes:      u64,
+    pub total_sst_size_bytes:     u64,
+    pub memtable_size_bytes:      u64,
+    pub pending_compaction_bytes: u64,
+    pub dbs:                      Vec<DbStats>,
+}
+
+impl InstanceStats {
+    pub fn new(path: String, dbs: Vec<DbStats>) -> Self {
+        let mut stats = InstanceStats { path, total_dbs: dbs.len(), ..InstanceStats::default() };
+
+        for db in dbs.iter() {
+            stats.estimated_keys += db.estimated_keys;
+            stats.live_sst_size_bytes += db.live_sst_size_bytes;
+            stats.total_sst_size_bytes += db.total_sst_size_bytes;
+            stats.memtable_size_bytes += db.memtable_size_bytes;
+            stats.pending_compaction_bytes += db.pending_compaction_bytes;
+        }
+
+        stats.dbs = dbs;
+        stats
+    }
+}
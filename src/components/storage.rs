@@ -0,0 +1,165 @@
+use std::{path::Path, str::FromStr, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::components::{
+    backup::BackupInfo,
+    changes::{Change, ChangesPage},
+    config::RocksConfig,
+    db_options::DbOptions,
+    durability::Durability,
+    errors::Error,
+    maintenance::{MaintenanceReport, VerifyReport},
+    memory::Memory,
+    rocks::Rocks,
+    simple_pair::SimplePair,
+    sled_storage::Sled,
+    snapshots::SnapshotLease,
+    stats::{DbStats, InstanceStats},
+};
+
+pub type KvIter<'a> = Box<dyn Iterator<Item = SimplePair> + Send + Sync + 'a>;
+
+/// Consumes the records of a range, applying the filters of the request, while the storage keeps
+/// whatever it needs to iterate them alive.
+pub type IterFn = Box<dyn FnOnce(KvIter) -> Vec<SimplePair> + Send>;
+
+pub enum BatchOp {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Storage layer behind every handler. A "db" is a namespace of keys: a column family in RocksDB
+/// and a tree in sled. Dbs must be created with `create_db` before writing into them.
+///
+/// Operations that only make sense in RocksDB (snapshots, statistics, maintenance, backups and changes)
+/// return `Error::Unsupported` by default.
+pub trait Storage: Send + Sync {
+    fn get(&self, db: &str, id: &str, snapshot: Option<&str>) -> Result<SimplePair, Error>;
+
+    fn put(&self, db: &str, k: Vec<u8>, v: Vec<u8>, durability: Durability) -> Result<(), Error> {
+        self.batch(db, vec![BatchOp::Put(k, v)], durability)
+    }
+
+    fn delete(&self, db: &str, k: Vec<u8>, durability: Durability) -> Result<(), Error> {
+        self.batch(db, vec![BatchOp::Delete(k)], durability)
+    }
+
+    /// Applies every operation atomically.
+    fn batch(&self, db: &str, ops: Vec<BatchOp>, durability: Durability) -> Result<(), Error>;
+
+    /// Iterates `db` from `id` (or from one of the ends if `None`) in the requested direction.
+    fn range(
+        &self, db: &str, is_reverse: bool, id: Option<String>, snapshot: Option<&str>, f: IterFn,
+    ) -> Result<Vec<SimplePair>, Error>;
+
+    /// Iterates every key of `db` starting with `prefix`.
+    fn range_prefix(&self, db: &str, prefix: String, snapshot: Option<&str>, f: IterFn)
+        -> Result<Vec<SimplePair>, Error>;
+
+    fn create_db(&self, db: &str, opts: &DbOptions) -> Result<(), Error>;
+
+    fn drop_db(&self, db: &str) -> Result<(), Error>;
+
+    /// Removes every record of `db`, keeping its options.
+    fn truncate_db(&self, db: &str) -> Result<(), Error>;
+
+    /// Copies every record of `from` into a new db `to`, passing each of them through `f` first.
+    /// Records for which `f` returns `None` are not copied. Returns the number of records written.
+    fn copy_db(&self, from: &str, to: &str, f: &dyn Fn(SimplePair) -> Option<SimplePair>) -> Result<usize, Error>;
+
+    fn rename_db(&self, from: &str, to: &str) -> Result<usize, Error>;
+
+    fn list_dbs(&self) -> Result<Vec<String>, Error>;
+
+    fn stats(&self, _db: &str) -> Result<DbStats, Error> { Err(Error::Unsupported("stats".to_string())) }
+
+    fn instance_stats(&self) -> Result<InstanceStats, Error> { Err(Error::Unsupported("stats".to_string())) }
+
+    /// Compacts the keys between `start` and `end` of `db` or of every db if `db` is `None`.
+    fn compact(&self, _db: Option<&str>, _start: Option<&str>, _end: Option<&str>) -> Result<MaintenanceReport, Error> {
+        Err(Error::Unsupported("compaction".to_string()))
+    }
+
+    /// Flushes the memtables of `db` or of every db if `db` is `None`.
+    fn flush(&self, _db: Option<&str>) -> Result<MaintenanceReport, Error> {
+        Err(Error::Unsupported("flush".to_string()))
+    }
+
+    fn verify(&self, _db: Option<&str>) -> Result<Vec<VerifyReport>, Error> {
+        Err(Error::Unsupported("checksum verification".to_string()))
+    }
+
+    /// Creates a backup named `path` inside the backups folder `root`.
+    fn backup(&self, _root: &Path, _path: Option<&str>) -> Result<BackupInfo, Error> {
+        Err(Error::Unsupported("backups".to_string()))
+    }
+
+    fn create_snapshot(&self, _lease_secs: Option<u64>) -> Result<SnapshotLease, Error> {
+        Err(Error::Unsupported("snapshots".to_string()))
+    }
+
+    fn release_snapshot(&self, _id: &str) -> Result<(), Error> { Err(Error::Unsupported("snapshots".to_string())) }
+
+    /// Up to `limit` writes of `db`, or of every db if `None`, after `since_seq`. If there are none
+    /// it waits up to `wait` for new ones.
+    fn changes(
+        &self, _db: Option<&str>, _since_seq: u64, _limit: usize, _wait: Option<Duration>,
+    ) -> Result<ChangesPage, Error> {
+        Err(Error::Unsupported("change data capture".to_string()))
+    }
+
+    /// Sequence number of the last change written.
+    fn last_seq(&self) -> Result<u64, Error> { Err(Error::Unsupported("change data capture".to_string())) }
+
+    /// Sequence number of the oldest change kept, older ones were trimmed.
+    fn first_seq(&self) -> Result<u64, Error> { Err(Error::Unsupported("change data capture".to_string())) }
+
+    /// Applies changes read from the log of another storage, keeping their sequence numbers.
+    fn apply_changes(&self, _changes: Vec<Change>) -> Result<(), Error> {
+        Err(Error::Unsupported("replication".to_string()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Rocksdb,
+    Sled,
+    Memory,
+}
+
+impl Default for Backend {
+    fn default() -> Self { Backend::Rocksdb }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rocksdb" => Ok(Backend::Rocksdb),
+            "sled" => Ok(Backend::Sled),
+            "memory" => Ok(Backend::Memory),
+            s => Err(format!("unknown storage '{}', use one of rocksdb, sled or memory", s)),
+        }
+    }
+}
+
+/// For storages without snapshots support.
+pub(crate) fn no_snapshot(snapshot: Option<&str>) -> Result<(), Error> {
+    match snapshot {
+        Some(_) => Err(Error::Unsupported("snapshots".to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Opens the storage selected at startup. `path` is ignored by the in-memory storage and `rocksdb`
+/// only applies to RocksDB.
+pub fn new_storage(backend: Backend, path: String, rocksdb: &RocksConfig) -> Result<Arc<dyn Storage>, Error> {
+    match backend {
+        Backend::Rocksdb => Ok(Arc::new(Rocks::new(path, rocksdb)?)),
+        Backend::Sled => Ok(Arc::new(Sled::new(path)?)),
+        Backend::Memory => Ok(Arc::new(Memory::default())),
+    }
+}
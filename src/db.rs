@@ -0,0 +1,432 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use serde_json::Value;
+use sqlparser::{ast::Statement, dialect::GenericDialect, parser::Parser};
+use uuid::Uuid;
+
+use crate::{
+    channels::channel::{self, Channel, ChannelToParseJSON, ChannelVersion},
+    components::{
+        changes::{ChangesPage, CHANGES_CF},
+        config::{Config, RocksConfig, DEFAULT_BACKUP_PATH, DEFAULT_LIMIT},
+        db_options::{DbOptions, OPTIONS_CF},
+        durability::Durability,
+        errors::Error,
+        rocks::DEFAULT_CF,
+        simple_pair::SimplePair,
+        sql::{self, json_nested_value},
+        stats::InstanceStats,
+        storage::{new_storage, Backend, BatchOp, IterFn, Storage},
+    },
+    inputs::file::INPUTS_DB,
+    server::{filters::Filters, query::Query},
+};
+
+pub const CHANNELS_DB: &str = "_channel";
+/// Every saved definition of the channels, keyed by `{id}@{version}` with the version zero padded
+/// so they are sorted.
+pub const CHANNEL_VERSIONS_DB: &str = "_channel_versions";
+
+/// Dbs kept by sledge itself. They aren't listed and can't be written, dropped, truncated, renamed
+/// or copied through the `/_db` routes.
+const INTERNAL_DBS: &[&str] = &[DEFAULT_CF, CHANGES_CF, OPTIONS_CF, CHANNELS_DB, CHANNEL_VERSIONS_DB, INPUTS_DB];
+
+/// Time a `follow` read of the changes waits for new ones by default, and at most.
+const DEFAULT_WAIT_SECS: u64 = 30;
+const MAX_WAIT_SECS: u64 = 300;
+
+/// Entry point to use sledge as a library. It wraps a `Storage` with everything the HTTP server
+/// does on top of it: id generation, query options, channels and SQL.
+///
+/// Cloning a `Db` is cheap and every clone uses the same storage.
+#[derive(Clone)]
+pub struct Db {
+    storage:       Arc<dyn Storage>,
+    durability:    Durability,
+    default_limit: usize,
+    backup_path:   PathBuf,
+    /// Taken while saving a channel so two saves can't get the same version.
+    channels_lock: Arc<Mutex<()>>,
+}
+
+impl Db {
+    pub fn new(storage: Arc<dyn Storage>, durability: Durability) -> Self {
+        Db {
+            storage,
+            durability,
+            default_limit: DEFAULT_LIMIT,
+            backup_path: PathBuf::from(DEFAULT_BACKUP_PATH),
+            channels_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn open(backend: Backend, path: String) -> Result<Self, Error> {
+        Ok(Db::new(new_storage(backend, path, &RocksConfig::default())?, Durability::default()))
+    }
+
+    /// Opens the storage in `config` with its durability, default limit and backups folder.
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let storage = new_storage(config.storage, config.path.clone(), &config.rocksdb)?;
+        Ok(Db::new(storage, config.durability)
+            .with_default_limit(config.default_limit)
+            .with_backup_path(PathBuf::from(&config.backup_path)))
+    }
+
+    pub fn with_durability(self, durability: Durability) -> Self { Db { durability, ..self } }
+
+    /// Records returned by reads whose query doesn't set a `limit`.
+    pub fn with_default_limit(self, default_limit: usize) -> Self { Db { default_limit, ..self } }
+
+    /// Folder where backups are created, listed and restored from.
+    pub fn with_backup_path(self, backup_path: PathBuf) -> Self { Db { backup_path, ..self } }
+
+    pub fn backup_path(&self) -> &Path { &self.backup_path }
+
+    /// Durability of the writes whose query doesn't set one.
+    pub fn durability(&self) -> Durability { self.durability }
+
+    /// The underlying storage, for db management and admin operations.
+    pub fn storage(&self) -> &Arc<dyn Storage> { &self.storage }
+
+    /// Every db but the internal ones.
+    pub fn list_dbs(&self) -> Result<Vec<String>, Error> {
+        Ok(self.storage.list_dbs()?.into_iter().filter(|db| !is_internal(db)).collect())
+    }
+
+    /// Statistics of the instance, leaving the internal dbs out.
+    pub fn instance_stats(&self) -> Result<InstanceStats, Error> {
+        let stats = self.storage.instance_stats()?;
+        Ok(InstanceStats::new(stats.path, stats.dbs.into_iter().filter(|db| !is_internal(&db.name)).collect()))
+    }
+
+    /// Writes `value` into `db` after applying `query` and `ch` to it. The id is taken from the
+    /// `field_path` of the query if set, and from `id` otherwise, where `_auto` and `_auto_time`
+    /// generate a uuid and a timestamp. Returns the id written or `None` if the value was filtered
+    /// out. Internal dbs are rejected.
+    pub fn put(
+        &self, db: &str, id: Option<&str>, value: &[u8], query: Option<Query>, ch: Option<Channel>,
+    ) -> Result<Option<String>, Error> {
+        check_user_db(db)?;
+        let id = get_id(&query, id, Some(value))?;
+        let durability = query.as_ref().and_then(|q| q.durability).unwrap_or(self.durability);
+
+        let mut filters = Filters::new(query, ch, None, self.default_limit);
+        let sp = SimplePair::new_str_vec(&id, value.to_vec());
+
+        match filters.apply(vec![sp].into_iter()).next() {
+            Some(sp) => {
+                self.storage.put(db, sp.id, sp.value, durability)?;
+                Ok(Some(id))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Applies `ops` to `db` atomically with the durability of the `Db`. Internal dbs are rejected.
+    pub fn write_batch(&self, db: &str, ops: Vec<BatchOp>) -> Result<(), Error> {
+        check_user_db(db)?;
+        self.storage.batch(db, ops, self.durability)
+    }
+
+    /// Reads `id` from `db`. Returns `None` if the record was filtered out by `query` or `ch`.
+    pub fn get(
+        &self, db: &str, id: &str, query: Option<Query>, ch: Option<Channel>,
+    ) -> Result<Option<SimplePair>, Error> {
+        let sp = self.storage.get(db, id, snapshot(&query).as_deref())?;
+        Ok(Filters::new(query, ch, None, self.default_limit).apply(vec![sp].into_iter()).next())
+    }
+
+    /// Reads `db` from `id` (or from the first record if `None`) in the direction of the query.
+    pub fn range(
+        &self, db: &str, id: Option<&str>, query: Option<Query>, ch: Option<Channel>,
+    ) -> Result<Vec<SimplePair>, Error> {
+        let (reverse, snapshot) = (is_reverse(&query), snapshot(&query));
+        self.storage.range(db, reverse, id.map(String::from), snapshot.as_deref(), self.iter_filters(query, ch, None))
+    }
+
+    /// Reads every record of `db` whose id starts with `prefix`.
+    pub fn range_prefix(
+        &self, db: &str, prefix: &str, query: Option<Query>, ch: Option<Channel>,
+    ) -> Result<Vec<SimplePair>, Error> {
+        let snapshot = snapshot(&query);
+        self.storage.range_prefix(db, prefix.to_string(), snapshot.as_deref(), self.iter_filters(query, ch, None))
+    }
+
+    /// Runs a `SELECT` over the db in its `FROM`, returning the projected records.
+    pub fn sql(&self, sql: &str, query: Option<Query>, ch: Option<Channel>) -> Result<Vec<SimplePair>, Error> {
+        let ast = Parser::parse_sql(&GenericDialect {}, sql.to_string()).map_err(Error::SqlError)?;
+        let from = sql::utils::get_from(&ast).ok_or_else(|| Error::CFNotFound("".to_string()))?;
+
+        let (reverse, snapshot) = (is_reverse(&query), snapshot(&query));
+        self.storage.range(&from, reverse, None, snapshot.as_deref(), self.iter_filters(query, ch, Some(ast)))
+    }
+
+    /// Writes of `db` after the `since_seq` of the query. With `follow` it waits up to `wait_secs`
+    /// for new writes if there are none.
+    pub fn changes(&self, db: Option<&str>, query: &Option<Query>) -> Result<ChangesPage, Error> {
+        let since_seq = query.as_ref().and_then(|q| q.since_seq).unwrap_or_default();
+        let limit = query.as_ref().and_then(|q| q.limit).unwrap_or(self.default_limit);
+        let wait = query.as_ref().filter(|q| q.follow.unwrap_or_default()).map(|q| {
+            Duration::from_secs(q.wait_secs.unwrap_or(DEFAULT_WAIT_SECS).min(MAX_WAIT_SECS))
+        });
+
+        self.storage.changes(db, since_seq, limit, wait)
+    }
+
+    /// Reads the stored channel with `id`, or its version `n` with `id@n`.
+    pub fn channel(&self, id: &str, omit_errors: bool) -> Result<Channel, Error> {
+        Channel::new_vec(self.channel_definition(id)?, omit_errors)
+    }
+
+    /// Stores the channel `definition` as `id`, after checking that every mutator in it can be
+    /// created. Every save is kept as a new version that can be read with `id@{version}`, even
+    /// after the channel is deleted. Returns the version saved.
+    pub fn put_channel(&self, id: &str, definition: &[u8]) -> Result<u64, Error> {
+        check_channel_id(id)?;
+        Channel::new_strict(serde_json::from_slice(definition)?, false)?;
+        let mut definition: ChannelToParseJSON = serde_json::from_slice(definition)?;
+
+        let _guard = self.channels_lock.lock().unwrap();
+        for db in &[CHANNELS_DB, CHANNEL_VERSIONS_DB] {
+            match self.storage.create_db(db, &DbOptions::default()) {
+                Ok(()) | Err(Error::DbAlreadyExists(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut versions = self.channel_versions(id)?;
+        if versions.is_empty() {
+            // a channel stored before versioning becomes the version 1
+            if let Some(mut head) = self.unversioned_channel(id)? {
+                head.version = Some(1);
+                let key = version_key(id, 1).into_bytes();
+                self.storage.put(CHANNEL_VERSIONS_DB, key, serde_json::to_vec(&head)?, self.durability)?;
+                versions.push(head);
+            }
+        }
+
+        let version = versions.last().and_then(|v| v.version).unwrap_or_default() + 1;
+        definition.name = id.to_string();
+        definition.version = Some(version);
+        definition.saved_at = Some(Utc::now().to_rfc3339());
+
+        let value = serde_json::to_vec(&definition)?;
+        self.storage.put(CHANNEL_VERSIONS_DB, version_key(id, version).into_bytes(), value.clone(), self.durability)?;
+        self.storage.put(CHANNELS_DB, id.into(), value, self.durability)?;
+
+        Ok(version)
+    }
+
+    /// The stored definition of the channel `id`, or of its version `n` with `id@n`.
+    pub fn get_channel(&self, id: &str) -> Result<Value, Error> {
+        Ok(serde_json::from_slice(&self.channel_definition(id)?)?)
+    }
+
+    /// Deletes the channel `id`. Its versions are kept, so reads pinned to one of them still work.
+    pub fn delete_channel(&self, id: &str) -> Result<(), Error> {
+        check_channel_id(id)?;
+        self.channel_definition(id)?;
+        self.storage.delete(CHANNELS_DB, id.into(), self.durability)
+    }
+
+    /// Definitions of every stored channel, by id.
+    pub fn list_channels(&self) -> Result<Vec<Value>, Error> {
+        match self.storage.range(CHANNELS_DB, false, None, None, box |iter| iter.collect()) {
+            Ok(sps) => Ok(sps.into_iter().filter_map(|sp| serde_json::from_slice(&sp.value).ok()).collect()),
+            Err(Error::CFNotFound(_)) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Every version of the channel `id`, oldest first, with the changes from the previous one.
+    pub fn channel_history(&self, id: &str) -> Result<Vec<ChannelVersion>, Error> {
+        check_channel_id(id)?;
+        let mut versions = self.channel_versions(id)?;
+        if versions.is_empty() {
+            let mut head = self.unversioned_channel(id)?.ok_or_else(|| Error::ChannelNotFound(id.to_string()))?;
+            head.version = Some(1);
+            versions.push(head);
+        }
+
+        Ok(channel::history(versions))
+    }
+
+    fn channel_definition(&self, id: &str) -> Result<Vec<u8>, Error> {
+        let res = match id.find('@') {
+            Some(i) => {
+                match id[i + 1..].parse() {
+                    Ok(version) => self.storage.get(CHANNEL_VERSIONS_DB, &version_key(&id[..i], version), None),
+                    Err(_) => Err(Error::ChannelNotFound(id.to_string())),
+                }
+            }
+            None => self.storage.get(CHANNELS_DB, id, None),
+        };
+
+        match res {
+            Ok(sp) => Ok(sp.value),
+            Err(Error::NotFound(_)) | Err(Error::CFNotFound(_)) => Err(Error::ChannelNotFound(id.to_string())),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn channel_versions(&self, id: &str) -> Result<Vec<ChannelToParseJSON>, Error> {
+        let prefix = format!("{}@", id);
+        match self.storage.range_prefix(CHANNEL_VERSIONS_DB, prefix, None, box |iter| iter.collect()) {
+            Ok(sps) => sps.iter().map(|sp| serde_json::from_slice(&sp.value).map_err(Error::SerdeError)).collect(),
+            Err(Error::CFNotFound(_)) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The channel `id` if it was stored before versioning, so it has no version.
+    fn unversioned_channel(&self, id: &str) -> Result<Option<ChannelToParseJSON>, Error> {
+        match self.storage.get(CHANNELS_DB, id, None) {
+            Ok(sp) => {
+                let head: ChannelToParseJSON = serde_json::from_slice(&sp.value)?;
+                Ok(Some(head).filter(|head| head.version.is_none()))
+            }
+            Err(Error::NotFound(_)) | Err(Error::CFNotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Applies the stored channel with `channel_id` to `value` without writing anything.
+    pub fn apply_channel(&self, channel_id: &str, value: &[u8]) -> Result<Value, Error> {
+        let ch = self.channel(channel_id, false)?;
+        let res = ch.parse_and_modify(value).ok_or_else(|| Error::ChannelError(channel_id.to_string()))?;
+
+        serde_json::from_slice(res.as_slice()).map_err(Error::SerdeError)
+    }
+
+    fn iter_filters(&self, query: Option<Query>, ch: Option<Channel>, sql: Option<Vec<Statement>>) -> IterFn {
+        let default_limit = self.default_limit;
+        box move |iter| Filters::new(query, ch, sql, default_limit).apply(iter).collect()
+    }
+}
+
+pub fn is_internal(db: &str) -> bool { INTERNAL_DBS.contains(&db) }
+
+/// Fails for the internal dbs, which can only be changed by sledge itself.
+pub fn check_user_db(db: &str) -> Result<(), Error> {
+    if is_internal(db) {
+        return Err(Error::InternalDb(db.to_string()))
+    }
+
+    Ok(())
+}
+
+fn check_channel_id(id: &str) -> Result<(), Error> {
+    if id.is_empty() || id.starts_with('_') || id.contains('@') {
+        return Err(Error::InvalidChannelId(id.to_string()))
+    }
+
+    Ok(())
+}
+
+fn version_key(id: &str, version: u64) -> String { format!("{}@{:010}", id, version) }
+
+fn snapshot(query: &Option<Query>) -> Option<String> { query.as_ref().and_then(|q| q.snapshot.clone()) }
+
+fn is_reverse(query: &Option<Query>) -> bool { query.as_ref().and_then(|q| q.direction_reverse).unwrap_or_default() }
+
+pub(crate) fn get_id(query: &Option<Query>, path_id: Option<&str>, req: Option<&[u8]>) -> Result<String, Error> {
+    if let Some(q) = query {
+        if let (Some(id), Some(req)) = (q.field_path.as_ref(), req) {
+            let j: Value = serde_json::from_slice(req).map_err(Error::SerdeError)?;
+            let val: &Value = json_nested_value(id, &j);
+            return Ok(val.as_str().ok_or_else(|| Error::IdNotFoundInJSON(id.clone()))?.to_string())
+        }
+    }
+
+    let id = path_id.ok_or(Error::NoIdFoundOnRequest)?;
+    match id {
+        "_auto" => Ok(Uuid::new_v4().to_string()),
+        "_auto_time" => Ok(Utc::now().to_rfc3339()),
+        _ => Ok(id.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        channels::channel::MutatorChange,
+        components::{db_options::DbOptions, memory::Memory},
+        db::*,
+    };
+
+    #[test]
+    fn test_db_sql() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+
+        db.put("people", Some("1"), br#"{"name":"mario","age":35}"#, None, None).unwrap();
+        db.put("people", Some("2"), br#"{"name":"ula","age":31}"#, None, None).unwrap();
+
+        let res = db.sql("SELECT name FROM people WHERE age > 32", None, None).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(serde_json::from_slice::<Value>(&res[0].value).unwrap(), serde_json::json!({"name":"mario"}));
+    }
+
+    #[test]
+    fn test_channels() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        assert!(db.list_channels().unwrap().is_empty());
+
+        let invalid = br#"{"channel":[{"type":"remove","field":"a"},{"type":"append","field":"b"},{"type":"nope"}]}"#;
+        match db.put_channel("ch", invalid) {
+            Err(Error::InvalidChannel(errors)) => {
+                assert_eq!(errors.iter().map(|e| e.index).collect::<Vec<_>>(), vec![1, 2]);
+                assert_eq!(errors[0].type_.as_deref(), Some("append"));
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(matches!(db.put_channel("ch", br#"{"channel":[]}"#), Err(Error::InvalidChannel(_))));
+        assert!(matches!(db.get_channel("ch"), Err(Error::ChannelNotFound(_))));
+
+        let remove = serde_json::json!({"type":"remove","field":"a"});
+        assert_eq!(db.put_channel("ch", br#"{"channel":[{"type":"remove","field":"a"}]}"#).unwrap(), 1);
+        let definition = db.get_channel("ch").unwrap();
+        assert_eq!((&definition["name"], &definition["version"]), (&Value::from("ch"), &Value::from(1)));
+        assert_eq!(db.list_channels().unwrap(), vec![definition]);
+        assert_eq!(db.apply_channel("ch", br#"{"a":1,"b":2}"#).unwrap(), serde_json::json!({"b":2}));
+
+        // a new save is a new version and the previous one can still be pinned
+        let set = br#"{"channel":[{"type":"set","field":"c","value":3},{"type":"remove","field":"a"}]}"#;
+        assert_eq!(db.put_channel("ch", set).unwrap(), 2);
+        assert_eq!(db.apply_channel("ch", br#"{"a":1}"#).unwrap(), serde_json::json!({"c":3}));
+        assert_eq!(db.apply_channel("ch@1", br#"{"a":1,"b":2}"#).unwrap(), serde_json::json!({"b":2}));
+        assert!(matches!(db.channel("ch@3", false), Err(Error::ChannelNotFound(_))));
+        assert!(matches!(db.put_channel("ch@1", set), Err(Error::InvalidChannelId(_))));
+
+        // channels are only written through put_channel, so every write is a version
+        assert!(matches!(db.put(CHANNELS_DB, Some("ch"), set, None, None), Err(Error::InternalDb(_))));
+        let ops = vec![BatchOp::Put(b"ch".to_vec(), set.to_vec())];
+        assert!(matches!(db.write_batch(CHANNEL_VERSIONS_DB, ops), Err(Error::InternalDb(_))));
+
+        let history = db.channel_history("ch").unwrap();
+        assert_eq!(history.iter().map(|v| v.version).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(history[0].changes, vec![MutatorChange::Added { index: 0, mutator: remove }]);
+        assert_eq!(history[1].changes.len(), 1);
+
+        db.delete_channel("ch").unwrap();
+        assert!(matches!(db.delete_channel("ch"), Err(Error::ChannelNotFound(_))));
+        assert!(db.channel("ch@2", false).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_stored_channel() {
+        // channels stored before they were validated load without the mutators that can't be created
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db(CHANNELS_DB, &DbOptions::default()).unwrap();
+        let old = br#"{"name":"old","channel":[{"type":"remove","field":"a"},{"type":"nope"}]}"#;
+        db.storage().put(CHANNELS_DB, b"old".to_vec(), old.to_vec(), Durability::None).unwrap();
+
+        assert_eq!(db.channel("old", false).unwrap().channel.len(), 1);
+        assert_eq!(db.apply_channel("old", br#"{"a":1,"b":2}"#).unwrap(), serde_json::json!({"b":2}));
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channels::channel::Channel,
+    components::{
+        config::{FileInputConfig, StartAt},
+        db_options::DbOptions,
+        errors::Error,
+        storage::BatchOp,
+    },
+    db::Db,
+    inputs::{transform, BatchWriter, Ids},
+};
+
+/// Db with the read offsets of the file inputs, keyed by `{input}/{device}:{inode}`.
+pub const INPUTS_DB: &str = "_inputs";
+
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Device and inode of a file. Files are followed by id rather than by path, so a rotated file
+/// still matched by the patterns under its new name isn't read again from the start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    fn of(meta: &fs::Metadata) -> Self { FileId { dev: meta.dev(), ino: meta.ino() } }
+}
+
+/// Position after the last line read of a file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+struct Offset {
+    inode:  u64,
+    offset: u64,
+}
+
+/// A file being followed. The handle is kept when the file is renamed or removed, so the lines
+/// written to it before are read too.
+struct Tail {
+    /// Path the file was last seen at.
+    path:    PathBuf,
+    reader:  BufReader<File>,
+    offset:  Offset,
+    /// A line read without its newline yet.
+    partial: Vec<u8>,
+    /// The offset changed and wasn't saved yet.
+    dirty:   bool,
+}
+
+impl Tail {
+    fn open(path: PathBuf, saved: Option<Offset>, start_at: StartAt) -> io::Result<Self> {
+        let file = File::open(&path)?;
+        let meta = file.metadata()?;
+        let position = match saved {
+            Some(saved) if saved.inode == meta.ino() && saved.offset <= meta.len() => saved.offset,
+            Some(_) => 0,
+            None if start_at == StartAt::End => meta.len(),
+            None => 0,
+        };
+
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(position))?;
+        let offset = Offset { inode: meta.ino(), offset: position };
+
+        Ok(Tail { path, reader, offset, partial: Vec::new(), dirty: saved != Some(offset) })
+    }
+
+    fn rewind(&mut self) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.offset.offset = 0;
+        self.partial.clear();
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Reads complete lines into `lines` until it holds `limit` of them. Returns whether the end of
+    /// the file was reached.
+    fn read_lines(&mut self, lines: &mut Vec<String>, limit: usize) -> io::Result<bool> {
+        while lines.len() < limit {
+            let n = self.reader.read_until(b'\n', &mut self.partial)?;
+            if n == 0 || !self.partial.ends_with(b"\n") {
+                return Ok(true)
+            }
+
+            self.offset.offset += self.partial.len() as u64;
+            self.dirty = true;
+
+            let line = String::from_utf8_lossy(&self.partial);
+            let line = line.trim_end_matches(&['\n', '\r'][..]);
+            if !line.trim().is_empty() {
+                lines.push(line.to_string());
+            }
+            self.partial.clear();
+        }
+
+        Ok(false)
+    }
+}
+
+/// Writes the lines appended to the files of `paths` into a db in batches. The offsets are saved
+/// after a batch is written and a batch is retried until it is, so no line is lost on restarts but
+/// the last batch can be written twice.
+pub struct FileInput {
+    name:    String,
+    db:      Db,
+    config:  FileInputConfig,
+    channel: Option<Channel>,
+    tails:   HashMap<FileId, Tail>,
+    /// Files no longer followed whose offsets are deleted with the next save.
+    done:    Vec<FileId>,
+    writer:  BatchWriter,
+    ids:     Ids,
+    /// Every file was read to its end in the last step.
+    idle:    bool,
+}
+
+impl FileInput {
+    pub fn new(name: &str, db: Db, config: FileInputConfig) -> Result<Self, Error> {
+        for pattern in config.paths.iter() {
+            glob::Pattern::new(pattern)
+                .map_err(|err| Error::Config(format!("input '{}' path '{}': {}", name, pattern, err)))?;
+        }
+
+        match db.storage().create_db(INPUTS_DB, &DbOptions::default()) {
+            Ok(()) | Err(Error::DbAlreadyExists(_)) => {}
+            Err(err) => return Err(err),
+        }
+
+        let channel = match config.channel.as_ref() {
+            Some(id) => Some(db.channel(id, config.omit_errors)?),
+            None => None,
+        };
+
+        Ok(FileInput {
+            name: name.to_string(),
+            writer: BatchWriter::new(db.clone(), &config.db),
+            db,
+            config,
+            channel,
+            tails: HashMap::new(),
+            done: Vec::new(),
+            ids: Ids::default(),
+            idle: false,
+        })
+    }
+
+    pub fn run(mut self) {
+        log::info!("input '{}' following {:?} into '{}'", self.name, self.config.paths, self.config.db);
+
+        loop {
+            match self.step() {
+                Ok(_) if self.idle => thread::sleep(Duration::from_millis(self.config.poll_ms)),
+                Ok(_) => {}
+                Err(err) => {
+                    log::error!("input '{}': {}", self.name, err);
+                    thread::sleep(RETRY_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Reads a batch, unless the previous one failed to be written, writes it and saves the offsets
+    /// of the files read. Returns the number of records written.
+    pub fn step(&mut self) -> Result<usize, Error> {
+        if self.writer.is_empty() {
+            for line in self.read()? {
+                let value = match transform(&line, self.config.format, self.channel.as_ref()) {
+                    Some(value) => value,
+                    None => continue,
+                };
+
+                match self.ids.get(&None, &self.config.id, &value) {
+                    Ok(id) => self.writer.push(id, value),
+                    Err(err) => log::warn!("input '{}' skipping line: {}", self.name, err),
+                }
+            }
+        }
+
+        let written = self.writer.write()?;
+        self.save_offsets()?;
+
+        Ok(written)
+    }
+
+    /// Reads up to `batch_size` lines from the files matching the patterns and from the ones
+    /// followed before that were renamed out of them or removed, which are dropped once read to
+    /// their end. The files followed before are read first, so a rotated file is read to its end
+    /// before the new one.
+    fn read(&mut self) -> Result<Vec<String>, Error> {
+        let matching = self.matching_files();
+        let mut followed: Vec<(&PathBuf, FileId)> = self.tails.iter().map(|(id, tail)| (&tail.path, *id)).collect();
+        followed.sort();
+        let mut found: Vec<(&PathBuf, FileId)> = matching
+            .iter()
+            .filter(|(id, _)| !self.tails.contains_key(*id))
+            .map(|(id, (path, _))| (path, *id))
+            .collect();
+        found.sort();
+        let files: Vec<FileId> = followed.into_iter().chain(found).map(|(_, id)| id).collect();
+
+        let (mut lines, limit) = (Vec::new(), self.config.batch_size);
+        self.idle = true;
+
+        for id in files {
+            if lines.len() >= limit {
+                self.idle = false;
+                break
+            }
+
+            // path and length of the file, if it's still followed
+            let current = matching.get(&id);
+
+            if !self.tails.contains_key(&id) {
+                let path = current.map(|(path, _)| path.clone()).unwrap_or_default();
+                let saved = self.saved_offset(id)?;
+                match Tail::open(path.clone(), saved, self.config.start_at) {
+                    Ok(tail) => self.tails.insert(id, tail),
+                    Err(err) => {
+                        log::warn!("input '{}' can't open {}: {}", self.name, path.display(), err);
+                        continue
+                    }
+                };
+            }
+
+            let tail = self.tails.get_mut(&id).unwrap();
+            let read = match current {
+                Some((path, len)) if *len < tail.offset.offset => {
+                    log::info!("input '{}': {} was truncated", self.name, path.display());
+                    tail.rewind().and_then(|_| tail.read_lines(&mut lines, limit))
+                }
+                _ => tail.read_lines(&mut lines, limit),
+            };
+            if let Some((path, _)) = current {
+                tail.path = path.clone();
+            }
+
+            match (read, current) {
+                (Ok(false), _) => self.idle = false,
+                (Ok(true), None) => {
+                    log::info!("input '{}': {} is no longer followed", self.name, tail.path.display());
+                    self.tails.remove(&id);
+                    self.done.push(id);
+                }
+                (Ok(true), Some(_)) => {}
+                (Err(err), _) => {
+                    log::warn!("input '{}' can't read {}: {}", self.name, tail.path.display(), err);
+                    self.tails.remove(&id);
+                }
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Files matching the patterns with their first path and length. Hard links and files matched
+    /// under several names are only followed once.
+    fn matching_files(&self) -> HashMap<FileId, (PathBuf, u64)> {
+        let mut paths: Vec<PathBuf> = self
+            .config
+            .paths
+            .iter()
+            .filter_map(|pattern| glob::glob(pattern).ok())
+            .flat_map(|paths| paths.filter_map(Result::ok))
+            .collect();
+        paths.sort();
+
+        let mut files = HashMap::new();
+        for path in paths {
+            match fs::metadata(&path) {
+                Ok(meta) if meta.is_file() => {
+                    files.entry(FileId::of(&meta)).or_insert((path, meta.len()));
+                }
+                _ => {}
+            }
+        }
+
+        files
+    }
+
+    fn saved_offset(&self, id: FileId) -> Result<Option<Offset>, Error> {
+        match self.db.storage().get(INPUTS_DB, &offset_key(&self.name, id), None) {
+            Ok(sp) => Ok(Some(serde_json::from_slice(&sp.value)?)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn save_offsets(&mut self) -> Result<(), Error> {
+        let mut ops = Vec::new();
+        for (id, tail) in self.tails.iter().filter(|(_, tail)| tail.dirty) {
+            ops.push(BatchOp::Put(offset_key(&self.name, *id).into_bytes(), serde_json::to_vec(&tail.offset)?));
+        }
+        ops.extend(self.done.iter().map(|id| BatchOp::Delete(offset_key(&self.name, *id).into_bytes())));
+        if ops.is_empty() {
+            return Ok(())
+        }
+
+        self.db.storage().batch(INPUTS_DB, ops, self.db.durability())?;
+        self.tails.values_mut().for_each(|tail| tail.dirty = false);
+        self.done.clear();
+
+        Ok(())
+    }
+}
+
+fn offset_key(input: &str, id: FileId) -> String { format!("{}/{}:{}", input, id.dev, id.ino) }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write, path::Path};
+
+    use crate::inputs::{file::*, test_db};
+
+    fn append(path: &Path, data: &str) {
+        OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_file_input() {
+        let dir = std::env::temp_dir().join(format!("sledge_file_input_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("app.log");
+
+        let db = test_db(&["logs"]);
+        let config = FileInputConfig {
+            paths: vec![dir.join("*.log").to_string_lossy().to_string()],
+            db: "logs".to_string(),
+            id: "_auto".to_string(),
+            ..FileInputConfig::default()
+        };
+
+        append(&log, "one\ntwo\nthr");
+        let mut input = FileInput::new("app", db.clone(), config.clone()).unwrap();
+        assert_eq!(input.step().unwrap(), 2);
+        assert_eq!(input.step().unwrap(), 0);
+
+        append(&log, "ee\n");
+        assert_eq!(input.step().unwrap(), 1);
+
+        // a new input resumes from the saved offset
+        append(&log, "four\n");
+        let mut input = FileInput::new("app", db.clone(), config).unwrap();
+        assert_eq!(input.step().unwrap(), 1);
+
+        // rotation: the rest of the old file is read before the new one
+        append(&log, "five\n");
+        fs::rename(&log, dir.join("app.log.1")).unwrap();
+        append(&log, "six\n");
+        assert_eq!(input.step().unwrap(), 2);
+        assert_eq!(input.step().unwrap(), 0);
+
+        // truncation
+        fs::write(&log, "").unwrap();
+        assert_eq!(input.step().unwrap(), 0);
+        append(&log, "seven\n");
+        assert_eq!(input.step().unwrap(), 1);
+
+        let mut values: Vec<String> = db
+            .range("logs", None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|sp| serde_json::from_slice::<serde_json::Value>(&sp.value).unwrap()["message"].to_string())
+            .collect();
+        values.sort();
+        assert_eq!(values.len(), 7);
+        assert_eq!(values[0], r#""five""#);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn test_rotated_files_still_matched() {
+        let dir = std::env::temp_dir().join(format!("sledge_file_input_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (log, rotated) = (dir.join("app.log"), dir.join("app.log.1"));
+
+        let db = test_db(&["logs"]);
+        let config = FileInputConfig {
+            paths: vec![dir.join("*.log*").to_string_lossy().to_string()],
+            db: "logs".to_string(),
+            ..FileInputConfig::default()
+        };
+
+        append(&log, "one\ntwo\n");
+        let mut input = FileInput::new("app", db.clone(), config.clone()).unwrap();
+        assert_eq!(input.step().unwrap(), 2);
+
+        // the rotated file is followed under its new name, so only its new lines are read
+        append(&log, "three\n");
+        fs::rename(&log, &rotated).unwrap();
+        append(&log, "four\n");
+        assert_eq!(input.step().unwrap(), 2);
+        assert_eq!(input.step().unwrap(), 0);
+
+        // a new input resumes both files
+        append(&rotated, "five\n");
+        let mut input = FileInput::new("app", db.clone(), config).unwrap();
+        assert_eq!(input.step().unwrap(), 1);
+
+        // the lines read in the same step don't get the same _auto_time id
+        assert_eq!(db.range("logs", None, None, None).unwrap().len(), 5);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, CommitMode, Consumer},
+    Message,
+};
+
+use crate::{
+    channels::channel::Channel,
+    components::{config::KafkaInputConfig, errors::Error},
+    db::Db,
+    inputs::{BatchWriter, Ids},
+    server::query::Query,
+};
+
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Messages consumed by an input. Implemented by the Kafka consumer and by in-process sources in
+/// the tests.
+pub trait MessageSource {
+    /// Waits up to `timeout` for the next message. Messages without payload are returned empty.
+    fn recv(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Commits every message received so far.
+    fn commit(&mut self) -> Result<(), Error>;
+}
+
+pub struct KafkaSource {
+    consumer: BaseConsumer,
+}
+
+impl KafkaSource {
+    pub fn new(config: &KafkaInputConfig) -> Result<Self, Error> {
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", &config.brokers)
+            .set("group.id", &config.group_id)
+            .set("enable.auto.commit", "false");
+        if let Some(reset) = config.auto_offset_reset.as_ref() {
+            client.set("auto.offset.reset", reset);
+        }
+        for (name, value) in config.properties.iter() {
+            client.set(name, value);
+        }
+
+        let consumer: BaseConsumer = client.create().map_err(Error::KafkaError)?;
+        let topics: Vec<&str> = config.topics.iter().map(String::as_str).collect();
+        consumer.subscribe(&topics).map_err(Error::KafkaError)?;
+
+        Ok(KafkaSource { consumer })
+    }
+}
+
+impl MessageSource for KafkaSource {
+    fn recv(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, Error> {
+        match self.consumer.poll(timeout) {
+            Some(Ok(msg)) => Ok(Some(msg.payload().map(<[u8]>::to_vec).unwrap_or_default())),
+            Some(Err(err)) => Err(Error::KafkaError(err)),
+            None => Ok(None),
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        self.consumer.commit_consumer_state(CommitMode::Sync).map_err(Error::KafkaError)
+    }
+}
+
+/// Writes the messages of a `MessageSource` into a db in batches. A batch is retried until it's
+/// written, so offsets are never committed past a record that wasn't stored.
+pub struct KafkaInput<S: MessageSource> {
+    name:    String,
+    config:  KafkaInputConfig,
+    source:  S,
+    channel: Option<Channel>,
+    writer:  BatchWriter,
+    ids:     Ids,
+}
+
+impl<S: MessageSource> KafkaInput<S> {
+    pub fn new(name: &str, db: Db, config: KafkaInputConfig, source: S) -> Result<Self, Error> {
+        let channel = match config.channel.as_ref() {
+            Some(id) => Some(db.channel(id, config.omit_errors)?),
+            None => None,
+        };
+
+        let writer = BatchWriter::new(db, &config.db);
+        Ok(KafkaInput { name: name.to_string(), config, source, channel, writer, ids: Ids::default() })
+    }
+
+    pub fn run(mut self) {
+        log::info!("input '{}' writing topics {:?} into '{}'", self.name, self.config.topics, self.config.db);
+
+        loop {
+            if let Err(err) = self.step() {
+                log::error!("input '{}': {}", self.name, err);
+                thread::sleep(RETRY_BACKOFF);
+            }
+        }
+    }
+
+    /// Receives a batch, unless the previous one failed to be written, writes it and commits it.
+    /// Returns the number of records written. A failed commit is done with the next batch.
+    pub fn step(&mut self) -> Result<usize, Error> {
+        if self.writer.is_empty() {
+            self.receive()?;
+        }
+        if self.writer.is_empty() {
+            return Ok(0)
+        }
+
+        let written = self.writer.write()?;
+        self.source.commit()?;
+
+        Ok(written)
+    }
+
+    /// Fills the batch with up to `batch_size` records or the ones received before the batch
+    /// timeout. Messages that can't be transformed or have no id are logged and skipped.
+    fn receive(&mut self) -> Result<(), Error> {
+        let deadline = Instant::now() + Duration::from_millis(self.config.batch_timeout_ms);
+        let query = Some(Query { field_path: self.config.field_path.clone(), ..Query::default() });
+
+        while self.writer.len() < self.config.batch_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break
+            }
+
+            let msg = match self.source.recv(deadline - now)? {
+                Some(msg) if msg.is_empty() => continue,
+                Some(msg) => msg,
+                None => break,
+            };
+
+            let value = match self.channel.as_ref() {
+                Some(ch) => {
+                    match ch.parse_and_modify(&msg) {
+                        Some(value) => value,
+                        None => continue,
+                    }
+                }
+                None => msg,
+            };
+
+            match self.ids.get(&query, &self.config.id, &value) {
+                Ok(id) => self.writer.push(id, value),
+                Err(err) => log::warn!("input '{}' skipping message: {}", self.name, err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use crate::{
+        components::db_options::DbOptions,
+        inputs::{kafka::*, test_db},
+    };
+
+    #[derive(Default)]
+    struct VecSource {
+        messages:  VecDeque<Vec<u8>>,
+        received:  usize,
+        committed: usize,
+    }
+
+    impl MessageSource for VecSource {
+        fn recv(&mut self, _timeout: Duration) -> Result<Option<Vec<u8>>, Error> {
+            let msg = self.messages.pop_front();
+            self.received += msg.is_some() as usize;
+            Ok(msg)
+        }
+
+        fn commit(&mut self) -> Result<(), Error> {
+            self.committed = self.received;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_kafka_input() {
+        let db = test_db(&[]);
+        db.put_channel("up", br#"{"channel":[{"type":"uppercase","field":"name"}]}"#).unwrap();
+
+        let mut source = VecSource::default();
+        source.messages.push_back(br#"{"id":"1","name":"mario"}"#.to_vec());
+        source.messages.push_back(br#"{"name":"no id"}"#.to_vec());
+        source.messages.push_back(br#"{"id":"2","name":"ula"}"#.to_vec());
+
+        let config = KafkaInputConfig {
+            db: "people".to_string(),
+            channel: Some("up".to_string()),
+            field_path: Some("id".to_string()),
+            batch_size: 10,
+            ..KafkaInputConfig::default()
+        };
+        let mut input = KafkaInput::new("people", db.clone(), config, source).unwrap();
+
+        // the db doesn't exist yet, so nothing is committed and the batch is kept
+        assert!(input.step().is_err());
+        assert_eq!(input.source.committed, 0);
+
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        assert_eq!(input.step().unwrap(), 2);
+        assert_eq!(input.source.committed, 3);
+
+        let sp = db.get("people", "1", None, None).unwrap().unwrap();
+        assert_eq!(sp.value, br#"{"id":"1","name":"MARIO"}"#.to_vec());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    sync::mpsc::sync_channel,
+    thread::{self, JoinHandle},
+};
+
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::{
+    channels::channel::Channel,
+    components::{
+        config::{Config, InputConfig, LineFormat},
+        errors::Error,
+        storage::BatchOp,
+    },
+    db::{check_user_db, get_id, Db},
+    server::query::Query,
+};
+
+pub mod file;
+pub mod kafka;
+pub mod socket;
+pub mod syslog;
+
+use file::FileInput;
+use kafka::{KafkaInput, KafkaSource};
+use socket::SocketInput;
+
+/// Lines received by a socket input and not written yet.
+const SOCKET_BUFFER: usize = 10_000;
+
+/// Starts a thread for every input in `config`.
+pub fn start(config: &Config, db: Db) -> Result<Vec<JoinHandle<()>>, Error> {
+    config
+        .inputs
+        .iter()
+        .map(|(name, input)| {
+            let target = match input {
+                InputConfig::Kafka(input) => &input.db,
+                InputConfig::Socket(config) => &config.db,
+                InputConfig::File(config) => &config.db,
+            };
+            check_user_db(target).map_err(|err| Error::Config(format!("input '{}': {}", name, err)))?;
+
+            match input {
+                InputConfig::Kafka(input) => {
+                    let source = KafkaSource::new(input)
+                        .map_err(|err| Error::Config(format!("input '{}': {}", name, err)))?;
+                    let input = KafkaInput::new(name, db.clone(), input.clone(), source)?;
+                    Ok(thread::spawn(move || input.run()))
+                }
+                InputConfig::Socket(config) => {
+                    let (tx, rx) = sync_channel(SOCKET_BUFFER);
+                    let input = SocketInput::new(name, db.clone(), config.clone(), rx)?;
+                    socket::listen(name, config, tx)?;
+                    Ok(thread::spawn(move || input.run()))
+                }
+                InputConfig::File(config) => {
+                    let input = FileInput::new(name, db.clone(), config.clone())?;
+                    Ok(thread::spawn(move || input.run()))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Turns a line read by the socket and file inputs into the value written. Syslog messages become
+/// their fields and raw lines that aren't JSON objects are passed to the channel as they are, or
+/// stored as `{"message":"..."}` without one.
+pub(crate) fn transform(line: &str, format: LineFormat, channel: Option<&Channel>) -> Option<Vec<u8>> {
+    let as_message = || json!({ "message": line }).to_string().into_bytes();
+
+    let value = match format {
+        LineFormat::Syslog => syslog::parse(line).map(|v| v.to_string().into_bytes()).unwrap_or_else(as_message),
+        LineFormat::Raw => {
+            match serde_json::from_str::<Value>(line) {
+                Ok(Value::Object(_)) => line.as_bytes().to_vec(),
+                _ if channel.is_some() => line.as_bytes().to_vec(),
+                _ => as_message(),
+            }
+        }
+    };
+
+    match channel {
+        Some(ch) => ch.parse_and_modify(&value),
+        None => Some(value),
+    }
+}
+
+/// Records of an input waiting to be written into its db. They are only dropped once written, so
+/// a batch that fails is retried as it is instead of reading new records.
+pub(crate) struct BatchWriter {
+    db:      Db,
+    target:  String,
+    pending: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl BatchWriter {
+    pub(crate) fn new(db: Db, target: &str) -> Self {
+        BatchWriter { db, target: target.to_string(), pending: Vec::new() }
+    }
+
+    pub(crate) fn push(&mut self, id: String, value: Vec<u8>) { self.pending.push((id.into_bytes(), value)) }
+
+    pub(crate) fn len(&self) -> usize { self.pending.len() }
+
+    pub(crate) fn is_empty(&self) -> bool { self.pending.is_empty() }
+
+    /// Writes the pending records in a batch. Returns the number of records written.
+    pub(crate) fn write(&mut self) -> Result<usize, Error> {
+        if self.pending.is_empty() {
+            return Ok(0)
+        }
+
+        let ops = self.pending.iter().map(|(k, v)| BatchOp::Put(k.clone(), v.clone())).collect();
+        self.db.write_batch(&self.target, ops)?;
+
+        Ok(self.pending.drain(..).count())
+    }
+}
+
+/// Ids of the records of an input, taken like `get_id` does. `_auto_time` ids get a counter
+/// appended, as the records read within the same clock tick would get the same id otherwise and
+/// overwrite each other.
+#[derive(Default)]
+pub(crate) struct Ids {
+    count: u64,
+}
+
+impl Ids {
+    pub(crate) fn get(&mut self, query: &Option<Query>, id: &str, value: &[u8]) -> Result<String, Error> {
+        let from_field = query.as_ref().map_or(false, |q| q.field_path.is_some());
+        if id != "_auto_time" || from_field {
+            return get_id(query, Some(id), Some(value))
+        }
+
+        self.count += 1;
+        Ok(format!("{}-{:020}", Utc::now().to_rfc3339(), self.count))
+    }
+}
+
+/// In-memory db with the dbs `names`, for the tests of the inputs.
+#[cfg(test)]
+pub(crate) fn test_db(names: &[&str]) -> Db {
+    use std::sync::Arc;
+
+    use crate::components::{db_options::DbOptions, durability::Durability, memory::Memory};
+
+    let db = Db::new(Arc::new(Memory::default()), Durability::None);
+    for name in names {
+        db.storage().create_db(name, &DbOptions::default()).unwrap();
+    }
+
+    db
+}
@@ -0,0 +1,263 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc::{Receiver, RecvTimeoutError, SyncSender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    channels::channel::Channel,
+    components::{
+        config::{Protocol, SocketInputConfig},
+        errors::Error,
+    },
+    db::Db,
+    inputs::{transform, BatchWriter, Ids},
+};
+
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_DATAGRAM: usize = 64 * 1024;
+/// Longest line read from a TCP connection, which is closed when a line is longer.
+const MAX_LINE: usize = 1024 * 1024;
+
+/// Binds `config.listen` and starts the threads that receive lines into `lines`. The socket is
+/// bound before returning so a wrong address fails the startup.
+pub fn listen(name: &str, config: &SocketInputConfig, lines: SyncSender<String>) -> Result<JoinHandle<()>, Error> {
+    let bind_error =
+        |err: io::Error| Error::Config(format!("input '{}' can't listen on {}: {}", name, config.listen, err));
+    let name = name.to_string();
+
+    match config.protocol {
+        Protocol::Udp => {
+            let socket = UdpSocket::bind(&config.listen).map_err(bind_error)?;
+            Ok(thread::spawn(move || receive_datagrams(&name, socket, lines)))
+        }
+        Protocol::Tcp => {
+            let listener = TcpListener::bind(&config.listen).map_err(bind_error)?;
+            Ok(thread::spawn(move || accept(&name, listener, lines)))
+        }
+    }
+}
+
+/// Every datagram holds a message, or several separated by newlines.
+fn receive_datagrams(name: &str, socket: UdpSocket, lines: SyncSender<String>) {
+    let mut buf = vec![0; MAX_DATAGRAM];
+    loop {
+        let n = match socket.recv_from(&mut buf) {
+            Ok((n, _)) => n,
+            Err(err) => {
+                log::error!("input '{}': {}", name, err);
+                thread::sleep(RETRY_BACKOFF);
+                continue
+            }
+        };
+
+        for line in String::from_utf8_lossy(&buf[..n]).lines() {
+            if !line.trim().is_empty() && lines.send(line.to_string()).is_err() {
+                return
+            }
+        }
+    }
+}
+
+fn accept(name: &str, listener: TcpListener, lines: SyncSender<String>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let (name, lines) = (name.to_string(), lines.clone());
+                thread::spawn(move || receive_stream(&name, stream, lines));
+            }
+            Err(err) => log::error!("input '{}': {}", name, err),
+        }
+    }
+}
+
+/// Reads newline delimited messages until the connection is closed.
+fn receive_stream(name: &str, stream: TcpStream, lines: SyncSender<String>) {
+    let mut reader = BufReader::new(stream);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.by_ref().take(MAX_LINE as u64 + 1).read_until(b'\n', &mut buf) {
+            Ok(0) => return,
+            Ok(n) if n > MAX_LINE && !buf.ends_with(b"\n") => {
+                log::warn!("input '{}' closing connection: line longer than {} bytes", name, MAX_LINE);
+                return
+            }
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf);
+                let line = line.trim_end_matches(&['\n', '\r'][..]);
+                if !line.trim().is_empty() && lines.send(line.to_string()).is_err() {
+                    return
+                }
+            }
+            Err(err) => {
+                log::warn!("input '{}' closing connection: {}", name, err);
+                return
+            }
+        }
+    }
+}
+
+/// Writes the lines received by the listener threads into a db in batches. A batch is retried
+/// until it's written, while the listeners block once the lines channel is full.
+pub struct SocketInput {
+    name:    String,
+    config:  SocketInputConfig,
+    lines:   Receiver<String>,
+    channel: Option<Channel>,
+    writer:  BatchWriter,
+    ids:     Ids,
+}
+
+impl SocketInput {
+    pub fn new(name: &str, db: Db, config: SocketInputConfig, lines: Receiver<String>) -> Result<Self, Error> {
+        let channel = match config.channel.as_ref() {
+            Some(id) => Some(db.channel(id, config.omit_errors)?),
+            None => None,
+        };
+
+        let writer = BatchWriter::new(db, &config.db);
+        Ok(SocketInput { name: name.to_string(), config, lines, channel, writer, ids: Ids::default() })
+    }
+
+    pub fn run(mut self) {
+        log::info!("input '{}' writing lines from {} into '{}'", self.name, self.config.listen, self.config.db);
+
+        loop {
+            match self.step() {
+                Err(Error::Config(err)) => {
+                    log::error!("input '{}' stopped: {}", self.name, err);
+                    return
+                }
+                Err(err) => {
+                    log::error!("input '{}': {}", self.name, err);
+                    thread::sleep(RETRY_BACKOFF);
+                }
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Receives a batch, unless the previous one failed to be written, and writes it. Returns the
+    /// number of records written.
+    pub fn step(&mut self) -> Result<usize, Error> {
+        if self.writer.is_empty() {
+            self.receive()?;
+        }
+
+        self.writer.write()
+    }
+
+    /// Fills the batch with up to `batch_size` records or the ones received before the batch
+    /// timeout. Lines that can't be transformed are logged and skipped.
+    fn receive(&mut self) -> Result<(), Error> {
+        let deadline = Instant::now() + Duration::from_millis(self.config.batch_timeout_ms);
+
+        while self.writer.len() < self.config.batch_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break
+            }
+
+            let line = match self.lines.recv_timeout(deadline - now) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::Config(format!("the listener of {} is gone", self.config.listen)))
+                }
+            };
+
+            let value = match transform(&line, self.config.format, self.channel.as_ref()) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            match self.ids.get(&None, &self.config.id, &value) {
+                Ok(id) => self.writer.push(id, value),
+                Err(err) => log::warn!("input '{}' skipping line: {}", self.name, err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::mpsc::sync_channel};
+
+    use serde_json::{json, Value};
+
+    use crate::{
+        components::config::LineFormat,
+        inputs::{socket::*, test_db},
+    };
+
+    #[test]
+    fn test_socket_input() {
+        let db = test_db(&["logs"]);
+
+        let (tx, rx) = sync_channel(10);
+        let config = SocketInputConfig {
+            db: "logs".to_string(),
+            id: "_auto".to_string(),
+            format: LineFormat::Syslog,
+            batch_timeout_ms: 10,
+            ..SocketInputConfig::default()
+        };
+        let mut input = SocketInput::new("logs", db.clone(), config, rx).unwrap();
+
+        tx.send("<34>Oct 11 22:14:15 mymachine su: failed".to_string()).unwrap();
+        tx.send("not syslog".to_string()).unwrap();
+        assert_eq!(input.step().unwrap(), 2);
+
+        let mut values: Vec<Value> = db
+            .range("logs", None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|sp| serde_json::from_slice(&sp.value).unwrap())
+            .collect();
+        values.sort_by_key(|v| v["message"].as_str().unwrap().to_string());
+        assert_eq!(values[0]["app_name"], "su");
+        assert_eq!(values[1], json!({"message": "not syslog"}));
+
+        drop(tx);
+        assert!(input.step().is_err());
+    }
+
+    #[test]
+    fn test_auto_time_ids() {
+        let db = test_db(&["logs"]);
+
+        let (tx, rx) = sync_channel(100);
+        let config = SocketInputConfig { db: "logs".to_string(), batch_timeout_ms: 10, ..SocketInputConfig::default() };
+        let mut input = SocketInput::new("logs", db.clone(), config, rx).unwrap();
+
+        for i in 0..100 {
+            tx.send(format!(r#"{{"n":{}}}"#, i)).unwrap();
+        }
+        assert_eq!(input.step().unwrap(), 100);
+        assert_eq!(db.range("logs", None, None, None).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_long_lines_close_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let (tx, rx) = sync_channel(10);
+        let receiver = thread::spawn(move || receive_stream("logs", stream, tx));
+
+        let mut data = b"first\n".to_vec();
+        data.extend(vec![b'a'; MAX_LINE + 1]);
+        data.extend(b"\nlast\n");
+        // the connection can be closed before the whole line is sent
+        let _ = client.write_all(&data);
+        receiver.join().unwrap();
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec!["first".to_string()]);
+    }
+}
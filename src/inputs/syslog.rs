@@ -0,0 +1,141 @@
+use serde_json::{Map, Value};
+
+/// Parses an RFC 5424 or RFC 3164 message into its fields. Returns `None` if the line doesn't start
+/// with a priority. Whatever can't be parsed after the priority ends up in `message`.
+pub fn parse(line: &str) -> Option<Value> {
+    let (pri, rest) = priority(line)?;
+
+    let mut fields = Map::new();
+    fields.insert("facility".to_string(), Value::from(pri / 8));
+    fields.insert("severity".to_string(), Value::from(pri % 8));
+
+    if rest.starts_with("1 ") {
+        rfc5424(&rest[2..], &mut fields);
+    } else {
+        rfc3164(rest, &mut fields);
+    }
+
+    Some(Value::Object(fields))
+}
+
+fn priority(line: &str) -> Option<(u8, &str)> {
+    if !line.starts_with('<') {
+        return None
+    }
+
+    let end = line.find('>')?;
+    let pri: u8 = line.get(1..end)?.parse().ok()?;
+    if end > 4 || pri > 191 {
+        return None
+    }
+
+    Some((pri, &line[end + 1..]))
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`, where `-` is a missing value.
+fn rfc5424(mut rest: &str, fields: &mut Map<String, Value>) {
+    for name in &["timestamp", "hostname", "app_name", "proc_id", "msg_id"] {
+        let (value, tail) = split_word(rest);
+        insert(fields, name, value);
+        rest = tail;
+    }
+
+    let (sd, msg) = structured_data(rest);
+    insert(fields, "structured_data", sd);
+    insert(fields, "message", msg.trim_start_matches('\u{feff}'));
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`, where only the message is mandatory.
+fn rfc3164(mut rest: &str, fields: &mut Map<String, Value>) {
+    let b = rest.as_bytes();
+    if b.len() > 16 && b[3] == b' ' && b[6] == b' ' && b[9] == b':' && b[12] == b':' && b[15] == b' ' {
+        if let (Some(timestamp), Some(tail)) = (rest.get(..15), rest.get(16..)) {
+            let (hostname, tail) = split_word(tail);
+            insert(fields, "timestamp", timestamp);
+            insert(fields, "hostname", hostname);
+            rest = tail;
+        }
+    }
+
+    let (tag, msg) = split_word(rest);
+    if tag.ends_with(':') {
+        let tag = &tag[..tag.len() - 1];
+        match tag.find('[') {
+            Some(i) if tag.ends_with(']') => {
+                insert(fields, "app_name", &tag[..i]);
+                insert(fields, "proc_id", &tag[i + 1..tag.len() - 1]);
+            }
+            _ => insert(fields, "app_name", tag),
+        }
+        rest = msg;
+    }
+
+    insert(fields, "message", rest);
+}
+
+/// Splits the `[id key="value"]` elements, where values can hold escaped quotes and brackets, from
+/// the message.
+fn structured_data(data: &str) -> (&str, &str) {
+    if !data.starts_with('[') {
+        return split_word(data)
+    }
+
+    let (mut quoted, mut escaped) = (false, false);
+    for (i, c) in data.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ']' if !quoted && !data[i + 1..].starts_with('[') => {
+                let msg = &data[i + 1..];
+                return (&data[..=i], if msg.starts_with(' ') { &msg[1..] } else { msg })
+            }
+            _ => {}
+        }
+    }
+
+    (data, "")
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    match s.find(' ') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, ""),
+    }
+}
+
+fn insert(fields: &mut Map<String, Value>, name: &str, value: &str) {
+    if !value.is_empty() && value != "-" {
+        fields.insert(name.to_string(), Value::String(value.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::inputs::syslog::*;
+
+    #[test]
+    fn test_parse() {
+        let msg = "<34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed for lonvick on /dev/pts/8";
+        assert_eq!(
+            parse(msg).unwrap(),
+            json!({"facility": 4, "severity": 2, "timestamp": "Oct 11 22:14:15", "hostname": "mymachine",
+                   "app_name": "su", "proc_id": "230", "message": "'su root' failed for lonvick on /dev/pts/8"})
+        );
+
+        let msg = r#"<165>1 2003-10-11T22:14:15.003Z host evntslog - ID47 [a b="\"]"][c d="e"] An application event"#;
+        assert_eq!(
+            parse(msg).unwrap(),
+            json!({"facility": 20, "severity": 5, "timestamp": "2003-10-11T22:14:15.003Z", "hostname": "host",
+                   "app_name": "evntslog", "msg_id": "ID47", "structured_data": r#"[a b="\"]"][c d="e"]"#,
+                   "message": "An application event"})
+        );
+
+        let msg = json!({"facility": 1, "severity": 5, "message": "just a message"});
+        assert_eq!(parse("<13>just a message").unwrap(), msg);
+        assert!(parse("no priority").is_none());
+        assert!(parse("<999>too high").is_none());
+    }
+}
@@ -9,4 +9,12 @@ extern crate log;
 
 pub mod channels;
 pub mod components;
+pub mod db;
+pub mod inputs;
+pub mod replication;
 pub mod server;
+pub mod shell;
+pub mod sinks;
+pub mod transfer;
+
+pub use db::Db;
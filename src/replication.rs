@@ -0,0 +1,312 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use http::{Method, Request, Response, StatusCode};
+use hyper::{body::HttpBody, client::HttpConnector, Body, Client};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    components::{
+        backup::{BackupFile, BackupInfo},
+        changes::decode_frames,
+        errors::Error,
+    },
+    db::Db,
+    server::reply::Reply,
+};
+
+/// Changes requested to the leader at once.
+const BATCH_SIZE: usize = 1000;
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Range of sequence numbers kept in the change log of a leader.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ReplicationStatus {
+    pub first_seq: u64,
+    pub last_seq:  u64,
+}
+
+/// Copies a backup of the leader into `path`, unless it already has data from a previous run. The
+/// backup is downloaded into a temporary folder first, so a failed bootstrap can be retried.
+pub async fn bootstrap(leader: &str, path: &str) -> Result<(), Error> {
+    let target = Path::new(path);
+    if fs::read_dir(target).map(|mut entries| entries.next().is_some()).unwrap_or_default() {
+        log::info!("resuming replication of '{}' into '{}'", leader, path);
+        return Ok(())
+    }
+
+    let leader = Leader::new(leader);
+    let name = format!("_replica_{}", Uuid::new_v4());
+    let info: BackupInfo = leader.data(Method::POST, &format!("/_admin/_backup?path={}", name)).await?;
+    log::info!("downloading backup '{}' of '{}' ({} bytes)", info.name, leader.base, info.size_bytes);
+
+    let tmp = PathBuf::from(format!("{}.bootstrap", path));
+    let res = leader.download(&name, &tmp).await;
+
+    if let Err(err) = leader.request(Method::DELETE, &format!("/_admin/_backups/{}", name)).await {
+        log::warn!("error deleting backup '{}' from the leader: {}", name, err);
+    }
+    res?;
+
+    if target.exists() {
+        fs::remove_dir(target).map_err(replication_error)?;
+    }
+    fs::rename(&tmp, target).map_err(replication_error)?;
+    log::info!("bootstrapped '{}' from '{}'", path, leader.base);
+
+    Ok(())
+}
+
+/// Checks that `leader` can be followed by `db`. Fails when the leader has no change log or `db`
+/// applied changes the leader doesn't have, and returns `false` when the changes after the last
+/// one applied were trimmed from the leader's log, so `db` has to be bootstrapped again.
+pub async fn handshake(db: &Db, leader: &str) -> Result<bool, Error> {
+    let leader = Leader::new(leader);
+    let status: ReplicationStatus = leader.data(Method::GET, "/_admin/_replication/_status").await?;
+    let since_seq = db.storage().last_seq()?;
+
+    if since_seq > status.last_seq {
+        return Err(Error::Replication(format!(
+            "applied changes up to {} but the log of '{}' ends at {}",
+            since_seq, leader.base, status.last_seq
+        )))
+    }
+
+    Ok(since_seq + 1 >= status.first_seq)
+}
+
+/// Moves the data folder of a follower that can no longer follow its leader out of the way, to
+/// `{path}.stale`, so the next bootstrap starts from an empty folder.
+pub fn discard(path: &str) -> Result<(), Error> {
+    let stale = PathBuf::from(format!("{}.stale", path));
+    if stale.exists() {
+        fs::remove_dir_all(&stale).map_err(replication_error)?;
+    }
+    fs::rename(path, &stale).map_err(replication_error)?;
+    log::warn!("moved '{}' to '{}'", path, stale.display());
+
+    Ok(())
+}
+
+/// Applies the changes of the leader as they happen, starting after the last one applied. Errors
+/// are logged and the request retried, until the leader replies that the changes needed were
+/// trimmed from its log: that error is returned, as the follower has to be bootstrapped again.
+pub async fn follow(db: Db, leader: String) -> Error {
+    let leader = Leader::new(&leader);
+
+    loop {
+        match follow_step(&db, &leader).await {
+            Err(err @ Error::ChangesTrimmed(..)) => return err,
+            Err(err) => {
+                log::error!("error replicating '{}': {}", leader.base, err);
+                tokio::time::delay_for(RETRY_BACKOFF).await;
+            }
+            Ok(()) => (),
+        }
+    }
+}
+
+async fn follow_step(db: &Db, leader: &Leader) -> Result<(), Error> {
+    let since_seq = db.storage().last_seq()?;
+    let path = format!("/_admin/_replication?since_seq={}&follow=true&limit={}", since_seq, BATCH_SIZE);
+
+    let res = leader.send(Method::GET, &path).await?;
+    if res.status() == StatusCode::GONE {
+        let status: ReplicationStatus = leader.data(Method::GET, "/_admin/_replication/_status").await?;
+        return Err(Error::ChangesTrimmed(since_seq, status.first_seq))
+    }
+
+    let res = leader.check(res, &path).await?;
+    let body = hyper::body::to_bytes(res.into_body()).await.map_err(replication_error)?;
+    let changes = decode_frames(&body)?;
+    if changes.is_empty() {
+        return Ok(())
+    }
+
+    let last_seq = changes.last().map(|c| c.seq).unwrap_or_default();
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || db.storage().apply_changes(changes)).await??;
+    log::debug!("replicated up to sequence number {}", last_seq);
+
+    Ok(())
+}
+
+struct Leader {
+    client: Client<HttpConnector>,
+    base:   String,
+}
+
+impl Leader {
+    fn new(base: &str) -> Self { Leader { client: Client::new(), base: base.trim_end_matches('/').to_string() } }
+
+    /// Sends a request, turning error replies into errors.
+    async fn request(&self, method: Method, path: &str) -> Result<Response<Body>, Error> {
+        let res = self.send(method, path).await?;
+        self.check(res, path).await
+    }
+
+    async fn send(&self, method: Method, path: &str) -> Result<Response<Body>, Error> {
+        let req = Request::builder().method(method).uri(format!("{}{}", self.base, path)).body(Body::empty())?;
+        self.client.request(req).await.map_err(replication_error)
+    }
+
+    async fn check(&self, res: Response<Body>, path: &str) -> Result<Response<Body>, Error> {
+        if res.status().is_success() {
+            return Ok(res)
+        }
+
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.map_err(replication_error)?;
+        let cause = serde_json::from_slice::<Reply>(&body).ok().and_then(|r| r.cause).unwrap_or_default();
+
+        Err(Error::Replication(format!("'{}' replied {}: {}", path, status, cause)))
+    }
+
+    async fn data<T: DeserializeOwned>(&self, method: Method, path: &str) -> Result<T, Error> {
+        let res = self.request(method, path).await?;
+        let body = hyper::body::to_bytes(res.into_body()).await.map_err(replication_error)?;
+        let reply: Reply = serde_json::from_slice(&body)?;
+
+        Ok(serde_json::from_value(reply.data.map(|d| *d).unwrap_or(Value::Null))?)
+    }
+
+    async fn download(&self, name: &str, target: &Path) -> Result<(), Error> {
+        if target.exists() {
+            fs::remove_dir_all(target).map_err(replication_error)?;
+        }
+        fs::create_dir_all(target).map_err(replication_error)?;
+
+        let files: Vec<BackupFile> = self.data(Method::GET, &format!("/_admin/_backups/{}", name)).await?;
+        for file in files {
+            let mut res = self.request(Method::GET, &format!("/_admin/_backups/{}/{}", name, file.name)).await?;
+            let mut out = File::create(target.join(&file.name)).map_err(replication_error)?;
+
+            while let Some(chunk) = res.body_mut().data().await {
+                out.write_all(&chunk.map_err(replication_error)?).map_err(replication_error)?;
+            }
+            out.sync_all().map_err(replication_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn replication_error(err: impl ToString) -> Error { Error::Replication(err.to_string()) }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hyper::{service::make_service_fn, Server};
+    use tokio::runtime::Runtime;
+
+    use crate::{
+        components::{
+            config::{Config, RocksConfig},
+            db_options::DbOptions,
+            durability::Durability,
+            memory::Memory,
+            rocks::Rocks,
+        },
+        replication::*,
+        server::service::Svc,
+    };
+
+    fn temp_path() -> String {
+        std::env::temp_dir().join(format!("sledge_rocks_{}", Uuid::new_v4())).to_string_lossy().to_string()
+    }
+
+    fn rocks(path: &str, retention: u64) -> Db {
+        let config = RocksConfig { change_log: true, change_log_retention: retention, ..RocksConfig::default() };
+        Db::new(Arc::new(Rocks::new(path.to_string(), &config).unwrap()), Durability::None)
+    }
+
+    /// Serves `db` on a local port, returning its url.
+    fn serve(rt: &mut Runtime, db: Db) -> String {
+        let svc = Svc::new(db, &Config::default()).unwrap();
+        let make_svc = make_service_fn(move |_| {
+            let svc = svc.clone();
+            async move { Ok::<_, hyper::Error>(svc) }
+        });
+
+        let server = rt.enter(|| Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc));
+        let url = format!("http://{}", server.local_addr());
+        rt.spawn(server);
+
+        url
+    }
+
+    fn put(db: &Db, ids: &[&str], n: u64) {
+        for id in ids {
+            db.put("people", Some(*id), format!(r#"{{"n":{}}}"#, n).as_bytes(), None, None).unwrap();
+        }
+    }
+
+    fn value(db: &Db, id: &str) -> Option<String> {
+        db.get("people", id, None, None).unwrap().map(|sp| String::from_utf8(sp.value).unwrap())
+    }
+
+    #[test]
+    fn test_follow() {
+        let mut rt = Runtime::new().unwrap();
+        let (leader_path, follower_path) = (temp_path(), temp_path());
+        let leader = rocks(&leader_path, 0);
+        leader.storage().create_db("people", &DbOptions::default()).unwrap();
+        put(&leader, &["a", "b"], 1);
+        let url = serve(&mut rt, leader.clone());
+
+        let follower = rocks(&follower_path, 0);
+        assert!(rt.block_on(handshake(&follower, &url)).unwrap());
+        let remote = Leader::new(&url);
+        rt.block_on(follow_step(&follower, &remote)).unwrap();
+        assert_eq!(value(&follower, "a"), Some(r#"{"n":1}"#.to_string()));
+        assert_eq!(value(&follower, "b"), Some(r#"{"n":1}"#.to_string()));
+
+        put(&leader, &["b", "c"], 2);
+        rt.block_on(follow_step(&follower, &remote)).unwrap();
+        assert_eq!(value(&follower, "b"), Some(r#"{"n":2}"#.to_string()));
+        assert_eq!(value(&follower, "c"), Some(r#"{"n":2}"#.to_string()));
+        assert_eq!(follower.storage().last_seq().unwrap(), leader.storage().last_seq().unwrap());
+
+        drop((leader, follower, rt));
+        fs::remove_dir_all(leader_path).unwrap();
+        fs::remove_dir_all(follower_path).unwrap();
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut rt = Runtime::new().unwrap();
+        let url = serve(&mut rt, Db::new(Arc::new(Memory::default()), Durability::None));
+        let (follower_path, leader_path) = (temp_path(), temp_path());
+        let follower = rocks(&follower_path, 0);
+
+        // the leader has no change log
+        let err = rt.block_on(handshake(&follower, &url)).unwrap_err();
+        assert_eq!(err.code(), "replication_error");
+
+        // the changes after the follower's last one were trimmed from the leader's log
+        let leader = rocks(&leader_path, 2);
+        leader.storage().create_db("people", &DbOptions::default()).unwrap();
+        put(&leader, &["a", "b", "c", "d"], 1);
+        let url = serve(&mut rt, leader.clone());
+        assert!(!rt.block_on(handshake(&follower, &url)).unwrap());
+        let err = rt.block_on(follow_step(&follower, &Leader::new(&url))).unwrap_err();
+        assert_eq!(err.code(), "changes_trimmed");
+
+        // the follower applied changes the leader doesn't have
+        follower.storage().create_db("people", &DbOptions::default()).unwrap();
+        put(&follower, &["a", "b", "c", "d", "e"], 1);
+        let err = rt.block_on(handshake(&follower, &url)).unwrap_err();
+        assert_eq!(err.code(), "replication_error");
+
+        drop((leader, follower, rt));
+        fs::remove_dir_all(leader_path).unwrap();
+        fs::remove_dir_all(follower_path).unwrap();
+    }
+}
@@ -21,7 +21,7 @@ pub struct Filters {
 }
 
 impl Filters {
-    pub fn new(query: Option<Query>, ch: Option<Channel>, sql: Option<Vec<Statement>>) -> Self {
+    pub fn new(query: Option<Query>, ch: Option<Channel>, sql: Option<Vec<Statement>>, default_limit: usize) -> Self {
         let mut itermods: Vec<Filter> = Vec::new();
 
         if let Some(sql_) = sql {
@@ -40,7 +40,7 @@ impl Filters {
 
         // Limit is always used
         itermods.push(Filter::Limit(
-            query.as_ref().and_then(|q| q.limit).unwrap_or(1000),
+            query.as_ref().and_then(|q| q.limit).unwrap_or(default_limit),
         ));
 
         if let Some(until_key) = query.as_ref().and_then(|q| q.until_id.as_ref()) {
@@ -161,6 +161,7 @@ mod tests {
     use sqlparser::dialect::GenericDialect;
     use sqlparser::parser::Parser;
 
+    use crate::components::config::DEFAULT_LIMIT;
     use crate::components::simple_pair::SimplePair;
     use crate::server::filters::*;
 
@@ -179,7 +180,7 @@ mod tests {
         let dialect = GenericDialect {};
         let ast = Parser::parse_sql(&dialect, sql_query).unwrap();
 
-        let mut f = Filters::new(None, None, Some(ast));
+        let mut f = Filters::new(None, None, Some(ast), DEFAULT_LIMIT);
 
         let vs = data.into_iter().map(|x| {
             SimplePair {
@@ -1,24 +1,29 @@
-use std::sync::{Arc, RwLock};
+use std::{fs::File, io::Read, iter};
 
 use bytes::Bytes;
-use chrono::Utc;
-use futures::executor::block_on;
 use http::Response;
 use hyper::Body;
-use rocksdb::DBIterator;
+use serde::Deserialize;
 use serde_json::Value;
-use sqlparser::{dialect::GenericDialect, parser::Parser};
-use uuid::Uuid;
 
 use crate::{
-    channels::channel::Channel,
+    channels::channel::{Channel, ChannelTest},
     components::{
+        backup,
+        changes::encode_frames,
+        db_options::DbOptions,
         errors::Error,
-        rocks,
         simple_pair::{simple_pair_to_json, SimplePair},
-        sql,
+        storage::BatchOp,
     },
-    server::{filters::Filters, query::Query, reply::Reply, responses::get_iterating_response_with_topic},
+    db::{check_user_db, get_id, Db},
+    replication::ReplicationStatus,
+    server::{
+        query::Query,
+        reply::Reply,
+        responses::{get_iterating_response_with_topic, TotalRecords},
+    },
+    sinks::OutputSink,
 };
 
 // struct IndexedValue {
@@ -78,7 +83,7 @@ pub struct AppRequest<'a> {
     pub ch:    Option<Channel>,
     pub path:  SPath<'a>,
     pub query: Option<Query>,
-    pub body:  Body,
+    pub body:  Bytes,
 }
 
 pub struct SPath<'a> {
@@ -97,32 +102,33 @@ pub struct SinceRequest<'a> {
     pub id:    Option<&'a str>,
     pub cf:    &'a str,
     pub topic: Option<&'a str>,
+    pub sink:  &'a dyn OutputSink,
     pub ch:    Option<Channel>,
-    pub db:    Arc<RwLock<rocksdb::DB>>,
+    pub db:    Db,
     is_prefix: bool,
 }
 
 impl SinceRequest<'a> {
     pub fn new(
-        db: Arc<RwLock<rocksdb::DB>>, req: AppRequest<'a>, id: &'a str, cf: &'a str, topic: Option<&'a str>,
+        db: Db, req: AppRequest<'a>, id: &'a str, cf: &'a str, topic: Option<&'a str>, sink: &'a dyn OutputSink,
     ) -> Self {
         let is_prefix = id.ends_with('*');
         let id = if is_prefix { Some(id.trim_end_matches('*')) } else { req.path.param1 };
 
-        SinceRequest { query: req.query, id, cf, topic, ch: req.ch, db, is_prefix }
+        SinceRequest { query: req.query, id, cf, topic, sink, ch: req.ch, db, is_prefix }
     }
 }
 
 pub struct SqlRequest {
-    db:    Arc<RwLock<rocksdb::DB>>,
+    db:    Db,
     query: Option<Query>,
-    req:   Body,
+    req:   Bytes,
     ch:    Option<Channel>,
 }
 
 impl SqlRequest {
-    pub fn new(db: Arc<RwLock<rocksdb::DB>>, query: Option<Query>, req: Body, ch: Option<Channel>) -> Self {
-        SqlRequest { db, query, req, ch }
+    pub fn new(db: Db, req: AppRequest) -> Self {
+        SqlRequest { db, query: req.query, req: req.body, ch: req.ch }
     }
 }
 
@@ -130,13 +136,13 @@ pub struct PutRequest<'a> {
     pub cf:      &'a str,
     pub query:   Option<Query>,
     pub path_id: Option<&'a str>,
-    pub req:     Body,
+    pub req:     Bytes,
     pub ch:      Option<Channel>,
-    pub db:      Arc<RwLock<rocksdb::DB>>,
+    pub db:      Db,
 }
 
 impl PutRequest<'a> {
-    pub fn new(db: Arc<RwLock<rocksdb::DB>>, req: AppRequest, cf: &'a str, path_id: Option<&'a str>) -> Self {
+    pub fn new(db: Db, req: AppRequest, cf: &'a str, path_id: Option<&'a str>) -> Self {
         PutRequest { cf, query: req.query, path_id, req: req.body, ch: req.ch, db }
     }
 }
@@ -146,94 +152,69 @@ pub fn since(r: SinceRequest) -> Result<Response<Body>, Error> {
 
     if r.is_prefix {
         let topic = r.topic;
-        let data = rocks::range_prefix(r.db.clone(), id, r.cf, dbiterator_filters(r.query, r.ch))?;
-        get_iterating_response_with_topic(data, topic)
+        let data = r.db.range_prefix(r.cf, &id, r.query, r.ch)?;
+        get_iterating_response_with_topic(data, topic, r.sink)
     } else {
-        let data = rocks::range(r.db, is_reverse(&r.query), Some(id), r.cf, dbiterator_filters(r.query, r.ch))?;
+        let data = r.db.range(r.cf, Some(&id), r.query, r.ch)?;
 
-        get_iterating_response_with_topic(data, r.topic)
+        get_iterating_response_with_topic(data, r.topic, r.sink)
     }
 }
 
-pub fn all(
-    db: Arc<RwLock<rocksdb::DB>>, query: Option<Query>, cf: &str, ch: Option<Channel>,
-) -> Result<Response<Body>, Error> {
-    let data = rocks::range(db, is_reverse(&query), None, cf, dbiterator_filters(query, ch))?;
-    get_iterating_response_with_topic(data, None)
+/// Reads the records of `cf`, from the first one or from the id in the `from` query parameter.
+pub fn all(db: Db, query: Option<Query>, cf: &str, ch: Option<Channel>) -> Result<Response<Body>, Error> {
+    let from = query.as_ref().and_then(|q| q.from.clone());
+    let data = db.range(cf, from.as_deref(), query, ch)?;
+    new_read_ok_iter_with_db(data)
 }
 
 pub fn sql(r: SqlRequest) -> Result<Response<Body>, Error> {
-    let value = block_on(hyper::body::to_bytes(r.req)).map_err(Error::BodyParsingError)?;
-    let sql = std::str::from_utf8(value.as_ref()).map_err(|err| Error::Utf8Error(err.to_string()))?;
-
-    let dialect = GenericDialect {};
-    let ast = Parser::parse_sql(&dialect, sql.to_string()).map_err(Error::SqlError)?;
+    let sql = std::str::from_utf8(r.req.as_ref()).map_err(|err| Error::Utf8Error(err.to_string()))?;
 
-    let from = sql::utils::get_from(&ast).ok_or_else(|| Error::CFNotFound("".to_string()))?;
-
-    since(SinceRequest {
-        query:     r.query,
-        id:        None,
-        cf:        from.as_str(),
-        topic:     None,
-        ch:        r.ch,
-        db:        r.db,
-        is_prefix: false,
-    })
+    let data = r.db.sql(sql, r.query, r.ch)?;
+    new_read_ok_iter_with_db(data)
 }
 
-pub fn try_streaming(db: Arc<RwLock<rocksdb::DB>>) -> Result<Response<Body>, Error> {
-    let res = rocks::try_streaming(db, dbiterator_filters(None, None))?;
+pub fn try_streaming(db: Db) -> Result<Response<Body>, Error> {
+    let res = db.range("test_db", None, None, None)?;
     new_read_ok_iter_with_db(res)
 }
 
 pub fn put(r: PutRequest) -> Result<Response<Body>, Error> {
-    let value = block_on(hyper::body::to_bytes(r.req)).map_err(Error::BodyParsingError)?;
-    let id = get_id(&r.query, r.path_id, Some(&value))?;
+    check_user_db(r.cf)?;
+    r.db.put(r.cf, r.path_id, r.req.as_ref(), r.query, r.ch)?;
 
-    let cf = r.cf;
-    let mut filters = Filters::new(r.query, r.ch, None);
-    let sp = SimplePair { id: Vec::from(id), value: value.to_vec() };
+    Ok(Reply::ok(None).into())
+}
 
-    let iter = filters.apply(vec![sp].into_iter());
-    let db = r.db;
+/// Writes a JSON array of `{"id":"...","val":...}` records into `cf` at once.
+pub fn batch(db: Db, cf: &str, req: Bytes) -> Result<Response<Body>, Error> {
+    check_user_db(cf)?;
+    let records: Vec<BatchRecord> = serde_json::from_slice(req.as_ref()).map_err(Error::SerdeError)?;
+    let total = records.len();
 
-    let errors = iter
-        .filter_map(|v| {
-            let res = rocks::put(db.clone(), cf, v.id, v.value);
+    let ops = records
+        .into_iter()
+        .map(|r| Ok(BatchOp::Put(r.id.into_bytes(), serde_json::to_vec(&r.val).map_err(Error::SerdeError)?)))
+        .collect::<Result<_, Error>>()?;
+    db.write_batch(cf, ops)?;
 
-            if let Err(e) = res {
-                Some(e.to_string())
-            } else {
-                None
-            }
-        })
-        .fold(None, |acc, s| {
-            match acc {
-                Some(e) => Some(format!("{}, {}", e, s)),
-                None => Some(s),
-            }
-        });
-
-    match errors {
-        Some(errs) => Err(Error::Multi(errs)),
-        None => Ok(Reply::ok(None).into()),
-    }
+    total_records_reply(total)
 }
 
-pub fn get(
-    db: Arc<RwLock<rocksdb::DB>>, cf: &str, id: &str, query: Option<Query>, ch: Option<Channel>,
-) -> Result<Response<Body>, Error> {
-    let result = rocks::get(db, &cf, &id, |i| {
-        let iter = vec![i].into_iter();
-        apply_filters(query, ch, iter)
-    })?;
+#[derive(Deserialize)]
+struct BatchRecord {
+    id:  String,
+    val: Value,
+}
 
-    new_read_ok_iter_with_db(result)
+pub fn get(db: Db, cf: &str, id: &str, query: Option<Query>, ch: Option<Channel>) -> Result<Response<Body>, Error> {
+    let sp = db.get(cf, id, query, ch)?;
+    new_read_ok_iter_with_db(sp.into_iter().collect())
 }
 
-pub fn get_all_dbs() -> Result<Response<Body>, Error> {
-    let res = rocks::get_all_dbs()?;
+pub fn get_all_dbs(db: Db) -> Result<Response<Body>, Error> {
+    let res = db.list_dbs()?;
 
     let v = serde_json::to_string(&res).map_err(Error::SerdeError)?;
 
@@ -243,56 +224,237 @@ pub fn get_all_dbs() -> Result<Response<Body>, Error> {
     Ok(reply.into())
 }
 
-pub fn create_db(db: Arc<RwLock<rocksdb::DB>>, cf: &str) -> Result<Response<Body>, Error> {
-    if let Err(err) = rocks::create_cf(db, cf) {
+pub fn create_db(db: Db, cf: &str, req: Bytes) -> Result<Response<Body>, Error> {
+    check_user_db(cf)?;
+    let opts = DbOptions::from_slice(req.as_ref())?;
+
+    if let Err(err) = db.storage().create_db(cf, &opts) {
         return Ok(err.into())
     }
 
     Ok(Reply::ok(None).into())
 }
 
-fn is_reverse(q: &Option<Query>) -> bool { q.as_ref().and_then(|q| q.direction_reverse).unwrap_or_default() }
+pub fn drop_db(db: Db, cf: &str) -> Result<Response<Body>, Error> {
+    check_user_db(cf)?;
+    db.storage().drop_db(cf)?;
+    Ok(Reply::ok(None).into())
+}
 
-pub fn new_read_ok_iter_with_db(v: Vec<SimplePair>) -> Result<Response<Body>, Error> {
-    let data =
-        box serde_json::to_value(v.into_iter().flat_map(|x| simple_pair_to_json(x, true)).collect::<Vec<Value>>())
-            .map_err(Error::SerdeError)?;
+pub fn truncate_db(db: Db, cf: &str) -> Result<Response<Body>, Error> {
+    check_user_db(cf)?;
+    db.storage().truncate_db(cf)?;
+    Ok(Reply::ok(None).into())
+}
 
-    let reply = Reply::ok(Some(data));
+pub fn rename_db(db: Db, cf: &str, query: Option<Query>) -> Result<Response<Body>, Error> {
+    let to = target_db(&query)?;
+    check_user_db(cf)?;
+    check_user_db(&to)?;
+    let total = db.storage().rename_db(cf, &to)?;
 
-    Ok(reply.into())
+    total_records_reply(total)
 }
 
-fn dbiterator_filters(query: Option<Query>, ch: Option<Channel>) -> Box<dyn FnOnce(DBIterator) -> Vec<SimplePair>> {
-    box move |iter| -> Vec<SimplePair> {
-        let sledge_iter = iter.map(SimplePair::new_boxed);
-        apply_filters(query, ch, sledge_iter)
-    }
+/// Copies `cf` into the db in the `to` query param. When a channel is passed, it is applied to
+/// every record so the copy works as a migration of the source db.
+pub fn copy_db(db: Db, cf: &str, query: Option<Query>, ch: Option<Channel>) -> Result<Response<Body>, Error> {
+    let to = target_db(&query)?;
+    check_user_db(cf)?;
+    check_user_db(&to)?;
+    let total = db.storage().copy_db(cf, &to, &|sp| {
+        match &ch {
+            Some(ch) => ch.parse_and_modify(sp.value.as_slice()).map(|v| SimplePair::new_vec(sp.id, v)),
+            None => Some(sp),
+        }
+    })?;
+
+    total_records_reply(total)
 }
 
-fn apply_filters(
-    query: Option<Query>, ch: Option<Channel>, iter: impl Iterator<Item = SimplePair> + Send + Sync,
-) -> Vec<SimplePair> {
-    let mut mods = Filters::new(query, ch, None);
-    let iter2 = mods.apply(iter);
-    iter2.collect()
+/// Stores a channel as a new version, rejecting it with the errors of every mutator that can't be
+/// created.
+pub fn put_channel(db: Db, id: &str, req: Bytes) -> Result<Response<Body>, Error> {
+    let version = db.put_channel(id, req.as_ref())?;
+    Ok(Reply::ok(Some(box serde_json::json!({ "version": version }))).into())
 }
 
-fn get_id(query: &Option<Query>, path_id: Option<&str>, req: Option<&Bytes>) -> Result<String, Error> {
-    if let Some(q) = query {
-        if let (Some(id), Some(req)) = (q.field_path.as_ref(), req) {
-            let j: Value = serde_json::from_slice(req.as_ref()).map_err(Error::SerdeError)?;
-            let val: &Value = json_nested_value(id, &j);
-            return Ok(val.as_str().ok_or_else(|| Error::IdNotFoundInJSON(id.clone()))?.to_string())
+pub fn get_channel(db: Db, id: &str) -> Result<Response<Body>, Error> {
+    let definition = db.get_channel(id)?;
+    Ok(Reply::ok(Some(box definition)).into())
+}
+
+pub fn delete_channel(db: Db, id: &str) -> Result<Response<Body>, Error> {
+    db.delete_channel(id)?;
+    Ok(Reply::ok(None).into())
+}
+
+pub fn list_channels(db: Db) -> Result<Response<Body>, Error> {
+    let channels = db.list_channels()?;
+    Ok(Reply::ok(Some(box Value::Array(channels))).into())
+}
+
+pub fn channel_history(db: Db, id: &str) -> Result<Response<Body>, Error> {
+    let history = db.channel_history(id)?;
+    let data = box serde_json::to_value(history).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+/// Runs the channel definition of the body on its samples without storing anything.
+pub fn test_channel(req: Bytes) -> Result<Response<Body>, Error> {
+    let test: ChannelTest = serde_json::from_slice(req.as_ref())?;
+    let data = box serde_json::to_value(test.run()?).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn backup(db: Db, query: Option<Query>) -> Result<Response<Body>, Error> {
+    let info = db.storage().backup(db.backup_path(), query.as_ref().and_then(|q| q.path.as_deref()))?;
+    let data = box serde_json::to_value(info).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn list_backups(db: Db) -> Result<Response<Body>, Error> {
+    let backups = backup::list(db.backup_path())?;
+    let data = box serde_json::to_value(backups).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn backup_files(db: Db, name: &str) -> Result<Response<Body>, Error> {
+    let files = backup::files(db.backup_path(), name)?;
+    let data = box serde_json::to_value(files).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Streams a file of a backup as it is.
+pub fn backup_file(db: Db, name: &str, file: &str) -> Result<Response<Body>, Error> {
+    let path = backup::file_path(db.backup_path(), name, file)?;
+    let mut file = File::open(path).map_err(|err| Error::Backup(err.to_string()))?;
+
+    let chunks = iter::from_fn(move || {
+        let mut buf = vec![0; FILE_CHUNK_SIZE];
+        match file.read(&mut buf) {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some(Ok(buf))
+            }
+            Err(err) => Some(Err(err)),
         }
-    }
+    });
 
-    let id = path_id.ok_or(Error::NoIdFoundOnRequest)?;
-    match id {
-        "_auto" => Ok(Uuid::new_v4().to_string()),
-        "_auto_time" => Ok(Utc::now().to_rfc3339()),
-        _ => Ok(id.to_string()),
-    }
+    http::Response::builder()
+        .header("Content-Type", "application/octet-stream")
+        .body(Body::wrap_stream(futures::stream::iter(chunks)))
+        .map_err(Error::GeneratingResponse)
+}
+
+pub fn delete_backup(db: Db, name: &str) -> Result<Response<Body>, Error> {
+    backup::delete(db.backup_path(), name)?;
+    Ok(Reply::ok(None).into())
+}
+
+/// Changes of every db in the binary format read by followers.
+pub fn replication_changes(db: Db, query: Option<Query>) -> Result<Response<Body>, Error> {
+    let page = db.changes(None, &query)?;
+
+    http::Response::builder()
+        .header("Content-Type", "application/octet-stream")
+        .body(Body::from(encode_frames(&page.changes)))
+        .map_err(Error::GeneratingResponse)
+}
+
+/// Range of sequence numbers kept in the change log, checked by followers before following it.
+pub fn replication_status(db: Db) -> Result<Response<Body>, Error> {
+    let storage = db.storage();
+    let status = ReplicationStatus { first_seq: storage.first_seq()?, last_seq: storage.last_seq()? };
+    let data = box serde_json::to_value(status).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn create_snapshot(db: Db, query: Option<Query>) -> Result<Response<Body>, Error> {
+    let lease = db.storage().create_snapshot(query.and_then(|q| q.lease_secs))?;
+    let data = box serde_json::to_value(lease).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn release_snapshot(db: Db, id: &str) -> Result<Response<Body>, Error> {
+    db.storage().release_snapshot(id)?;
+    Ok(Reply::ok(None).into())
+}
+
+pub fn db_stats(db: Db, cf: &str) -> Result<Response<Body>, Error> {
+    let stats = db.storage().stats(cf)?;
+    let data = box serde_json::to_value(stats).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn changes(db: Db, cf: &str, query: Option<Query>) -> Result<Response<Body>, Error> {
+    let changes = db.changes(Some(cf), &query)?;
+    let data = box serde_json::to_value(changes).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn instance_stats(db: Db) -> Result<Response<Body>, Error> {
+    let stats = db.instance_stats()?;
+    let data = box serde_json::to_value(stats).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn compact(db: Db, cf: Option<&str>, query: Option<Query>) -> Result<Response<Body>, Error> {
+    let start = query.as_ref().and_then(|q| q.start.as_deref());
+    let end = query.as_ref().and_then(|q| q.end.as_deref());
+
+    let report = db.storage().compact(cf, start, end)?;
+    let data = box serde_json::to_value(report).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn flush(db: Db, cf: Option<&str>) -> Result<Response<Body>, Error> {
+    let report = db.storage().flush(cf)?;
+    let data = box serde_json::to_value(report).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn verify_checksums(db: Db, cf: Option<&str>) -> Result<Response<Body>, Error> {
+    let reports = db.storage().verify(cf)?;
+    let data = box serde_json::to_value(reports).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+fn target_db(query: &Option<Query>) -> Result<String, Error> {
+    query.as_ref().and_then(|q| q.to.clone()).ok_or(Error::MissingTargetDb)
+}
+
+fn total_records_reply(total: usize) -> Result<Response<Body>, Error> {
+    let records = TotalRecords { total_records: total as i32 };
+    let data = box serde_json::to_value(records).map_err(Error::SerdeError)?;
+
+    Ok(Reply::ok(Some(data)).into())
+}
+
+pub fn new_read_ok_iter_with_db(v: Vec<SimplePair>) -> Result<Response<Body>, Error> {
+    let data =
+        box serde_json::to_value(v.into_iter().flat_map(|x| simple_pair_to_json(x, true)).collect::<Vec<Value>>())
+            .map_err(Error::SerdeError)?;
+
+    let reply = Reply::ok(Some(data));
+
+    Ok(reply.into())
 }
 
 pub fn json_nested_value<'a>(k: &str, v: &'a Value) -> &'a Value { k.split('.').fold(v, move |acc, x| &acc[x]) }
@@ -1,6 +1,6 @@
-mod filters;
+pub(crate) mod filters;
 mod handlers;
-mod query;
+pub mod query;
 pub(crate) mod reply;
 pub(crate) mod responses;
 pub mod service;
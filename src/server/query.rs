@@ -2,7 +2,9 @@ use std::fmt::{Display, Formatter};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use crate::components::durability::Durability;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Query {
     pub field_path: Option<String>,
     pub end: Option<String>,
@@ -14,7 +16,17 @@ pub struct Query {
     pub channel: Option<String>,
     pub include_ids: Option<bool>,
     pub omit_errors: Option<bool>,
-    pub broker: Option<String>,
+    pub sink: Option<String>,
+    pub to: Option<String>,
+    pub durability: Option<Durability>,
+    pub path: Option<String>,
+    pub snapshot: Option<String>,
+    pub lease_secs: Option<u64>,
+    pub start: Option<String>,
+    pub from: Option<String>,
+    pub since_seq: Option<u64>,
+    pub follow: Option<bool>,
+    pub wait_secs: Option<u64>,
 }
 
 impl Display for Query {
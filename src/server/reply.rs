@@ -1,3 +1,4 @@
+use http::StatusCode;
 use hyper::{Body, Response};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -8,8 +9,12 @@ use crate::server::responses::unknown_error;
 #[derive(Serialize, Deserialize)]
 pub struct Reply {
     pub(crate) error: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) code: Option<String>,
     pub(crate) cause: Option<String>,
     pub(crate) data: Option<Box<Value>>,
+    #[serde(skip)]
+    pub(crate) status: StatusCode,
 }
 
 impl From<Reply> for Response<Body> {
@@ -18,6 +23,7 @@ impl From<Reply> for Response<Body> {
             .unwrap_or_else(|err| err.to_string());
 
         http::Response::builder()
+            .status(r.status)
             .header("Content-Type", "application/json")
             .body(Body::from(body))
             .map_err(Error::GeneratingResponse)
@@ -29,23 +35,29 @@ impl Reply {
     pub fn empty() -> Self {
         Reply {
             error: false,
+            code: None,
             cause: None,
             data: None,
+            status: StatusCode::OK,
         }
     }
     pub fn ok(data: Option<Box<Value>>) -> Self {
         Reply {
             error: false,
+            code: None,
             cause: None,
             data,
+            status: StatusCode::OK,
         }
     }
 
     pub fn error(err: Error) -> Self {
         Reply {
             error: true,
+            code: Some(err.code().to_string()),
             cause: Some(err.to_string()),
-            data: None,
+            data: err.details().map(Box::new),
+            status: err.status(),
         }
     }
-}
\ No newline at end of file
+}
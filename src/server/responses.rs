@@ -1,14 +1,13 @@
-use futures::executor::block_on;
+use http::StatusCode;
 use hyper::Body;
 use hyper::Response;
-use rdkafka::config::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
 use serde::{Deserialize, Serialize};
 
 use crate::components::errors::Error;
-use crate::components::simple_pair::{KvUTF8, SimplePair};
+use crate::components::simple_pair::SimplePair;
 use crate::server::handlers::new_read_ok_iter_with_db;
 use crate::server::reply::Reply;
+use crate::sinks::OutputSink;
 
 // pub fn get_iterating_response(
 //     iter: DBIterator,
@@ -42,54 +41,28 @@ use crate::server::reply::Reply;
 // }
 
 #[derive(Serialize, Deserialize)]
-struct TotalRecords {
-    total_records: i32,
+pub(crate) struct TotalRecords {
+    pub(crate) total_records: i32,
 }
 
 pub fn get_iterating_response_with_topic(
     data: Vec<SimplePair>,
     topic_name: Option<&str>,
+    sink: &dyn OutputSink,
 ) -> Result<Response<Body>, Error> {
-    if topic_name.is_none() {
-        return new_read_ok_iter_with_db(data);
-    }
+    let topic = match topic_name {
+        Some(topic) => topic,
+        None => return new_read_ok_iter_with_db(data),
+    };
 
-    let topic = topic_name.unwrap();
-
-    let producer: FutureProducer = ClientConfig::new()
-        .set("bootstrap.servers", "4af3c87b16f6:9092")
-        .set("message.timeout.ms", "5000")
-        .create()
-        .map_err(Error::KafkaError)?;
-
-    let thread_iter = data
-        .into_iter()
-        .filter_map(From::from)
-        .map(|v: KvUTF8| producer.send(FutureRecord::to(topic).payload(&v.value).key(&v.id), 0));
-
-    let mut reply = Reply::empty();
-    let mut records = TotalRecords { total_records: 0 };
-
-    for delivery_result in thread_iter {
-        records.total_records += 1;
-
-        let result = block_on(delivery_result);
-        match result {
-            Ok(r) => {
-                if let Err(err) = r {
-                    reply.error = true;
-                    reply.cause = reply
-                        .cause
-                        .map(|s| format!("{}: {:?}. {}", err.0.to_string(), err.1, s));
-                }
-            }
-            Err(_) => {
-                reply.error = true;
-                reply.cause = reply.cause.map(|s| format!("Cancelled delivery. {}", s));
-            }
-        }
-    }
+    let delivery = sink.send(topic, data);
 
+    // Records delivered before a failure are still reported
+    let mut reply = match delivery.errors {
+        errors if errors.is_empty() => Reply::empty(),
+        errors => Reply::error(Error::Sink(errors)),
+    };
+    let records = TotalRecords { total_records: delivery.total_records as i32 };
     reply.data = Some(box serde_json::to_value(records).unwrap_or_default());
 
     Ok(reply.into())
@@ -97,6 +70,7 @@ pub fn get_iterating_response_with_topic(
 
 pub fn unknown_error(err: String) -> Response<Body> {
     http::Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
         .header("Content-Type", "application/json")
         .body(Body::from(format!(
             r#"{{"result":{{"error":"true", "cause":"{}"}}}}"#,
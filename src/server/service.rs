@@ -1,49 +1,88 @@
-use std::sync::{Arc, RwLock};
+use std::borrow::Cow;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
-use futures_util::future;
+use bytes::Bytes;
 use http::{Method, Uri};
 use hyper::service::Service;
 use hyper::{Body, Request, Response};
+use percent_encoding::percent_decode_str;
 
 use crate::channels::channel::Channel;
+use crate::components::config::Config;
 use crate::components::errors::Error;
-use crate::components::rocks;
+use crate::db::Db;
 use crate::server::handlers;
 use crate::server::handlers::{AppRequest, PutRequest, SPath, SinceRequest, SqlRequest};
 use crate::server::query::Query;
+use crate::sinks::Sinks;
 
-#[derive(Debug)]
+#[derive(Clone)]
 pub struct Svc {
-    db: Arc<RwLock<rocksdb::DB>>,
+    db:        Db,
+    sinks:     Arc<Sinks>,
+    read_only: bool,
 }
 
 impl Service<Request<Body>> for Svc {
     type Response = Response<Body>;
     type Error = hyper::Error;
-    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Ok(()).into()
     }
 
+    /// The body is read asynchronously and the rest of the request, which hits the storage, runs in
+    /// the blocking thread pool so a long scan doesn't stall the rest of the connections.
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        let (parts, body) = req.into_parts();
-        let query = get_query(&parts.uri);
-        let path = get_path(parts.uri.path());
+        let svc = self.clone();
 
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(err) => return Ok(Error::BodyParsingError(err).into()),
+            };
+
+            match tokio::task::spawn_blocking(move || svc.handle(parts.method, &parts.uri, body)).await {
+                Ok(res) => Ok(res),
+                Err(err) => Ok(Error::Join(err).into()),
+            }
+        })
+    }
+}
+
+impl Svc {
+    /// Creates the sinks in `config`. The `Svc` is then cloned for every connection. Followers of a
+    /// `leader` reject writes.
+    pub fn new(db: Db, config: &Config) -> Result<Self, Error> {
+        Ok(Svc { db, sinks: Arc::new(Sinks::from_config(config)?), read_only: config.leader.is_some() })
+    }
+
+    fn handle(&self, method: Method, uri: &Uri, body: Bytes) -> Response<Body> {
+        let query = get_query(uri);
+        let segments = get_path(uri.path());
+
+        let segment = |i: usize| segments.get(i).map(|s| &**s);
         let path = SPath {
-            route: path.get(0).cloned(),
-            cf: path.get(1).cloned(),
-            id_or_action: path.get(2).cloned(),
-            param1: path.get(3).cloned(),
-            id_or_action2: path.get(4).cloned(),
-            param2: path.get(5).cloned(),
+            route: segment(0),
+            cf: segment(1),
+            id_or_action: segment(2),
+            param1: segment(3),
+            id_or_action2: segment(4),
+            param2: segment(5),
         };
 
+        if self.read_only && rejected_by_followers(&method, &path) {
+            return Error::ReadOnly.into()
+        }
+
         let ch = match self.fetch_channel(&query) {
             Ok(res) => res,
-            Err(err) => return future::ok(err.into()),
+            Err(err) => return err.into(),
         };
 
         let common = AppRequest {
@@ -53,29 +92,25 @@ impl Service<Request<Body>> for Svc {
             body,
         };
 
-        let res: Result<Response<Body>, Error> = match parts.method {
+        let res: Result<Response<Body>, Error> = match method {
             Method::GET => self.get_handlers(common),
             Method::PUT => self.put_handlers(common),
             Method::POST => self.post_handlers(common),
+            Method::DELETE => self.delete_handlers(common),
             _ => Err(Error::MethodNotFound),
         };
 
         match res {
-            Ok(res) => future::ok(res),
-            Err(err) => future::ok(err.into()),
+            Ok(res) => res,
+            Err(err) => err.into(),
         }
     }
-}
-
-impl Svc {
-    pub fn new(db: Arc<RwLock<rocksdb::DB>>) -> Self {
-        Svc { db }
-    }
 
     fn put_handlers(&self, req: AppRequest<'_>) -> Result<Response<Body>, Error> {
         match (req.path.route, req.path.cf, req.path.id_or_action) {
             // (Some("_db"), Some(cf), Some("_create_secondary_index"))=>Some("_create_secondary_index") => handlers::create(req.query, cf_name).await,
-            (Some("_db"), Some(cf), Some("_create_db")) => handlers::create_db(self.db.clone(), cf),
+            (Some("_db"), Some(cf), Some("_create_db")) => handlers::create_db(self.db.clone(), cf, req.body),
+            (Some("_channel"), Some(id), None) => handlers::put_channel(self.db.clone(), id, req.body),
             (Some("_db"), Some(cf), id) => {
                 handlers::put(PutRequest::new(self.db.clone(), req, cf, id))
             }
@@ -88,12 +123,25 @@ impl Svc {
     fn post_handlers(&self, r: AppRequest<'_>) -> Result<Response<Body>, Error> {
         match (r.path.route, r.path.cf, r.path.id_or_action) {
             (Some("_sql"), ..) => {
-                handlers::sql(SqlRequest::new(self.db.clone(), r.query, r.body, r.ch))
+                handlers::sql(SqlRequest::new(self.db.clone(), r))
             }
+            (Some("_db"), Some(cf), Some("_truncate")) => handlers::truncate_db(self.db.clone(), cf),
+            (Some("_db"), Some(cf), Some("_rename")) => handlers::rename_db(self.db.clone(), cf, r.query),
+            (Some("_db"), Some(cf), Some("_copy")) => handlers::copy_db(self.db.clone(), cf, r.query, r.ch),
+            (Some("_db"), Some(cf), Some("_compact")) => handlers::compact(self.db.clone(), Some(cf), r.query),
+            (Some("_db"), Some(cf), Some("_flush")) => handlers::flush(self.db.clone(), Some(cf)),
+            (Some("_db"), Some(cf), Some("_verify")) => handlers::verify_checksums(self.db.clone(), Some(cf)),
+            (Some("_db"), Some(cf), Some("_batch")) => handlers::batch(self.db.clone(), cf, r.body),
             (Some("_db"), Some(cf), Some(id)) => {
                 handlers::get(self.db.clone(), cf, id, r.query, r.ch)
             }
             (Some("_test"), ..) => handlers::try_streaming(self.db.clone()),
+            (Some("_channel"), Some("_test"), None) => handlers::test_channel(r.body),
+            (Some("_admin"), Some("_backup"), ..) => handlers::backup(self.db.clone(), r.query),
+            (Some("_admin"), Some("_snapshot"), ..) => handlers::create_snapshot(self.db.clone(), r.query),
+            (Some("_admin"), Some("_compact"), ..) => handlers::compact(self.db.clone(), None, r.query),
+            (Some("_admin"), Some("_flush"), ..) => handlers::flush(self.db.clone(), None),
+            (Some("_admin"), Some("_verify"), ..) => handlers::verify_checksums(self.db.clone(), None),
 
             _ => Err(Error::WrongQuery),
         }
@@ -101,6 +149,18 @@ impl Svc {
         .or_else(|err| Ok(err.into()))
     }
 
+    fn delete_handlers(&self, r: AppRequest<'_>) -> Result<Response<Body>, Error> {
+        match (r.path.route, r.path.cf, r.path.id_or_action) {
+            (Some("_db"), Some(cf), None) => handlers::drop_db(self.db.clone(), cf),
+            (Some("_channel"), Some(id), None) => handlers::delete_channel(self.db.clone(), id),
+            (Some("_admin"), Some("_snapshot"), Some(id)) => handlers::release_snapshot(self.db.clone(), id),
+            (Some("_admin"), Some("_backups"), Some(name)) => handlers::delete_backup(self.db.clone(), name),
+            _ => Err(Error::WrongQuery),
+        }
+        .and_then(Ok)
+        .or_else(|err| Ok(err.into()))
+    }
+
     fn get_handlers(&self, r: AppRequest<'_>) -> Result<Response<Body>, Error> {
         match (
             r.path.route,
@@ -110,9 +170,26 @@ impl Svc {
             r.path.id_or_action2,
             r.path.param2,
         ) {
-            (Some("_db"), Some("_all"), ..) => handlers::get_all_dbs(),
-            (Some("_db"), Some(cf), Some("_since"), Some(id), Some("_topic"), topic) => {
-                let since_request = SinceRequest::new(self.db.clone(), r, id, cf, topic);
+            (Some("_db"), Some("_all"), ..) => handlers::get_all_dbs(self.db.clone()),
+            (Some("_channel"), Some(id), None, ..) => handlers::get_channel(self.db.clone(), id),
+            (Some("_channel"), Some(id), Some("_history"), ..) => handlers::channel_history(self.db.clone(), id),
+            (Some("_channel"), None, ..) => handlers::list_channels(self.db.clone()),
+            (Some("_admin"), Some("_backups"), Some(name), Some(file), ..) => {
+                handlers::backup_file(self.db.clone(), name, file)
+            }
+            (Some("_admin"), Some("_backups"), Some(name), ..) => handlers::backup_files(self.db.clone(), name),
+            (Some("_admin"), Some("_backups"), ..) => handlers::list_backups(self.db.clone()),
+            (Some("_admin"), Some("_replication"), Some("_status"), ..) => {
+                handlers::replication_status(self.db.clone())
+            }
+            (Some("_admin"), Some("_replication"), ..) => handlers::replication_changes(self.db.clone(), r.query),
+            (Some("_admin"), Some("_stats"), ..) => handlers::instance_stats(self.db.clone()),
+            (Some("_db"), Some(cf), Some("_stats"), ..) => handlers::db_stats(self.db.clone(), cf),
+            (Some("_db"), Some(cf), Some("_changes"), ..) => handlers::changes(self.db.clone(), cf, r.query),
+            (Some("_db"), Some(cf), Some("_since"), Some(id), Some("_topic"), topic)
+            | (Some("_db"), Some(cf), Some("_since"), Some(id), None, topic) => {
+                let sink = self.sinks.get(r.query.as_ref().and_then(|q| q.sink.as_deref()))?;
+                let since_request = SinceRequest::new(self.db.clone(), r, id, cf, topic, sink);
                 handlers::since(since_request)
             }
             (Some("_db"), Some(cf_name), Some(id), ..) => match id {
@@ -126,32 +203,218 @@ impl Svc {
     }
 
     fn fetch_channel(&self, query: &Option<Query>) -> Result<Option<Channel>, Error> {
-        let inner = self.db.clone();
-
         if let Some(channel_id) = query.as_ref().and_then(|q| q.channel.as_ref()) {
-            let res = rocks::get(inner, "_channel", &channel_id, |x| vec![x])?
-                .pop()
-                .ok_or_else(|| Error::ChannelNotFound(channel_id.to_string()))?;
-
-            let c = Channel::new_vec(
-                res.value,
-                query
-                    .as_ref()
-                    .and_then(|q| q.omit_errors)
-                    .unwrap_or_default(),
-            )?;
-            return Ok(Some(c));
+            let omit_errors = query.as_ref().and_then(|q| q.omit_errors).unwrap_or_default();
+            return Ok(Some(self.db.channel(channel_id, omit_errors)?))
         }
 
         Ok(None)
     }
 }
 
+/// Requests rejected by followers: the ones that change the data, and snapshots, as a live one would
+/// fail the db changes replicated from the leader.
+fn rejected_by_followers(method: &Method, path: &SPath) -> bool {
+    match (method, path.route, path.cf, path.id_or_action) {
+        (&Method::PUT, ..) => true,
+        (&Method::DELETE, Some("_db"), ..) | (&Method::DELETE, Some("_channel"), ..) => true,
+        (&Method::POST, Some("_db"), _, Some(action)) => ["_truncate", "_rename", "_copy", "_batch"].contains(&action),
+        (&Method::POST, Some("_admin"), Some("_snapshot"), _) => true,
+        _ => false,
+    }
+}
+
 fn get_query(uri: &Uri) -> Option<Query> {
     serde_urlencoded::from_str::<Query>(uri.query()?).ok()
 }
 
-fn get_path(p: &str) -> Vec<&str> {
-    //TODO use some library for this
-    p.split('/').filter(|x| x != &"").collect()
+/// Segments of the path, percent decoded so ids and db names can have any character.
+fn get_path(p: &str) -> Vec<Cow<str>> {
+    p.split('/').filter(|x| x != &"").map(|x| percent_decode_str(x).decode_utf8_lossy()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use http::StatusCode;
+    use serde_json::Value;
+
+    use crate::{
+        components::{
+            config::{InputConfig, SocketInputConfig},
+            db_options::DbOptions,
+            durability::Durability,
+            memory::Memory,
+        },
+        db::{CHANNELS_DB, CHANNEL_VERSIONS_DB},
+        inputs,
+        server::service::*,
+        transfer::{self, Format, IdMode, Target},
+    };
+
+    fn request(svc: &Svc, method: Method, uri: &str, body: &str) -> (StatusCode, Value) {
+        let res = svc.handle(method, &uri.parse().unwrap(), Bytes::from(body.to_string()));
+        let status = res.status();
+        let body = block_on(hyper::body::to_bytes(res.into_body())).unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn ids(body: &Value) -> Vec<&str> {
+        body["data"].as_array().unwrap().iter().map(|r| r["id"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_since_without_topic() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        for id in &["a1", "a2", "b1"] {
+            db.put("people", Some(*id), br#"{"n":1}"#, None, None).unwrap();
+        }
+        let svc = Svc::new(db, &Config::default()).unwrap();
+
+        let (status, body) = request(&svc, Method::GET, "/_db/people/_since/a2", "");
+        assert_eq!((status, ids(&body)), (StatusCode::OK, vec!["a2", "b1"]));
+        let (status, body) = request(&svc, Method::GET, "/_db/people/_since/a*", "");
+        assert_eq!((status, ids(&body)), (StatusCode::OK, vec!["a1", "a2"]));
+    }
+
+    #[test]
+    fn test_encoded_path() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("my db", &DbOptions::default()).unwrap();
+        let svc = Svc::new(db.clone(), &Config::default()).unwrap();
+
+        let (status, _) = request(&svc, Method::PUT, "/_db/my%20db/a%2Fb%3F", r#"{"n":1}"#);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(db.get("my db", "a/b?", None, None).unwrap().unwrap().id, b"a/b?".to_vec());
+
+        let (_, body) = request(&svc, Method::GET, "/_db/my%20db/a%2Fb%3F", "");
+        assert_eq!(ids(&body), vec!["a/b?"]);
+    }
+
+    #[test]
+    fn test_internal_dbs() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        db.put_channel("ch", br#"{"channel":[{"type":"remove","field":"a"}]}"#).unwrap();
+        let svc = Svc::new(db.clone(), &Config::default()).unwrap();
+
+        let (status, body) = request(&svc, Method::GET, "/_db/_all", "");
+        assert_eq!((status, &body["data"]), (StatusCode::OK, &serde_json::json!(["people"])));
+
+        for (method, uri) in vec![
+            (Method::PUT, "/_db/_channel/ch"),
+            (Method::PUT, "/_db/_channel_versions/_create_db"),
+            (Method::DELETE, "/_db/_channel"),
+            (Method::POST, "/_db/_channel/_truncate"),
+            (Method::POST, "/_db/_channel/_rename?to=channels"),
+            (Method::POST, "/_db/_channel/_copy?to=channels"),
+            (Method::POST, "/_db/people/_rename?to=_channel"),
+            (Method::POST, "/_db/people/_copy?to=_inputs"),
+        ] {
+            let (status, body) = request(&svc, method.clone(), uri, r#"{"channel":[]}"#);
+            let expected = (StatusCode::FORBIDDEN, &Value::from("internal_db"));
+            assert_eq!((status, &body["code"]), expected, "{} {}", method, uri);
+        }
+
+        // internal dbs can still be read and the channel wasn't changed
+        let (status, body) = request(&svc, Method::GET, "/_db/_channel/ch", "");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"][0]["val"]["version"], Value::from(1));
+        assert_eq!(db.storage().list_dbs().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_follower_rejects_writes_and_snapshots() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        let config = Config { leader: Some("http://localhost:3000".to_string()), ..Config::default() };
+        let svc = Svc::new(db, &config).unwrap();
+
+        for (method, uri) in &[(Method::PUT, "/_db/people/1"), (Method::POST, "/_admin/_snapshot")] {
+            let (status, body) = request(&svc, method.clone(), uri, r#"{"n":1}"#);
+            assert_eq!((status, body["code"].as_str()), (StatusCode::FORBIDDEN, Some("read_only")));
+        }
+
+        let (status, _) = request(&svc, Method::GET, "/_db/people/_all", "");
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_batch() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        let svc = Svc::new(db.clone(), &Config::default()).unwrap();
+
+        let body = r#"[{"id":"1","val":{"n":1}},{"id":"a/b","val":"x"}]"#;
+        let (status, body) = request(&svc, Method::POST, "/_db/people/_batch", body);
+        assert_eq!((status, &body["data"]["total_records"]), (StatusCode::OK, &serde_json::json!(2)));
+        assert_eq!(db.get("people", "a/b", None, None).unwrap().unwrap().value, br#""x""#.to_vec());
+
+        let (status, body) = request(&svc, Method::POST, "/_db/people/_batch", r#"[{"val":1}]"#);
+        assert_eq!((status, body["code"].as_str()), (StatusCode::BAD_REQUEST, Some("invalid_json")));
+    }
+
+    #[test]
+    fn test_invalid_channel() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        let svc = Svc::new(db.clone(), &Config::default()).unwrap();
+        let invalid = r#"{"channel":[{"type":"nope"}]}"#;
+
+        for (method, uri, expected) in vec![
+            (Method::PUT, "/_channel/x", (StatusCode::BAD_REQUEST, "invalid_channel")),
+            (Method::PUT, "/_db/_channel/x", (StatusCode::FORBIDDEN, "internal_db")),
+        ] {
+            let (status, body) = request(&svc, method, uri, invalid);
+            assert_eq!((status, body["code"].as_str().unwrap()), expected, "{}", uri);
+        }
+        assert!(matches!(db.get_channel("x"), Err(Error::ChannelNotFound(_))));
+
+        let test = r#"{"channel":[{"type":"nope"}],"samples":[]}"#;
+        let (status, body) = request(&svc, Method::POST, "/_channel/_test", test);
+        assert_eq!((status, body["code"].as_str()), (StatusCode::BAD_REQUEST, Some("invalid_channel")));
+    }
+
+    #[test]
+    fn test_channel_writes_are_versioned() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        let svc = Svc::new(db.clone(), &Config::default()).unwrap();
+        let channel = r#"{"channel":[{"type":"remove","field":"a"}]}"#;
+
+        for version in 1..=2 {
+            let (status, body) = request(&svc, Method::PUT, "/_channel/ch", channel);
+            assert_eq!((status, &body["data"]["version"]), (StatusCode::OK, &Value::from(version)));
+        }
+
+        // every other way of writing into the channel dbs is rejected
+        let record = format!(r#"[{{"id":"ch","val":{}}}]"#, channel);
+        for (method, uri, body) in vec![
+            (Method::PUT, "/_db/_channel/ch", channel),
+            (Method::PUT, "/_db/_channel_versions/ch@3", channel),
+            (Method::POST, "/_db/_channel/_batch", record.as_str()),
+            (Method::POST, "/_db/people/_copy?to=_channel", ""),
+            (Method::POST, "/_db/people/_rename?to=_channel_versions", ""),
+        ] {
+            let (status, body) = request(&svc, method.clone(), uri, body);
+            assert_eq!((status, &body["code"]), (StatusCode::FORBIDDEN, &Value::from("internal_db")), "{}", uri);
+        }
+
+        let target = Target::Local(db.clone());
+        let line = format!(r#"{{"id":"ch","val":{}}}"#, channel);
+        let import = transfer::import(&target, CHANNELS_DB, Format::Ndjson, &IdMode::Record, &mut line.as_bytes());
+        assert!(matches!(block_on(import), Err(Error::InternalDb(_))));
+
+        let socket = SocketInputConfig { db: CHANNELS_DB.to_string(), ..SocketInputConfig::default() };
+        let inputs = vec![("ch".to_string(), InputConfig::Socket(socket))].into_iter().collect();
+        let config = Config { inputs, ..Config::default() };
+        assert!(inputs::start(&config, db.clone()).is_err());
+
+        // the stored channel is the last version and every version is kept
+        let versions = db.storage().range(CHANNEL_VERSIONS_DB, false, None, None, box |iter| iter.collect()).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(db.get_channel("ch").unwrap(), serde_json::from_slice::<Value>(&versions[1].value).unwrap());
+        assert_eq!(db.channel_history("ch").unwrap().iter().map(|v| v.version).collect::<Vec<_>>(), vec![1, 2]);
+    }
 }
@@ -0,0 +1,249 @@
+use std::{iter, time::Instant};
+
+use serde_json::{json, Value};
+
+use crate::{
+    components::errors::Error,
+    transfer::{cell, columns, Target},
+};
+
+pub const PROMPT: &str = "sledge> ";
+pub const CONTINUATION: &str = "     -> ";
+
+const HELP: &str = r#"SQL statements end with ';' and can span several lines.
+
+\dt          list the dbs
+\channels    list the stored channels
+\timing      toggle the time taken by every statement
+\json        toggle between tables and JSON
+\?           show this help
+\q           quit"#;
+
+/// What to do after a line of input.
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    /// The statement isn't finished yet.
+    Continue,
+    Print(String),
+    Quit,
+}
+
+/// State of an interactive session: the statement being written and the output options. Lines are
+/// read by the caller, so the session can be driven without a terminal.
+pub struct Shell {
+    target: Target,
+    buffer: String,
+    timing: bool,
+    json:   bool,
+}
+
+impl Shell {
+    pub fn new(target: Target) -> Self { Shell { target, buffer: String::new(), timing: false, json: false } }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            return PROMPT
+        }
+
+        CONTINUATION
+    }
+
+    /// Drops the statement being written.
+    pub fn cancel(&mut self) { self.buffer.clear() }
+
+    /// Runs a meta-command or, once the line ends the statement, the SQL statement.
+    pub async fn line(&mut self, line: &str) -> Result<Step, Error> {
+        let line = line.trim();
+        if self.buffer.is_empty() && line.starts_with('\\') {
+            return self.meta(line).await
+        }
+        if line.is_empty() {
+            return Ok(Step::Continue)
+        }
+
+        if !self.buffer.is_empty() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(line);
+        if !ends_statement(&self.buffer) {
+            return Ok(Step::Continue)
+        }
+
+        let sql = std::mem::take(&mut self.buffer);
+        let start = Instant::now();
+        let rows = self.target.sql(sql.trim_end_matches(';')).await?;
+
+        Ok(Step::Print(self.with_timing(self.render(&rows), start)))
+    }
+
+    async fn meta(&mut self, line: &str) -> Result<Step, Error> {
+        let start = Instant::now();
+        let out = match line {
+            "\\q" => return Ok(Step::Quit),
+            "\\?" => HELP.to_string(),
+            "\\timing" => {
+                self.timing = !self.timing;
+                format!("Timing is {}.", if self.timing { "on" } else { "off" })
+            }
+            "\\json" => {
+                self.json = !self.json;
+                format!("Output is {}.", if self.json { "JSON" } else { "tables" })
+            }
+            "\\dt" => {
+                let dbs = self.target.list_dbs().await?;
+                let out = if self.json {
+                    serde_json::to_string_pretty(&dbs)?
+                } else {
+                    table(&["db"], dbs.into_iter().map(|db| vec![db]).collect())
+                };
+                self.with_timing(out, start)
+            }
+            "\\channels" => {
+                let channels = self.target.list_channels().await?;
+                let out = if self.json {
+                    serde_json::to_string_pretty(&channels)?
+                } else {
+                    table(&["channel", "mutators"], channels.iter().map(mutators).collect())
+                };
+                self.with_timing(out, start)
+            }
+            cmd => return Err(Error::Config(format!("unknown command '{}', try \\?", cmd))),
+        };
+
+        Ok(Step::Print(out))
+    }
+
+    fn render(&self, rows: &[(String, Value)]) -> String {
+        if self.json {
+            let records: Vec<Value> = rows.iter().map(|(id, val)| json!({ "id": id, "val": val })).collect();
+            return serde_json::to_string_pretty(&records).unwrap_or_default()
+        }
+
+        let columns = columns(rows);
+        let cells = rows
+            .iter()
+            .map(|(id, val)| iter::once(id.clone()).chain(columns[1..].iter().map(|c| cell(val, c))).collect())
+            .collect();
+
+        table(&columns, cells)
+    }
+
+    fn with_timing(&self, out: String, start: Instant) -> String {
+        if !self.timing {
+            return out
+        }
+
+        format!("{}\nTime: {:.3} ms", out, start.elapsed().as_secs_f64() * 1000.0)
+    }
+}
+
+/// Name of a stored channel and the types of its mutators.
+fn mutators(channel: &Value) -> Vec<String> {
+    let types = match channel["channel"].as_array() {
+        Some(mutators) => mutators.iter().map(|m| cell(m, "type")).collect::<Vec<_>>().join(", "),
+        None => String::new(),
+    };
+
+    vec![cell(channel, "name"), types]
+}
+
+/// Whether `sql` ends with a `;` outside of string literals and quoted identifiers. Quotes escaped
+/// by doubling them close and open the literal again, so they need no special case.
+fn ends_statement(sql: &str) -> bool {
+    let mut quote = None;
+    let mut ends = false;
+    for c in sql.chars() {
+        match quote {
+            None if c == '\'' || c == '"' => quote = Some(c),
+            Some(q) if c == q => quote = None,
+            _ => {}
+        }
+        ends = c == ';' && quote.is_none();
+    }
+
+    ends
+}
+
+/// Aligned columns like `psql`, followed by the number of rows. Newlines in the cells are escaped.
+fn table(columns: &[&str], rows: Vec<Vec<String>>) -> String {
+    let rows: Vec<Vec<String>> =
+        rows.into_iter().map(|row| row.into_iter().map(|cell| cell.replace('\n', "\\n")).collect()).collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| rows.iter().map(|row| row[i].chars().count()).chain(iter::once(column.len())).max())
+        .map(Option::unwrap_or_default)
+        .collect();
+
+    let line = |values: &[&str]| {
+        let cells: Vec<String> = values
+            .iter()
+            .zip(widths.iter())
+            .map(|(value, width)| format!(" {}{} ", value, " ".repeat(width - value.chars().count())))
+            .collect();
+        cells.join("|").trim_end().to_string()
+    };
+
+    let mut out = vec![line(columns)];
+    out.push(widths.iter().map(|width| "-".repeat(width + 2)).collect::<Vec<_>>().join("+"));
+    out.extend(rows.iter().map(|row| line(&row.iter().map(String::as_str).collect::<Vec<_>>())));
+    out.push(format!("({} {})", rows.len(), if rows.len() == 1 { "row" } else { "rows" }));
+
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use crate::{
+        components::{db_options::DbOptions, durability::Durability, memory::Memory},
+        db::Db,
+        shell::*,
+    };
+
+    #[test]
+    fn test_shell() {
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        db.put("people", Some("1"), br#"{"name":"mario","age":35}"#, None, None).unwrap();
+        db.put("people", Some("2"), br#"{"name":"ula"}"#, None, None).unwrap();
+        let channels = db.clone();
+
+        let mut shell = Shell::new(Target::Local(db));
+        assert_eq!(block_on(shell.line("SELECT *")).unwrap(), Step::Continue);
+        assert_eq!(shell.prompt(), CONTINUATION);
+
+        let expected = " id | age | name\n----+-----+-------\n 1  | 35  | mario\n 2  |     | ula\n(2 rows)";
+        assert_eq!(block_on(shell.line("FROM people;")).unwrap(), Step::Print(expected.to_string()));
+        assert_eq!(shell.prompt(), PROMPT);
+
+        let dbs = " db\n--------\n people\n(1 row)";
+        assert_eq!(block_on(shell.line("\\dt")).unwrap(), Step::Print(dbs.to_string()));
+        assert!(matches!(block_on(shell.line("\\channels")), Ok(Step::Print(out)) if out.ends_with("(0 rows)")));
+        channels.put_channel("up", br#"{"channel":[{"type":"uppercase","field":"name"}]}"#).unwrap();
+        let listed = block_on(shell.line("\\channels")).unwrap();
+        assert!(matches!(listed, Step::Print(out) if out.contains(" up      | uppercase")));
+        assert!(block_on(shell.line("\\nope")).is_err());
+        assert_eq!(block_on(shell.line("\\q")).unwrap(), Step::Quit);
+    }
+
+    #[test]
+    fn test_quoted_semicolons() {
+        assert!(ends_statement("SELECT * FROM people;"));
+        assert!(!ends_statement("SELECT * FROM people WHERE name = 'a;"));
+        assert!(!ends_statement("SELECT * FROM people WHERE name = 'it''s;"));
+        assert!(!ends_statement("SELECT \"a;"));
+        assert!(ends_statement("SELECT * FROM people WHERE name = 'a;\nb';"));
+
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        db.storage().create_db("people", &DbOptions::default()).unwrap();
+        let mut shell = Shell::new(Target::Local(db));
+        assert_eq!(block_on(shell.line("SELECT * FROM people WHERE name = 'a;")).unwrap(), Step::Continue);
+        assert_eq!(shell.prompt(), CONTINUATION);
+        assert!(matches!(block_on(shell.line("b';")), Ok(Step::Print(_))));
+        assert_eq!(shell.prompt(), PROMPT);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::Utc;
+
+use crate::{
+    components::{config::FileSinkConfig, errors::Error, simple_pair::SimplePair},
+    sinks::{to_ndjson, Delivery, OutputSink},
+};
+
+/// Appends the records as NDJSON to a file per topic. When a file reaches `max_bytes` it's renamed
+/// with the current time, like `events.20200501T101500.123.ndjson`, and a new one is started.
+pub struct FileSink {
+    dir:       PathBuf,
+    max_bytes: u64,
+    lock:      Mutex<()>,
+}
+
+impl FileSink {
+    pub fn new(config: &FileSinkConfig) -> Result<Self, Error> {
+        fs::create_dir_all(&config.dir).map_err(|err| Error::Config(format!("creating '{}': {}", config.dir, err)))?;
+
+        Ok(FileSink { dir: PathBuf::from(&config.dir), max_bytes: config.max_bytes, lock: Mutex::new(()) })
+    }
+
+    fn write(&self, topic: &str, lines: &[String]) -> io::Result<()> {
+        if topic.contains(|c| c == '/' || c == '\\') || topic.starts_with('.') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("wrong topic name '{}'", topic)))
+        }
+
+        let _guard = self.lock.lock().unwrap();
+        let path = self.dir.join(format!("{}.ndjson", topic));
+        let mut size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let mut file = open(&path)?;
+
+        for line in lines {
+            let len = line.len() as u64 + 1;
+            if size > 0 && size + len > self.max_bytes {
+                file.flush()?;
+                let rotated = format!("{}.{}.ndjson", topic, Utc::now().format("%Y%m%dT%H%M%S%.f"));
+                fs::rename(&path, self.dir.join(rotated))?;
+                file = open(&path)?;
+                size = 0;
+            }
+
+            writeln!(file, "{}", line)?;
+            size += len;
+        }
+
+        file.flush()
+    }
+}
+
+impl OutputSink for FileSink {
+    fn send(&self, topic: &str, data: Vec<SimplePair>) -> Delivery {
+        let lines = to_ndjson(data);
+        let errors = self.write(topic, &lines).err().map(|err| err.to_string()).into_iter().collect();
+
+        Delivery { total_records: lines.len(), errors }
+    }
+}
+
+fn open(path: &Path) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::sinks::file::*;
+
+    #[test]
+    fn test_file_rotation() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let config = FileSinkConfig { dir: dir.to_string_lossy().to_string(), max_bytes: 60 };
+        let sink = FileSink::new(&config).unwrap();
+
+        let data = (0..3).map(|i| SimplePair::new_str_vec(&i.to_string(), br#"{"a":"bbbbbbbbbb"}"#.to_vec())).collect();
+        let delivery = sink.send("events", data);
+        assert!(delivery.errors.is_empty());
+        assert_eq!(delivery.total_records, 3);
+
+        // every line is 37 bytes long, so each one goes to its own file
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        let current = fs::read_to_string(dir.join("events.ndjson")).unwrap();
+        assert_eq!(current, "{\"id\":\"2\",\"val\":{\"a\":\"bbbbbbbbbb\"}}\n");
+
+        assert!(!sink.send("../events", vec![]).errors.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
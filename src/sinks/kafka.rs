@@ -0,0 +1,135 @@
+use futures::{executor::block_on, future::join_all};
+use rdkafka::{
+    config::ClientConfig,
+    producer::{DeliveryFuture, FutureProducer, FutureRecord},
+};
+use serde_json::Value;
+
+use crate::{
+    components::{
+        config::{KafkaConfig, KafkaKey},
+        errors::Error,
+        simple_pair::SimplePair,
+        sql::json_nested_value,
+    },
+    sinks::{Delivery, OutputSink},
+};
+
+/// Kafka producer created once at startup and shared by every request using its sink.
+pub struct KafkaSink {
+    producer:  FutureProducer,
+    key:       KafkaKey,
+    key_field: Option<String>,
+    partition: Option<i32>,
+}
+
+impl KafkaSink {
+    pub fn new(config: &KafkaConfig) -> Result<Self, Error> {
+        if config.key == KafkaKey::Field && config.key_field.is_none() {
+            return Err(Error::Config("'key_field' is required when 'key' is 'field'".to_string()))
+        }
+
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", &config.brokers)
+            .set("message.timeout.ms", &config.message_timeout_ms.to_string());
+
+        let optional = [
+            ("acks", &config.acks),
+            ("compression.codec", &config.compression),
+            ("partitioner", &config.partitioner),
+            ("security.protocol", &config.security_protocol),
+            ("sasl.mechanisms", &config.sasl_mechanism),
+            ("sasl.username", &config.sasl_username),
+            ("sasl.password", &config.sasl_password),
+            ("ssl.ca.location", &config.ssl_ca_location),
+        ];
+        for (name, value) in optional.iter() {
+            if let Some(value) = value {
+                client.set(name, value);
+            }
+        }
+        for (name, value) in config.properties.iter() {
+            client.set(name, value);
+        }
+
+        Ok(KafkaSink {
+            producer:  client.create().map_err(Error::KafkaError)?,
+            key:       config.key,
+            key_field: config.key_field.clone(),
+            partition: config.partition,
+        })
+    }
+
+    fn key(&self, sp: &SimplePair) -> Option<Vec<u8>> {
+        match (self.key, self.key_field.as_ref()) {
+            (KafkaKey::Id, _) => Some(sp.id.clone()),
+            (KafkaKey::Field, Some(field)) => {
+                let value: Value = serde_json::from_slice(&sp.value).ok()?;
+                match json_nested_value(field, &value) {
+                    Value::Null => None,
+                    Value::String(s) => Some(s.clone().into_bytes()),
+                    v => Some(v.to_string().into_bytes()),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl OutputSink for KafkaSink {
+    /// Sends every record to `topic` and waits until all of them are delivered or failed.
+    fn send(&self, topic: &str, data: Vec<SimplePair>) -> Delivery {
+        let deliveries: Vec<DeliveryFuture> = data
+            .iter()
+            .map(|sp| {
+                let key = self.key(sp);
+                let mut record: FutureRecord<[u8], [u8]> = FutureRecord::to(topic).payload(sp.value.as_slice());
+                if let Some(key) = key.as_ref() {
+                    record = record.key(key.as_slice());
+                }
+                if let Some(partition) = self.partition {
+                    record = record.partition(partition);
+                }
+
+                self.producer.send(record, 0)
+            })
+            .collect();
+
+        let errors = block_on(join_all(deliveries))
+            .into_iter()
+            .filter_map(|res| {
+                match res {
+                    Ok(Ok(_)) => None,
+                    Ok(Err((err, _))) => Some(err.to_string()),
+                    Err(_) => Some("cancelled delivery".to_string()),
+                }
+            })
+            .collect();
+
+        Delivery { total_records: data.len(), errors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        components::{config::KafkaConfig, simple_pair::SimplePair},
+        sinks::kafka::*,
+    };
+
+    #[test]
+    fn test_key() {
+        let config = KafkaConfig { key: KafkaKey::Field, key_field: Some("user.id".to_string()), ..Default::default() };
+        let sink = KafkaSink::new(&config).unwrap();
+
+        let sp = SimplePair::new_str_vec("1", br#"{"user":{"id":42}}"#.to_vec());
+        assert_eq!(sink.key(&sp), Some(b"42".to_vec()));
+
+        let sp = SimplePair::new_str_vec("2", br#"{"user":{}}"#.to_vec());
+        assert_eq!(sink.key(&sp), None);
+
+        let config = KafkaConfig { key: KafkaKey::Field, ..Default::default() };
+        assert!(KafkaSink::new(&config).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use crate::components::{
+    config::{Config, SinkConfig},
+    errors::Error,
+    simple_pair::{simple_pair_to_json, SimplePair},
+};
+
+pub mod file;
+pub mod kafka;
+pub mod webhook;
+
+use file::FileSink;
+use kafka::KafkaSink;
+use webhook::WebhookSink;
+
+/// Destination of the records of `_topic` requests.
+pub trait OutputSink: Send + Sync {
+    /// Sends `data` to `topic`, blocking until every record is delivered or failed.
+    fn send(&self, topic: &str, data: Vec<SimplePair>) -> Delivery;
+}
+
+/// Result of sending a batch of records.
+pub struct Delivery {
+    pub total_records: usize,
+    pub errors:        Vec<String>,
+}
+
+/// Outputs configured on the server, created once at startup so requests reuse their producers
+/// and connections.
+pub struct Sinks {
+    default: KafkaSink,
+    named:   HashMap<String, Box<dyn OutputSink>>,
+}
+
+impl Sinks {
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let named = config
+            .sinks
+            .iter()
+            .map(|(name, sink)| {
+                let sink = new_sink(sink).map_err(|err| Error::Config(format!("sink '{}': {}", name, err)))?;
+                Ok((name.clone(), sink))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Sinks { default: KafkaSink::new(&config.kafka)?, named })
+    }
+
+    /// The sink called `name`, or the Kafka producer in the `[kafka]` section if `None`.
+    pub fn get(&self, name: Option<&str>) -> Result<&dyn OutputSink, Error> {
+        match name {
+            Some(name) => {
+                self.named.get(name).map(|sink| sink.as_ref()).ok_or_else(|| Error::SinkNotFound(name.to_string()))
+            }
+            None => Ok(&self.default),
+        }
+    }
+}
+
+fn new_sink(config: &SinkConfig) -> Result<Box<dyn OutputSink>, Error> {
+    match config {
+        SinkConfig::Kafka(config) => Ok(box KafkaSink::new(config)?),
+        SinkConfig::Webhook(config) => Ok(box WebhookSink::new(config)?),
+        SinkConfig::File(config) => Ok(box FileSink::new(config)?),
+    }
+}
+
+/// Lines of `{"id":...,"val":...}`. Records whose value is not JSON are skipped.
+pub(crate) fn to_ndjson(data: Vec<SimplePair>) -> Vec<String> {
+    data.into_iter()
+        .filter_map(|sp| simple_pair_to_json(sp, true))
+        .filter_map(|v| serde_json::to_string(&v).ok())
+        .collect()
+}
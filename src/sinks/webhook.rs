@@ -0,0 +1,182 @@
+use std::{thread, time::Duration};
+
+use futures::executor::block_on;
+use http::{header::CONTENT_TYPE, Method, Request, Uri};
+use hyper::{client::HttpConnector, Body, Client};
+
+use crate::{
+    components::{config::WebhookConfig, errors::Error, simple_pair::SimplePair},
+    sinks::{to_ndjson, Delivery, OutputSink},
+};
+
+/// POSTs the records as NDJSON, with the topic in the `X-Sledge-Topic` header.
+pub struct WebhookSink {
+    client: Client<HttpConnector>,
+    config: WebhookConfig,
+}
+
+/// Whether a failed request is worth retrying.
+enum Failure {
+    Retry(String),
+    Fatal(String),
+}
+
+impl WebhookSink {
+    pub fn new(config: &WebhookConfig) -> Result<Self, Error> {
+        config.url.parse::<Uri>().map_err(|err| Error::Config(format!("wrong url '{}': {}", config.url, err)))?;
+        if config.batch_size == 0 {
+            return Err(Error::Config("'batch_size' must be greater than 0".to_string()))
+        }
+
+        Ok(WebhookSink { client: Client::new(), config: config.clone() })
+    }
+
+    /// Retries connection errors, 429 and 5xx replies with an exponential backoff.
+    fn post(&self, topic: &str, body: String) -> Result<(), String> {
+        let mut backoff = Duration::from_millis(self.config.backoff_ms);
+
+        for attempt in 0.. {
+            match self.try_post(topic, body.clone()) {
+                Ok(()) => return Ok(()),
+                Err(Failure::Retry(err)) if attempt < self.config.max_retries => {
+                    log::warn!("webhook '{}' failed, retrying in {:?}: {}", self.config.url, backoff, err);
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(Failure::Retry(err)) | Err(Failure::Fatal(err)) => return Err(err),
+            }
+        }
+
+        unreachable!()
+    }
+
+    fn try_post(&self, topic: &str, body: String) -> Result<(), Failure> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(self.config.url.as_str())
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .header("X-Sledge-Topic", topic);
+        for (name, value) in self.config.headers.iter() {
+            req = req.header(name.as_str(), value.as_str());
+        }
+
+        let req = req.body(Body::from(body)).map_err(|err| Failure::Fatal(err.to_string()))?;
+        let res = block_on(self.client.request(req)).map_err(|err| Failure::Retry(err.to_string()))?;
+
+        let status = res.status();
+        match status.as_u16() {
+            200..=299 => Ok(()),
+            429 | 500..=599 => Err(Failure::Retry(format!("webhook replied {}", status))),
+            _ => Err(Failure::Fatal(format!("webhook replied {}", status))),
+        }
+    }
+}
+
+impl OutputSink for WebhookSink {
+    fn send(&self, topic: &str, data: Vec<SimplePair>) -> Delivery {
+        let lines = to_ndjson(data);
+        let errors = lines
+            .chunks(self.config.batch_size)
+            .filter_map(|batch| self.post(topic, batch.join("\n") + "\n").err())
+            .collect();
+
+        Delivery { total_records: lines.len(), errors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server,
+    };
+    use tokio::runtime::Runtime;
+
+    use crate::sinks::webhook::*;
+
+    type Received = Arc<Mutex<Vec<String>>>;
+
+    /// Starts a server that replies `status` to the first `failures` requests and 200 afterwards,
+    /// keeping the body of every request.
+    fn serve(rt: &mut Runtime, failures: usize, status: u16) -> (String, Received) {
+        let received: Received = Arc::new(Mutex::new(Vec::new()));
+        let requests = received.clone();
+
+        let make_svc = make_service_fn(move |_| {
+            let requests = requests.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                    let requests = requests.clone();
+                    async move {
+                        let body = hyper::body::to_bytes(req.into_body()).await?;
+                        let mut requests = requests.lock().unwrap();
+                        requests.push(String::from_utf8(body.to_vec()).unwrap());
+
+                        let status = if requests.len() <= failures { status } else { 200 };
+                        Ok::<_, hyper::Error>(Response::builder().status(status).body(Body::empty()).unwrap())
+                    }
+                }))
+            }
+        });
+
+        let server = rt.enter(|| Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc));
+        let url = format!("http://{}/hook", server.local_addr());
+        rt.spawn(server);
+
+        (url, received)
+    }
+
+    fn send(rt: &mut Runtime, config: WebhookConfig, records: usize) -> (Delivery, Duration) {
+        let sink = WebhookSink::new(&config).unwrap();
+        let record = |i: usize| SimplePair::new_str_vec(&i.to_string(), format!("{{\"n\":{}}}", i).into_bytes());
+        let data = (0..records).map(record).collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let delivery = rt.block_on(tokio::task::spawn_blocking(move || sink.send("events", data))).unwrap();
+
+        (delivery, start.elapsed())
+    }
+
+    fn line(i: usize) -> String { format!("{{\"id\":\"{}\",\"val\":{{\"n\":{}}}}}\n", i, i) }
+
+    #[test]
+    fn test_webhook_retries() {
+        let mut rt = Runtime::new().unwrap();
+        let (url, received) = serve(&mut rt, 2, 503);
+        let config = WebhookConfig { url, batch_size: 2, max_retries: 3, backoff_ms: 20, ..WebhookConfig::default() };
+
+        let (delivery, elapsed) = send(&mut rt, config, 3);
+        assert!(delivery.errors.is_empty());
+        assert_eq!(delivery.total_records, 3);
+        // the first batch is retried twice, after 20 and 40ms
+        assert!(elapsed >= Duration::from_millis(60), "{:?}", elapsed);
+
+        let first = line(0) + &line(1);
+        assert_eq!(*received.lock().unwrap(), vec![first.clone(), first.clone(), first, line(2)]);
+    }
+
+    #[test]
+    fn test_webhook_gives_up() {
+        let mut rt = Runtime::new().unwrap();
+        let (url, received) = serve(&mut rt, usize::MAX, 500);
+        let config = WebhookConfig { url, batch_size: 10, max_retries: 2, backoff_ms: 10, ..WebhookConfig::default() };
+
+        let (delivery, elapsed) = send(&mut rt, config, 2);
+        assert_eq!(delivery.errors, vec!["webhook replied 500 Internal Server Error".to_string()]);
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert_eq!(received.lock().unwrap().len(), 3);
+
+        // client errors aren't retried
+        let (url, received) = serve(&mut rt, usize::MAX, 400);
+        let config = WebhookConfig { url, batch_size: 1, max_retries: 2, backoff_ms: 10, ..WebhookConfig::default() };
+
+        let (delivery, _) = send(&mut rt, config, 2);
+        assert_eq!(delivery.errors.len(), 2);
+        assert_eq!(*received.lock().unwrap(), vec![line(0), line(1)]);
+    }
+}
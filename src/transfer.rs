@@ -0,0 +1,534 @@
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, Write},
+    iter, mem,
+    str::FromStr,
+};
+
+use http::{Method, Request};
+use hyper::{client::HttpConnector, Body, Client};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde_json::{json, Value};
+
+use crate::{
+    channels::channel::Channel,
+    components::{db_options::DbOptions, errors::Error, simple_pair::SimplePair, storage::BatchOp},
+    db::{check_user_db, get_id, Db},
+    server::{query::Query, reply::Reply},
+};
+
+/// Records read or written at once.
+const PAGE_SIZE: usize = 1000;
+
+/// Characters encoded in the segments of the paths sent to a server, so ids and names can have
+/// `/`, `?`, spaces...
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Characters encoded in the query parameters, which also separate them or stand for spaces.
+const PARAM: &AsciiSet = &SEGMENT.add(b'&').add(b'=').add(b'+');
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A `{"id":"...","val":...}` record per line.
+    Ndjson,
+    /// A header with `id` and the fields of the values, then a row per record.
+    Csv,
+    /// An array of `{"id":"...","val":...}` records.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            s => Err(format!("unknown format '{}', use one of ndjson, csv or json", s)),
+        }
+    }
+}
+
+/// How `import` takes the id of every record.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdMode {
+    /// Records are `{"id":"...","val":...}`, as written by `export`.
+    Record,
+    /// Records are the values and the id is at this path of them, like `a.b`.
+    Path(String),
+    /// Records are the values and get a generated id.
+    Auto,
+}
+
+/// Where `export` reads from and `import` writes into: a data folder or a running server.
+pub enum Target {
+    Local(Db),
+    Remote(Remote),
+}
+
+/// Writes every record of `db` into `out`, from `since` on if set and after applying the stored
+/// `channel` if set. Returns the number of records written.
+///
+/// Csv needs the columns of every record for its header, so the records are read twice: once for
+/// the columns and once to write the rows.
+pub async fn export(
+    target: &Target, db: &str, format: Format, since: Option<&str>, channel: Option<&str>, out: &mut dyn Write,
+) -> Result<usize, Error> {
+    let channel = match channel {
+        Some(id) => Some(target.channel(id).await?),
+        None => None,
+    };
+
+    let columns = match format {
+        Format::Csv => {
+            let mut columns = BTreeSet::new();
+            let mut records = Records::new(target, db, since, channel.as_ref());
+            while let Some(page) = records.next_page().await? {
+                columns.extend(self::columns(&page)[1..].iter().map(|c| c.to_string()));
+            }
+            iter::once("id".to_string()).chain(columns).collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let mut writer = Writer::new(format, out, columns);
+    let mut records = Records::new(target, db, since, channel.as_ref());
+    while let Some(page) = records.next_page().await? {
+        for (id, val) in page {
+            writer.write(id, val)?;
+        }
+    }
+
+    writer.finish()
+}
+
+/// Pages of the records of a db, from `since` on if set and after applying `channel` if set.
+struct Records<'a> {
+    target:  &'a Target,
+    db:      &'a str,
+    channel: Option<&'a Channel>,
+    from:    Option<String>,
+    skip:    usize,
+}
+
+impl<'a> Records<'a> {
+    fn new(target: &'a Target, db: &'a str, since: Option<&str>, channel: Option<&'a Channel>) -> Self {
+        Records { target, db, channel, from: since.map(String::from), skip: 0 }
+    }
+
+    /// Reads the next page, `None` after the last record.
+    async fn next_page(&mut self) -> Result<Option<Vec<(String, Value)>>, Error> {
+        let page = self.target.page(self.db, self.from.as_deref(), self.skip).await?;
+        match page.last() {
+            Some((id, _)) => self.from = Some(id.clone()),
+            None => return Ok(None),
+        }
+        self.skip = 1;
+
+        let mut records = Vec::with_capacity(page.len());
+        for (id, val) in page {
+            match self.channel {
+                Some(ch) => {
+                    if let Some(val) = ch.parse_and_modify(&serde_json::to_vec(&val)?) {
+                        records.push((id, serde_json::from_slice(&val)?));
+                    }
+                }
+                None => records.push((id, val)),
+            }
+        }
+
+        Ok(Some(records))
+    }
+}
+
+/// Writes the ndjson or json records of `input` into `db`, which is created if it doesn't exist.
+/// Internal dbs like `_channel` are rejected. Returns the number of records written.
+pub async fn import(
+    target: &Target, db: &str, format: Format, ids: &IdMode, input: &mut dyn BufRead,
+) -> Result<usize, Error> {
+    check_user_db(db)?;
+    target.create_db(db).await?;
+
+    let mut written = 0;
+    match format {
+        Format::Csv => return Err(Error::Config("csv can only be exported".to_string())),
+        Format::Json => {
+            let records: Vec<Value> = serde_json::from_reader(input)?;
+            for page in records.chunks(PAGE_SIZE) {
+                let page = page.iter().map(|value| with_id(value.clone(), ids)).collect::<Result<_, _>>()?;
+                written += target.write(db, page).await?;
+            }
+        }
+        Format::Ndjson => {
+            let mut page = Vec::with_capacity(PAGE_SIZE);
+            for line in input.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue
+                }
+
+                page.push(with_id(serde_json::from_str(&line)?, ids)?);
+                if page.len() == PAGE_SIZE {
+                    written += target.write(db, mem::take(&mut page)).await?;
+                }
+            }
+            written += target.write(db, page).await?;
+        }
+    }
+
+    Ok(written)
+}
+
+fn with_id(value: Value, ids: &IdMode) -> Result<(String, Value), Error> {
+    match ids {
+        IdMode::Record => {
+            match (value.get("id"), value.get("val")) {
+                (Some(Value::String(id)), Some(val)) => Ok((id.clone(), val.clone())),
+                _ => Err(Error::IdNotFoundInJSON("id".to_string())),
+            }
+        }
+        IdMode::Path(path) => {
+            let query = Some(Query { field_path: Some(path.clone()), ..Query::default() });
+            Ok((get_id(&query, None, Some(&serde_json::to_vec(&value)?))?, value))
+        }
+        IdMode::Auto => Ok((get_id(&None, Some("_auto"), None)?, value)),
+    }
+}
+
+impl Target {
+    /// Reads up to `PAGE_SIZE` records of `db` from `from`, or from the first one, skipping `skip`.
+    pub(crate) async fn page(&self, db: &str, from: Option<&str>, skip: usize) -> Result<Vec<(String, Value)>, Error> {
+        match self {
+            Target::Local(local) => {
+                let page = local.storage().range(
+                    db,
+                    false,
+                    from.map(String::from),
+                    None,
+                    box move |iter| iter.skip(skip).take(PAGE_SIZE).collect(),
+                )?;
+
+                Ok(page.into_iter().map(to_record).collect())
+            }
+            Target::Remote(remote) => {
+                // `from` is an exact id, unlike the ones of `_since`, which can be prefixes or `_auto`
+                let mut path = format!("/_db/{}/_all?limit={}&skip={}", segment(db), PAGE_SIZE, skip);
+                if let Some(from) = from {
+                    path.push_str(&format!("&from={}", utf8_percent_encode(from, PARAM)));
+                }
+
+                remote.records(Method::GET, &path, Vec::new()).await
+            }
+        }
+    }
+
+    /// Runs a SQL query, with the default limit of the server or of the data folder.
+    pub(crate) async fn sql(&self, sql: &str) -> Result<Vec<(String, Value)>, Error> {
+        match self {
+            Target::Local(local) => Ok(local.sql(sql, None, None)?.into_iter().map(to_record).collect()),
+            Target::Remote(remote) => remote.records(Method::POST, "/_sql", sql.as_bytes().to_vec()).await,
+        }
+    }
+
+    pub(crate) async fn list_dbs(&self) -> Result<Vec<String>, Error> {
+        match self {
+            Target::Local(local) => local.list_dbs(),
+            Target::Remote(remote) => {
+                let dbs = remote.data(Method::GET, "/_db/_all", Vec::new()).await?;
+                Ok(serde_json::from_value(dbs)?)
+            }
+        }
+    }
+
+    /// Definitions of the stored channels.
+    pub(crate) async fn list_channels(&self) -> Result<Vec<Value>, Error> {
+        match self {
+            Target::Local(local) => local.list_channels(),
+            Target::Remote(remote) => {
+                let channels = remote.data(Method::GET, "/_channel", Vec::new()).await?;
+                Ok(serde_json::from_value(channels)?)
+            }
+        }
+    }
+
+    async fn channel(&self, id: &str) -> Result<Channel, Error> {
+        match self {
+            Target::Local(local) => local.channel(id, false),
+            Target::Remote(remote) => {
+                let channel = remote.data(Method::GET, &format!("/_channel/{}", segment(id)), Vec::new()).await?;
+                Channel::new_vec(serde_json::to_vec(&channel)?, false)
+            }
+        }
+    }
+
+    async fn create_db(&self, db: &str) -> Result<(), Error> {
+        match self {
+            Target::Local(local) => {
+                match local.storage().create_db(db, &DbOptions::default()) {
+                    Ok(()) | Err(Error::DbAlreadyExists(_)) => Ok(()),
+                    Err(err) => Err(err),
+                }
+            }
+            Target::Remote(remote) => {
+                let reply = remote.send(Method::PUT, &format!("/_db/{}/_create_db", segment(db)), Vec::new()).await?;
+                match reply.code.as_deref() {
+                    Some("db_already_exists") => Ok(()),
+                    _ => Remote::check(reply).map(|_| ()),
+                }
+            }
+        }
+    }
+
+    async fn write(&self, db: &str, records: Vec<(String, Value)>) -> Result<usize, Error> {
+        let written = records.len();
+        if written == 0 {
+            return Ok(0)
+        }
+
+        match self {
+            Target::Local(local) => {
+                let ops = records
+                    .into_iter()
+                    .map(|(id, val)| Ok(BatchOp::Put(id.into_bytes(), serde_json::to_vec(&val)?)))
+                    .collect::<Result<_, Error>>()?;
+                local.write_batch(db, ops)?;
+            }
+            Target::Remote(remote) => {
+                let body: Vec<Value> = records.into_iter().map(|(id, val)| json!({ "id": id, "val": val })).collect();
+                let path = format!("/_db/{}/_batch", segment(db));
+                remote.data(Method::POST, &path, serde_json::to_vec(&body)?).await?;
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+/// A running server, like `http://127.0.0.1:3000`.
+pub struct Remote {
+    client: Client<HttpConnector>,
+    base:   String,
+}
+
+impl Remote {
+    pub fn new(base: &str) -> Self { Remote { client: Client::new(), base: base.trim_end_matches('/').to_string() } }
+
+    async fn send(&self, method: Method, path: &str, body: Vec<u8>) -> Result<Reply, Error> {
+        let req = Request::builder().method(method).uri(format!("{}{}", self.base, path)).body(Body::from(body))?;
+        let res = self.client.request(req).await.map_err(remote_error)?;
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.map_err(remote_error)?;
+
+        serde_json::from_slice(&body).map_err(|_| Error::Remote(format!("'{}' replied {}", path, status)))
+    }
+
+    /// Sends a request and returns the data of the reply, turning error replies into errors.
+    async fn data(&self, method: Method, path: &str, body: Vec<u8>) -> Result<Value, Error> {
+        let reply = self.send(method, path, body).await?;
+        Remote::check(reply)
+    }
+
+    /// Sends a request replied with `{"id":"...","val":...}` records.
+    async fn records(&self, method: Method, path: &str, body: Vec<u8>) -> Result<Vec<(String, Value)>, Error> {
+        let records: Vec<Value> = serde_json::from_value(self.data(method, path, body).await?)?;
+        Ok(records.into_iter().filter_map(|r| with_id(r, &IdMode::Record).ok()).collect())
+    }
+
+    fn check(reply: Reply) -> Result<Value, Error> {
+        if reply.error {
+            return Err(Error::Remote(reply.cause.unwrap_or_default()))
+        }
+
+        Ok(reply.data.map(|d| *d).unwrap_or(Value::Null))
+    }
+}
+
+/// Values that aren't JSON are returned as strings.
+fn to_record(sp: SimplePair) -> (String, Value) {
+    let val = serde_json::from_slice(&sp.value)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&sp.value).to_string()));
+
+    (String::from_utf8_lossy(&sp.id).to_string(), val)
+}
+
+fn remote_error(err: impl ToString) -> Error { Error::Remote(err.to_string()) }
+
+fn segment(s: &str) -> String { utf8_percent_encode(s, SEGMENT).to_string() }
+
+/// Writes records in a `Format`. Csv rows have the fields of `columns`.
+struct Writer<'a> {
+    format:  Format,
+    out:     &'a mut dyn Write,
+    columns: Vec<String>,
+    written: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(format: Format, out: &'a mut dyn Write, columns: Vec<String>) -> Self {
+        Writer { format, out, columns, written: 0 }
+    }
+
+    fn write(&mut self, id: String, val: Value) -> Result<(), Error> {
+        match self.format {
+            Format::Ndjson => writeln!(self.out, "{}", json!({ "id": id, "val": val }))?,
+            Format::Json => {
+                let sep = if self.written == 0 { "[\n" } else { ",\n" };
+                write!(self.out, "{}{}", sep, json!({ "id": id, "val": val }))?
+            }
+            Format::Csv => {
+                if self.written == 0 {
+                    self.write_header()?;
+                }
+                let fields = self.columns[1..].iter().map(|column| csv_field(&cell(&val, column)));
+                let row: Vec<String> = iter::once(csv_field(&id)).chain(fields).collect();
+                writeln!(self.out, "{}", row.join(","))?
+            }
+        }
+        self.written += 1;
+
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), Error> {
+        let header: Vec<String> = self.columns.iter().map(|c| csv_field(c)).collect();
+        Ok(writeln!(self.out, "{}", header.join(","))?)
+    }
+
+    fn finish(mut self) -> Result<usize, Error> {
+        match self.format {
+            Format::Ndjson => {}
+            Format::Json if self.written == 0 => writeln!(self.out, "[]")?,
+            Format::Json => writeln!(self.out, "\n]")?,
+            Format::Csv if self.written == 0 => self.write_header()?,
+            Format::Csv => {}
+        }
+        self.out.flush()?;
+
+        Ok(self.written)
+    }
+}
+
+/// Columns to show records as rows: `id` and every field of the values, or `val` for values that
+/// aren't objects.
+pub(crate) fn columns(rows: &[(String, Value)]) -> Vec<&str> {
+    let mut columns = BTreeSet::new();
+    for (_, val) in rows {
+        match val {
+            Value::Object(fields) => columns.extend(fields.keys().map(String::as_str)),
+            _ => {
+                columns.insert("val");
+            }
+        }
+    }
+
+    iter::once("id").chain(columns.into_iter()).collect()
+}
+
+/// Field `column` of `val`. Strings are returned as they are and any other value as JSON.
+pub(crate) fn cell(val: &Value, column: &str) -> String {
+    let field = match val {
+        Value::Object(fields) => fields.get(column),
+        val if column == "val" => Some(val),
+        _ => None,
+    };
+
+    match field {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(field) => field.to_string(),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        return format!("\"{}\"", s.replace('"', "\"\""))
+    }
+
+    s.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+    use hyper::{service::make_service_fn, Server};
+    use tokio::runtime::Runtime;
+
+    use crate::{
+        components::{config::Config, durability::Durability, memory::Memory},
+        server::service::Svc,
+        transfer::*,
+    };
+
+    #[test]
+    fn test_export_import() {
+        let target = Target::Local(Db::new(Arc::new(Memory::default()), Durability::None));
+
+        let ndjson = concat!(r#"{"p":{"id":"1"},"name":"mario"}"#, "\n\n", r#"{"p":{"id":"2"},"name":"a, \"b\""}"#);
+        let mut input = ndjson.as_bytes();
+        let ids = IdMode::Path("p.id".to_string());
+        assert_eq!(block_on(import(&target, "people", Format::Ndjson, &ids, &mut input)).unwrap(), 2);
+
+        let mut out = Vec::new();
+        assert_eq!(block_on(export(&target, "people", Format::Ndjson, None, None, &mut out)).unwrap(), 2);
+        assert_eq!(String::from_utf8(out.clone()).unwrap().lines().count(), 2);
+
+        let mut csv = Vec::new();
+        block_on(export(&target, "people", Format::Csv, Some("2"), None, &mut csv)).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "id,name,p\n2,\"a, \"\"b\"\"\",\"{\"\"id\"\":\"\"2\"\"}\"\n");
+
+        let mut input = out.as_slice();
+        assert_eq!(block_on(import(&target, "copy", Format::Ndjson, &IdMode::Record, &mut input)).unwrap(), 2);
+        let mut json = Vec::new();
+        assert_eq!(block_on(export(&target, "copy", Format::Json, None, None, &mut json)).unwrap(), 2);
+        let records: Vec<Value> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(records[0], json!({"id": "1", "val": {"p": {"id": "1"}, "name": "mario"}}));
+    }
+
+    #[test]
+    fn test_remote() {
+        let mut rt = Runtime::new().unwrap();
+        let db = Db::new(Arc::new(Memory::default()), Durability::None);
+        let svc = Svc::new(db.clone(), &Config::default()).unwrap();
+        let make_svc = make_service_fn(move |_| {
+            let svc = svc.clone();
+            async move { Ok::<_, hyper::Error>(svc) }
+        });
+        let server = rt.enter(|| Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc));
+        let target = Target::Remote(Remote::new(&format!("http://{}", server.local_addr())));
+        rt.spawn(server);
+
+        let ndjson = concat!(r#"{"id":"a/b?","val":{"n":1}}"#, "\n", r#"{"id":"c d","val":{"m":2}}"#);
+        let mut input = ndjson.as_bytes();
+        assert_eq!(rt.block_on(import(&target, "my db", Format::Ndjson, &IdMode::Record, &mut input)).unwrap(), 2);
+        assert_eq!(db.get("my db", "a/b?", None, None).unwrap().unwrap().value, br#"{"n":1}"#.to_vec());
+
+        let mut csv = Vec::new();
+        rt.block_on(export(&target, "my db", Format::Csv, Some("a/b?"), None, &mut csv)).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "id,m,n\na/b?,,1\nc d,2,\n");
+
+        // ids exported from aren't prefixes or generated ids
+        let ndjson = ["_auto", "b*", "b+c", "ba"].iter().map(|id| format!(r#"{{"id":"{}","val":{{}}}}"#, id));
+        let ndjson = ndjson.collect::<Vec<_>>().join("\n");
+        rt.block_on(import(&target, "ids", Format::Ndjson, &IdMode::Record, &mut ndjson.as_bytes())).unwrap();
+        for (since, total) in &[("_auto", 4), ("b*", 3), ("b+c", 2)] {
+            let mut out = Vec::new();
+            rt.block_on(export(&target, "ids", Format::Ndjson, Some(since), None, &mut out)).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap().lines().count(), *total);
+        }
+    }
+}